@@ -4,18 +4,62 @@ use std::os::unix::fs::MetadataExt;
 use std::sync::Arc;
 
 use super::ArchiveError;
+use super::select::CodecSelector;
+use super::padding::Padding;
 
-use compressor::Compressor;
+use compressor::{Compressor, CompressorError};
+use compressor::filter::Filter;
+use compressor::registry::CODEC_NONE;
 use crypto::CipherProcessor;
+use zeroize::Zeroizing;
 
+/// EntryCodec records how an entry body was compressed. It is stored in the 8 byte
+/// field which used to hold the `compressed` flag: the first byte is the codec id,
+/// the second one is the level and the third one tells whether the codec was
+/// picked automatically. The next two bytes are the id and the parameter of the
+/// filter run before the codec, the sixth one tells whether the codec was primed
+/// with the archive dictionary and the seventh one is the [`Padding`] of the
+/// encrypted body. Old archives stored 1 there, which is LZW.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EntryCodec {
+    pub id: u8,
+    pub level: u8,
+    pub auto: bool,
+    pub filter: Filter,
+    pub dictionary: bool,
+    pub padding: Padding,
+}
+
+impl EntryCodec {
+    pub fn to_bytes(self) -> [u8; 8] {
+        let (filter_id, filter_param) = self.filter.to_parts();
+        [self.id, self.level, self.auto as u8, filter_id, filter_param, self.dictionary as u8, self.padding.to_byte(), 0]
+    }
+
+    pub fn from_bytes(bytes: [u8; 8]) -> Result<EntryCodec, ArchiveError> {
+        let filter = Filter::from_parts(bytes[3], bytes[4]).map_err(|_| ArchiveError::UnknownFilter)?;
+
+        Ok(EntryCodec {
+            id: bytes[0],
+            level: bytes[1],
+            auto: bytes[2] != 0,
+            filter,
+            dictionary: bytes[5] != 0,
+            padding: Padding::from_byte(bytes[6])?,
+        })
+    }
+}
+
+/// ArchiveFile is an entry on its way into or out of an archive. The body is
+/// wiped whenever it is replaced or dropped, so no decrypted copy outlives the entry.
 #[derive(Debug)]
 pub struct ArchiveFile {
     pub rel_path: String,
-    compressed: bool,
+    codec: EntryCodec,
     encrypted: bool,
     mode: u64,
     size: usize,
-    body: Vec<u8>,
+    body: Zeroizing<Vec<u8>>,
 }
 
 impl ArchiveFile {
@@ -31,11 +75,11 @@ impl ArchiveFile {
         let metadata = fs::symlink_metadata(path).map_err(|_| {ArchiveError::ErrorWithMetadataRead})?;
         if !metadata.file_type().is_file() {return Err(ArchiveError::IncorrectFileType)};
 
-        let body: Vec<u8> = fs::read(path).map_err(|_| ArchiveError::ErrorWithFileRead)?;
+        let body = Zeroizing::new(fs::read(path).map_err(|_| ArchiveError::ErrorWithFileRead)?);
 
         Ok(Self {
             rel_path,
-            compressed: false,
+            codec: EntryCodec::default(),
             encrypted: false,
             mode: metadata.mode() as u64,
             size: metadata.len() as usize,
@@ -43,14 +87,14 @@ impl ArchiveFile {
         })
     }
 
-    pub fn new(rel_path: String, compressed: bool, encrypted: bool, mode: u64, size: usize, body: Vec<u8>) -> ArchiveFile {
+    pub fn new(rel_path: String, codec: EntryCodec, encrypted: bool, mode: u64, size: usize, body: Vec<u8>) -> ArchiveFile {
         Self {
             rel_path,
-            compressed,
+            codec,
             encrypted,
             mode,
             size,
-            body,
+            body: Zeroizing::new(body),
         }
     }
 
@@ -58,45 +102,129 @@ impl ArchiveFile {
         if self.is_encrypted() { return Err(ArchiveError::CompressingEncryptedData); }
         if self.is_compressed() { return Err(ArchiveError::FileAlreadyCompressed); }
 
-        let new_body = compressor.compress(&self.body);
+        let new_body = Zeroizing::new(compressor.compress(&self.body));
+        let codec = EntryCodec {
+            id: compressor.codec_id(),
+            level: compressor.options().level,
+            auto: false,
+            filter: self.codec.filter,
+            dictionary: compressor.dictionary_id().is_some(),
+            padding: self.codec.padding,
+        };
 
         Ok(Self {
-            compressed: true,
+            codec,
             body: new_body,
             ..self
         })
     }
 
+    /// Compresses the body with the codec `selector` picks for this file,
+    /// or leaves it stored, and marks the codec as chosen automatically.
+    pub fn compress_auto(self, selector: &CodecSelector) -> Result<Self, ArchiveError> {
+        if self.is_encrypted() { return Err(ArchiveError::CompressingEncryptedData); }
+        if self.is_compressed() { return Err(ArchiveError::FileAlreadyCompressed); }
+
+        let choice = selector.choose(Path::new(&self.rel_path), &self.body);
+        let afile = match selector.compressor(choice) {
+            Some(c) if self.is_filtered() => self.compress(c)?,
+            Some(c) => {
+                let filter = selector.filter(&self.body);
+                self.filter(filter)?.compress(c)?
+            },
+            None => self,
+        };
+
+        Ok(Self {
+            codec: EntryCodec { auto: true, ..afile.codec },
+            ..afile
+        })
+    }
+
+    /// Decompresses the body, which may not grow past the recorded size of the entry.
     pub fn decompress(self, decompressor: Arc<dyn Compressor>) -> Result<Self, ArchiveError> {
+        let max_size = self.size;
+        self.decompress_limited(decompressor, max_size)
+    }
+
+    pub fn decompress_limited(self, decompressor: Arc<dyn Compressor>, max_size: usize) -> Result<Self, ArchiveError> {
         if self.is_encrypted() { return Err(ArchiveError::DecompressingEncryptedData); }
         if !self.is_compressed() { return Err(ArchiveError::FileAlreadyDecompressed); }
+        if decompressor.codec_id() != self.codec.id { return Err(ArchiveError::CodecMismatch); }
 
-        let new_body = decompressor.decompress(&self.body).map_err(|_| ArchiveError::DecompressError)?;
+        let new_body = decompressor.decompress_limited(&self.body, max_size).map_err(|e| match e {
+            CompressorError::OutputLimitExceeded => ArchiveError::OutputLimitExceeded,
+            _ => ArchiveError::DecompressError,
+        })?;
+        let new_body = Zeroizing::new(new_body);
 
         Ok(Self {
-            compressed: false,
+            codec: EntryCodec { filter: self.codec.filter, ..EntryCodec::default() },
             body: new_body,
             ..self
         })
     }
 
+    /// Runs `filter` over the body. The filter is recorded in the codec and has to be
+    /// undone with [`ArchiveFile::unfilter`] after decompression.
+    pub fn filter(mut self, filter: Filter) -> Result<Self, ArchiveError> {
+        if self.is_encrypted() { return Err(ArchiveError::CompressingEncryptedData); }
+        if self.is_compressed() { return Err(ArchiveError::FileAlreadyCompressed); }
+        if self.is_filtered() { return Err(ArchiveError::FileAlreadyFiltered); }
+
+        filter.encode(&mut self.body);
+
+        Ok(Self {
+            codec: EntryCodec { filter, ..self.codec },
+            ..self
+        })
+    }
+
+    pub fn unfilter(mut self) -> Result<Self, ArchiveError> {
+        if self.is_encrypted() { return Err(ArchiveError::DecompressingEncryptedData); }
+        if self.is_compressed() { return Err(ArchiveError::FileAlreadyCompressed); }
+
+        self.codec.filter.decode(&mut self.body);
+
+        Ok(Self {
+            codec: EntryCodec { filter: Filter::None, ..self.codec },
+            ..self
+        })
+    }
+
     pub fn encrypt(self, processor: Arc<dyn CipherProcessor>) -> Result<Self, ArchiveError> {
+        self.encrypt_padded(processor, Padding::None)
+    }
+
+    /// Pads the body with `padding` before encrypting it, so the ciphertext length
+    /// tells little about the size. The padding is recorded in the codec.
+    pub fn encrypt_padded(mut self, processor: Arc<dyn CipherProcessor>, padding: Padding) -> Result<Self, ArchiveError> {
         if self.is_encrypted() { return Err(ArchiveError::FileAlreadyEncrypted); }
 
+        if padding != Padding::None {
+            self.body = padding.pad(&self.body);
+        }
+
         Ok(Self{
             encrypted: true,
-            body: processor.encrypt_blocks(&self.body),
+            codec: EntryCodec { padding, ..self.codec },
+            body: Zeroizing::new(processor.encrypt_blocks(&self.body)),
             ..self
         })
     }
 
+    /// Decrypts the body and strips the padding recorded in the codec.
     pub fn decrypt(self, processor: Arc<dyn CipherProcessor>) -> Result<Self, ArchiveError> {
         if !self.is_encrypted() { return Err(ArchiveError::FileAlreadyDecrypted); }
 
-        let new_body = processor.decrypt_blocks(&self.body).map_err(|_| ArchiveError::DecryptError)?;
+        let mut new_body = Zeroizing::new(processor.decrypt_blocks(&self.body).map_err(|_| ArchiveError::DecryptError)?);
+        if self.codec.padding != Padding::None {
+            Padding::unpad(&mut new_body)?;
+        }
 
         Ok(Self{
             encrypted: false,
+            codec: EntryCodec { padding: Padding::None, ..self.codec },
             body: new_body,
             ..self
         })
@@ -107,7 +235,15 @@ impl ArchiveFile {
     }
 
     pub fn is_compressed(&self) -> bool {
-        self.compressed
+        self.codec.id != CODEC_NONE
+    }
+
+    pub fn is_filtered(&self) -> bool {
+        self.codec.filter != Filter::None
+    }
+
+    pub fn codec(&self) -> EntryCodec {
+        self.codec
     }
 
     pub fn mode(&self) -> u64 {
@@ -122,11 +258,12 @@ impl ArchiveFile {
         self.body.len()
     }
 
-    pub fn take_body(self) -> Vec<u8> {
-        self.body
+    /// Moves the body out of the entry. The caller is responsible for wiping it.
+    pub fn take_body(mut self) -> Vec<u8> {
+        std::mem::take(&mut *self.body)
     }
 
     pub fn clone_body(&self) -> Vec<u8> {
-        self.body.clone()
+        self.body.to_vec()
     }
 }
\ No newline at end of file
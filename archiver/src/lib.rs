@@ -1,21 +1,39 @@
 pub mod afile;
+pub mod mode;
+pub mod padding;
+pub mod select;
+pub mod solid;
 mod utils;
 
 use utils::get_absolute_paths;
 use afile::*;
+use select::CodecSelector;
+use mode::CipherMode;
+use padding::Padding;
+use solid::{SolidBlock, BlockHeader, MEMBER_HEADER_SIZE};
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::io::Read;
+use std::io::{Seek, SeekFrom};
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use byteorder::{ByteOrder, LittleEndian};
-
-use crypto::CipherProcessor;
-use compressor::Compressor;
+use zeroize::Zeroizing;
+
+use crypto::{CipherError, CipherProcessor};
+use crypto::envelope::Envelope;
+use crypto::keyslot::KeySlots;
+use crypto::signing::{SigningKey, VerifyingKey, SIGNATURE_SIZE};
+use crypto::skein::Skein;
+use compressor::{Compressor, CompressorOptions};
+use compressor::registry::{Registry, CODEC_NONE};
+use compressor::filter::Filter;
+use compressor::dictionary::Dictionary;
 
 use std::sync::mpsc::channel;
 use workerpool::Pool;
@@ -41,15 +59,104 @@ pub enum ArchiveError {
     FilePathError,
     DataWritingError,
     DifferentMagickValue,
+    UnknownCodec,
+    CodecMismatch,
+    OutputLimitExceeded,
+    CorruptedHeader,
+    UnknownFilter,
+    FileAlreadyFiltered,
+    MissingDictionary,
+    EntryNotFound,
+    InvalidEnvelope,
+    NoKeySlots,
+    KeySlotsFull,
+    InvalidKeySlot,
+    LastKeySlot,
+    NotSigned,
+    InvalidSignature,
+    SignedArchive,
+    EncryptedHeaders,
+    UnknownPadding,
+    UnknownCipherMode,
 }
 
 const MAGICK: [u8; 8] = [0x52, 0x5a, 0x88, 0x12, 0x78, 0xf1, 0x7, 0x13];
 
+/// Bits of the header field which used to be the `encrypted` flag.
+const FLAG_ENCRYPTED: u64 = 1;
+/// The header is followed by the length and the content of a dictionary.
+const FLAG_DICTIONARY: u64 = 1 << 1;
+/// Entries are grouped into solid blocks, the header is followed by the number of blocks.
+const FLAG_SOLID: u64 = 1 << 2;
+/// The content key is wrapped for recipients, the header is followed by the length and the envelope.
+const FLAG_RECIPIENTS: u64 = 1 << 3;
+/// The content key is wrapped under passwords, the header is followed by the key slots.
+const FLAG_KEY_SLOTS: u64 = 1 << 4;
+/// Entry and block headers are encrypted, each is preceded by the length of its ciphertext.
+const FLAG_ENCRYPTED_HEADERS: u64 = 1 << 5;
+/// Password keys are derived with the Skein KDF. Password archives without it
+/// were made by the first versions, whose key is the SHA-256 of the password.
+const FLAG_SKEIN_KDF: u64 = 1 << 6;
+/// Two bits with the [`CipherMode`] of the entries, zero for archives without a stored mode.
+const MODE_SHIFT: u32 = 7;
+const MODE_MASK: u64 = 0b11 << MODE_SHIFT;
+/// The archive ends with a signature trailer.
+const FLAG_SIGNED: u64 = 1 << 9;
+/// Offset of the flags in the archive header.
+const FLAGS_OFFSET: u64 = 16;
+
+/// Ends the signature trailer appended to signed archives.
+const SIGNATURE_MAGICK: [u8; 8] = [0x52, 0x5a, 0x53, 0x49, 0x47, 0x4e, 0x0, 0x1];
+/// signature and SIGNATURE_MAGICK.
+const SIGNATURE_TRAILER_SIZE: u64 = SIGNATURE_SIZE as u64 + 8;
+/// Prefix of the signed message, so archive signatures are never valid for anything else.
+const SIGNATURE_ID: &[u8] = b"crypto_container archive signature";
+
+const MAX_DICTIONARY_SIZE: usize = 1 << 24;
+const MAX_ENVELOPE_SIZE: usize = 1 << 20;
+/// Member tables of solid blocks make the largest encrypted headers.
+const MAX_HEADER_RECORD_SIZE: u64 = 1 << 28;
+/// Files larger than this are not used as dictionary samples.
+const MAX_SAMPLE_SIZE: u64 = 64 * 1024;
+const MAX_SAMPLES_TOTAL: usize = 16 * 1024 * 1024;
+
+/// mode, size, zip_size, codec and name_length.
+const ENTRY_HEADER_SIZE: u64 = 40;
+const MAX_NAME_LENGTH: usize = 4096;
+/// Largest entry size accepted from an archive header unless changed with [`Archiver::with_max_entry_size`].
+pub const DEFAULT_MAX_ENTRY_SIZE: usize = 1 << 36;
+
+/// EntryInfo is the header of an archive entry, without its body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub rel_path: String,
+    pub mode: u64,
+    pub size: usize,
+    pub zip_size: usize,
+    pub codec: EntryCodec,
+    pub encrypted: bool,
+    /// Index of the solid block holding the entry. Members of a block have
+    /// no packed size of their own, their `zip_size` is 0.
+    pub block: Option<usize>,
+}
+
 pub struct Archiver {
     target_path: String,
     n_workers: usize,
     processor: Option<Arc<dyn CipherProcessor>>,
     compressor: Option<Arc<dyn Compressor>>,
+    registry: Arc<Registry>,
+    selector: Option<Arc<CodecSelector>>,
+    filter: Filter,
+    dictionary: Option<Arc<Dictionary>>,
+    max_entry_size: usize,
+    solid_block_size: Option<usize>,
+    envelope: Option<Envelope>,
+    key_slots: Option<KeySlots>,
+    encrypted_headers: bool,
+    padding: Padding,
+    skein_kdf: bool,
+    cipher_mode: Option<CipherMode>,
     rx: Option<Receiver<Result<afile::ArchiveFile, ArchiveError>>>,
 }
 
@@ -64,11 +171,309 @@ impl Archiver {
             n_workers,
             processor,
             compressor,
+            registry: Arc::new(Registry::new()),
+            selector: None,
+            filter: Filter::None,
+            dictionary: None,
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            solid_block_size: None,
+            envelope: None,
+            key_slots: None,
+            encrypted_headers: false,
+            padding: Padding::None,
+            skein_kdf: false,
+            cipher_mode: None,
             rx: None,
         }
     }
 
+    /// Replaces the registry used to find decompressors for archive entries.
+    pub fn with_registry(self, registry: Arc<Registry>) -> Self {
+        Self {
+            registry,
+            ..self
+        }
+    }
+
+    /// Picks the codec of every entry with `selector` instead of using the compressor.
+    pub fn with_selector(self, selector: Arc<CodecSelector>) -> Self {
+        Self {
+            selector: Some(selector),
+            ..self
+        }
+    }
+
+    /// Runs `filter` over every entry before it is compressed.
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Self {
+            filter,
+            ..self
+        }
+    }
+
+    /// Stores `dictionary` in the archive and primes the compressors with it.
+    /// Entries compressed with codecs which can not use a dictionary do not depend on it.
+    pub fn with_dictionary(self, dictionary: Arc<Dictionary>) -> Self {
+        Self {
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    /// Trains a dictionary of at most `max_size` bytes on the small files of the target directory.
+    pub fn train_dictionary(&self, max_size: usize) -> Result<Dictionary, ArchiveError> {
+        let target_path = Path::new(&self.target_path);
+        let paths = get_absolute_paths(target_path).map_err(|_| ArchiveError::FilePathError)?;
+
+        let mut samples: Vec<Vec<u8>> = Vec::new();
+        let mut total = 0;
+        for path in paths {
+            let metadata = fs::symlink_metadata(&path).map_err(|_| ArchiveError::ErrorWithMetadataRead)?;
+            if !metadata.is_file() || metadata.len() > MAX_SAMPLE_SIZE {
+                continue;
+            }
+
+            let sample = fs::read(&path).map_err(|_| ArchiveError::ErrorWithFileRead)?;
+            total += sample.len();
+            samples.push(sample);
+
+            if total >= MAX_SAMPLES_TOTAL {
+                break;
+            }
+        }
+
+        let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_slice()).collect();
+        Ok(Dictionary::train(&samples, max_size.min(MAX_DICTIONARY_SIZE)))
+    }
+
+    /// Rejects archives with entries declaring more than `max_entry_size` bytes.
+    /// Decompression of an entry never produces more than its declared size.
+    pub fn with_max_entry_size(self, max_entry_size: usize) -> Self {
+        Self {
+            max_entry_size,
+            ..self
+        }
+    }
+
+    /// Groups files by extension into blocks of up to `block_size` bytes, every block is
+    /// compressed as one stream. A single file is extracted by decompressing only its block.
+    pub fn with_solid(self, block_size: usize) -> Self {
+        Self {
+            solid_block_size: Some(block_size),
+            ..self
+        }
+    }
+
+    /// Stores `envelope` in the archive header. It holds the content key the
+    /// processor was made with, wrapped for the recipients of the archive.
+    pub fn with_envelope(self, envelope: Envelope) -> Self {
+        Self {
+            envelope: Some(envelope),
+            ..self
+        }
+    }
+
+    /// Reads the envelope of an archive encrypted for recipients, `None` for other archives.
+    pub fn load_envelope(&self) -> Result<Option<Envelope>, ArchiveError> {
+        let file = File::open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (_, flags) = Archiver::load_archive_header(&file)?;
+        if flags & FLAG_RECIPIENTS == 0 {
+            return Ok(None);
+        }
+
+        Archiver::load_envelope_section(&file).map(Some)
+    }
+
+    /// Stores `key_slots` in the archive header. They hold the content key the
+    /// processor was made with, wrapped under every password of the archive.
+    pub fn with_key_slots(self, key_slots: KeySlots) -> Self {
+        Self {
+            key_slots: Some(key_slots),
+            ..self
+        }
+    }
+
+    /// Encrypts entry and block headers with the processor too, so names, modes
+    /// and sizes can only be listed with the key. The number of entries and the
+    /// total size stay visible. Has no effect on archives without a processor.
+    pub fn with_encrypted_headers(self) -> Self {
+        Self {
+            encrypted_headers: true,
+            ..self
+        }
+    }
+
+    /// Pads every encrypted body with `padding`, so the ciphertext lengths hide the
+    /// exact sizes. The sizes stay in the entry headers unless they are encrypted
+    /// too, see [`Archiver::with_encrypted_headers`].
+    pub fn with_padding(self, padding: Padding) -> Self {
+        Self {
+            padding,
+            ..self
+        }
+    }
+
+    /// Marks the archive as encrypted with a key derived from a password by the
+    /// Skein KDF, see [`Archiver::has_skein_kdf`].
+    pub fn with_skein_kdf(self) -> Self {
+        Self {
+            skein_kdf: true,
+            ..self
+        }
+    }
+
+    /// Tells whether the password key of the archive comes from the Skein KDF.
+    /// Without the flag the key of a password archive is the SHA-256 of the password.
+    pub fn has_skein_kdf(&self) -> Result<bool, ArchiveError> {
+        let file = File::open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (_, flags) = Archiver::load_archive_header(&file)?;
+
+        Ok(flags & FLAG_SKEIN_KDF != 0)
+    }
+
+    /// Stores the mode of the processor in the archive header, see [`Archiver::load_cipher_mode`].
+    pub fn with_cipher_mode(self, cipher_mode: CipherMode) -> Self {
+        Self {
+            cipher_mode: Some(cipher_mode),
+            ..self
+        }
+    }
+
+    /// Reads the mode the entries are encrypted with, `None` for archives which
+    /// are not encrypted or were made without storing it.
+    pub fn load_cipher_mode(&self) -> Result<Option<CipherMode>, ArchiveError> {
+        let file = File::open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (_, flags) = Archiver::load_archive_header(&file)?;
+
+        CipherMode::from_byte(((flags & MODE_MASK) >> MODE_SHIFT) as u8)
+    }
+
+    /// Reads the key slots of an archive encrypted with passwords, `None` for other archives.
+    pub fn load_key_slots(&self) -> Result<Option<KeySlots>, ArchiveError> {
+        let file = File::open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (_, flags) = Archiver::load_archive_header(&file)?;
+        if flags & FLAG_KEY_SLOTS == 0 {
+            return Ok(None);
+        }
+        if flags & FLAG_RECIPIENTS != 0 {
+            Archiver::load_envelope_section(&file)?;
+        }
+
+        Archiver::load_key_slots_section(&file).map(Some)
+    }
+
+    /// Overwrites the key slots section of the archive. The section has the same
+    /// size whatever slots are in use, so nothing else in the file moves.
+    /// Signed archives are refused, the new slots would invalidate the signature.
+    pub fn store_key_slots(&self, key_slots: &KeySlots) -> Result<(), ArchiveError> {
+        let mut file = File::options().read(true).write(true).open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (_, flags) = Archiver::load_archive_header(&file)?;
+        if flags & FLAG_KEY_SLOTS == 0 {
+            return Err(ArchiveError::NoKeySlots);
+        }
+        if flags & FLAG_SIGNED != 0 {
+            return Err(ArchiveError::SignedArchive);
+        }
+        if flags & FLAG_RECIPIENTS != 0 {
+            Archiver::load_envelope_section(&file)?;
+        }
+
+        let position = file.stream_position().map_err(|_| ArchiveError::FilePathError)?;
+        if Archiver::load_key_slots_section(&file)?.key_size() != key_slots.key_size() {
+            return Err(ArchiveError::InvalidKeySlot);
+        }
+
+        file.seek(SeekFrom::Start(position)).map_err(|_| ArchiveError::FilePathError)?;
+        Archiver::store_data(&file, &key_slots.to_bytes())?;
+        file.sync_all().map_err(|_| ArchiveError::DataWritingError)
+    }
+
+    /// Wraps the content key under `new_key` in a free slot, `key` must open one of the slots.
+    /// Returns the index of the new slot.
+    pub fn add_key_slot(&self, key: &[u8], new_key: &[u8], iterations: u64) -> Result<usize, ArchiveError> {
+        let mut key_slots = self.load_key_slots()?.ok_or(ArchiveError::NoKeySlots)?;
+        let (_, content_key) = key_slots.open(key).map_err(key_slot_error)?;
+
+        let index = key_slots.add(&content_key, new_key, iterations).map_err(key_slot_error)?;
+        self.store_key_slots(&key_slots)?;
+        Ok(index)
+    }
+
+    /// Frees slot `index`, `key` must open one of the slots.
+    pub fn remove_key_slot(&self, key: &[u8], index: usize) -> Result<(), ArchiveError> {
+        let mut key_slots = self.load_key_slots()?.ok_or(ArchiveError::NoKeySlots)?;
+        key_slots.open(key).map_err(key_slot_error)?;
+
+        key_slots.remove(index).map_err(key_slot_error)?;
+        self.store_key_slots(&key_slots)
+    }
+
+    /// Indices and KDF iterations of the key slots in use.
+    pub fn list_key_slots(&self) -> Result<Vec<(usize, u64)>, ArchiveError> {
+        let key_slots = self.load_key_slots()?.ok_or(ArchiveError::NoKeySlots)?;
+        Ok(key_slots.active())
+    }
+
+    /// Signs the archive with `key` and appends the signature as a trailer,
+    /// replacing the previous one. The signature covers a Skein-512 digest of
+    /// everything before the trailer: the header with its envelope, key slots
+    /// and dictionary, the index and the entry bodies, encrypted or not.
+    /// The header is marked as signed first, so the mark is signed too and
+    /// the key slots of a signed archive can no longer be changed.
+    pub fn sign(&self, key: &SigningKey) -> Result<(), ArchiveError> {
+        let mut file = File::options().read(true).write(true).open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (_, flags) = Archiver::load_archive_header(&file)?;
+
+        let length = match Archiver::load_signature_trailer(&file, flags)? {
+            Some((length, _)) => length,
+            None => {
+                file.seek(SeekFrom::Start(FLAGS_OFFSET)).map_err(|_| ArchiveError::FilePathError)?;
+                Archiver::store_data(&file, &(flags | FLAG_SIGNED).to_ne_bytes())?;
+                file.metadata().map_err(|_| ArchiveError::ErrorWithMetadataRead)?.len()
+            }
+        };
+        let message = [SIGNATURE_ID, &Archiver::archive_digest(&file, length)?].concat();
+        let signature = key.sign(&message);
+
+        file.set_len(length).map_err(|_| ArchiveError::DataWritingError)?;
+        file.seek(SeekFrom::End(0)).map_err(|_| ArchiveError::FilePathError)?;
+        Archiver::store_data(&file, &signature)?;
+        Archiver::store_data(&file, &SIGNATURE_MAGICK)?;
+        file.sync_all().map_err(|_| ArchiveError::DataWritingError)
+    }
+
+    /// Checks the signature trailer of the archive against the publisher's `key`.
+    pub fn verify_signature(&self, key: &VerifyingKey) -> Result<(), ArchiveError> {
+        let file = File::open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (_, flags) = Archiver::load_archive_header(&file)?;
+
+        let (length, signature) = Archiver::load_signature_trailer(&file, flags)?.ok_or(ArchiveError::NotSigned)?;
+        let message = [SIGNATURE_ID, &Archiver::archive_digest(&file, length)?].concat();
+
+        key.verify(&message, &signature).map_err(|_| ArchiveError::InvalidSignature)
+    }
+
+    /// Compressors primed with the dictionary when there is one.
+    fn primed_compressors(&self) -> (Option<Arc<dyn Compressor>>, Option<Arc<CodecSelector>>) {
+        match &self.dictionary {
+            Some(d) => (
+                self.compressor.as_ref().map(|c| c.with_dictionary(d).unwrap_or_else(|| c.clone())),
+                self.selector.as_ref().map(|s| Arc::new(s.with_dictionary(d))),
+            ),
+            None => (self.compressor.clone(), self.selector.clone()),
+        }
+    }
+
+    /// Decompressors for the entries of an archive with `dictionary`.
+    fn primed_decompressors(&self, dictionary: Option<&Dictionary>) -> Arc<Decompressors> {
+        Arc::new(Decompressors::new(self.registry.clone(), dictionary))
+    }
+
     pub fn zip(&mut self, output_path: &Path) -> Result<usize, ArchiveError> {
+        if let Some(block_size) = self.solid_block_size {
+            return self.zip_solid(output_path, block_size);
+        }
+
         let workers = Pool::<ThunkWorker<Result<ArchiveFile, ArchiveError>>>::new(self.n_workers);
 
         let target_path = Path::new(&self.target_path).to_owned();
@@ -77,15 +482,20 @@ impl Archiver {
 
         println!("Total files: {}", n_jobs);
 
+        let (compressor, selector) = self.primed_compressors();
+
         let (tx, rx) = channel();
         self.rx = Some(rx);
         for path in paths {
-            let compressor = self.compressor.clone();
+            let compressor = compressor.clone();
+            let selector = selector.clone();
+            let filter = self.filter;
             let processor = self.processor.clone();
+            let padding = self.padding;
             let target_path_clone = target_path.clone();
 
             workers.execute_to(tx.clone(), Thunk::of(move ||{
-                worker_zip(&path, &target_path_clone, compressor, processor)
+                worker_zip(&path, &target_path_clone, compressor, selector, filter, processor, padding)
             }
             ));
         }
@@ -96,23 +506,147 @@ impl Archiver {
         Ok(without_errors)
     }
 
-    pub fn unzip(&mut self, output_dir: &Path) -> Result<usize, ArchiveError> {
+    fn zip_solid(&mut self, output_path: &Path, block_size: usize) -> Result<usize, ArchiveError> {
+        let workers = Pool::<ThunkWorker<Result<SolidBlock, ArchiveError>>>::new(self.n_workers);
+
+        let target_path = Path::new(&self.target_path).to_owned();
+        let paths = get_absolute_paths(&target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let n_files = paths.len();
+        let blocks = solid::group_paths(paths, block_size)?;
+        let n_blocks = blocks.len();
+
+        println!("Total files: {}, blocks: {}", n_files, n_blocks);
+
+        let (compressor, selector) = self.primed_compressors();
+
+        let (tx, rx) = channel();
+        for paths in blocks {
+            let compressor = compressor.clone();
+            let selector = selector.clone();
+            let filter = self.filter;
+            let processor = self.processor.clone();
+            let padding = self.padding;
+            let target_path_clone = target_path.clone();
+
+            workers.execute_to(tx.clone(), Thunk::of(move || {
+                worker_zip_solid(&paths, &target_path_clone, compressor, selector, filter, processor, padding)
+            }));
+        }
+
+        let file = File::create(output_path).map_err(|_| ArchiveError::FilePathError)?;
+        self.store_archive_header(&file, n_files)?;
+        Archiver::store_data(&file, &n_blocks.to_ne_bytes())?;
+
+        let mut without_errors = 0;
+        for (n, result) in rx.iter().take(n_blocks).enumerate() {
+            let block = result?;
+            println!("Blocks zipped: {}/{}, files: {}, size: {}, codec: {}", n, n_blocks, block.members.len(), block.afile.size(), codec_name(&self.registry, block.afile.codec()));
+
+            without_errors += block.members.len();
+            block.store(&file, self.header_processor())?;
+        }
+
+        Ok(without_errors)
+    }
+
+    /// Writes a copy of the archive to `output_path` with every entry body and the
+    /// dictionary decrypted by the current processor and encrypted by `processor`.
+    /// Compressed bodies are not touched, names, modes, codecs and solid blocks
+    /// stay as they are. The envelope, key slots and KDF flag set on this archiver
+    /// replace the old ones, they should hold the key `processor` was made with.
+    /// The stored cipher mode is kept, `processor` should use it too.
+    pub fn rekey(&self, output_path: &Path, processor: Arc<dyn CipherProcessor>) -> Result<usize, ArchiveError> {
+        let old_processor = self.processor.clone().ok_or(ArchiveError::DecryptError)?;
+        let OpenedArchive { file, count: afiles_count, flags, dictionary } = self.open_archive()?;
+        if flags & FLAG_ENCRYPTED == 0 {
+            return Err(ArchiveError::FileAlreadyDecrypted);
+        }
+
+        // the signature is not copied, the new archive has to be signed again
+        let mut flags = flags & !(FLAG_RECIPIENTS | FLAG_KEY_SLOTS | FLAG_SKEIN_KDF | FLAG_SIGNED);
+        if self.envelope.is_some() { flags |= FLAG_RECIPIENTS; }
+        if self.key_slots.is_some() { flags |= FLAG_KEY_SLOTS; }
+        if self.skein_kdf { flags |= FLAG_SKEIN_KDF; }
+        let dictionary = dictionary.map(|d| Zeroizing::new(processor.encrypt_blocks(d.content())));
+        let encrypted_headers = flags & FLAG_ENCRYPTED_HEADERS != 0;
+        let header_processor = Some(&processor).filter(|_| encrypted_headers);
+
+        let output = File::create(output_path).map_err(|_| ArchiveError::FilePathError)?;
+        Archiver::store_header_sections(&output, afiles_count, flags, self.envelope.as_ref(), self.key_slots.as_ref(), dictionary.as_deref().map(|d| d.as_slice()))?;
+
+        let mut without_errors = 0;
+        if flags & FLAG_SOLID != 0 {
+            let blocks_count = Archiver::load_blocks_count(&file)?;
+            Archiver::store_data(&output, &blocks_count.to_ne_bytes())?;
+
+            for n in 0..blocks_count {
+                let mut block = self.load_block_header(&file, encrypted_headers)?.load_block(&file, true)?;
+                println!("Blocks rekeyed: {}/{}, files: {}, size: {}", n, blocks_count, block.members.len(), block.afile.size());
+
+                let padding = block.afile.codec().padding;
+                block.afile = block.afile.decrypt(old_processor.clone())?.encrypt_padded(processor.clone(), padding)?;
+                without_errors += block.members.len();
+                block.store(&output, header_processor)?;
+            }
+
+            return Ok(without_errors);
+        }
+
+        for _ in 0..afiles_count {
+            let afile = self.load_afile(&file, true, encrypted_headers)?;
+            println!("Files rekeyed: {}/{}, size: {}, path: {}", without_errors, afiles_count, afile.size(), afile.rel_path);
+
+            let padding = afile.codec().padding;
+            let afile = afile.decrypt(old_processor.clone())?.encrypt_padded(processor.clone(), padding)?;
+            Archiver::store_afile(&output, afile, header_processor)?;
+            without_errors += 1;
+        }
+
+        Ok(without_errors)
+    }
+
+    /// Opens the archive and reads everything before the entries.
+    fn open_archive(&self) -> Result<OpenedArchive, ArchiveError> {
         let file = File::open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
-        let (afiles_count, encrypted) = self.load_archive_header(&file)?;
-        
+        let (afiles_count, flags) = Archiver::load_archive_header(&file)?;
+        let encrypted = flags & FLAG_ENCRYPTED != 0;
+        if flags & FLAG_RECIPIENTS != 0 {
+            Archiver::load_envelope_section(&file)?;
+        }
+        if flags & FLAG_KEY_SLOTS != 0 {
+            Archiver::load_key_slots_section(&file)?;
+        }
+        let dictionary = if flags & FLAG_DICTIONARY != 0 {
+            self.load_dictionary(&file, encrypted)?
+        } else {
+            None
+        };
+
+        Ok(OpenedArchive { file, count: afiles_count, flags, dictionary })
+    }
+
+    pub fn unzip(&mut self, output_dir: &Path) -> Result<usize, ArchiveError> {
+        let OpenedArchive { file, count: afiles_count, flags, dictionary } = self.open_archive()?;
+        let encrypted = flags & FLAG_ENCRYPTED != 0;
+        let encrypted_headers = flags & FLAG_ENCRYPTED_HEADERS != 0;
+        if flags & FLAG_SOLID != 0 {
+            return self.unzip_solid(&file, output_dir, afiles_count, encrypted, encrypted_headers, dictionary);
+        }
+
         let workers = Pool::<ThunkWorker<Result<ArchiveFile, ArchiveError>>>::new(self.n_workers);
+        let decompressors = self.primed_decompressors(dictionary.as_deref());
 
         let (tx, rx) = channel();
         self.rx = Some(rx);
 
         for _ in 0..afiles_count {
-            let afile = Archiver::load_afile(&file, encrypted)?;
+            let afile = self.load_afile(&file, encrypted, encrypted_headers)?;
 
-            let compressor = self.compressor.clone();
+            let decompressors = decompressors.clone();
             let processor = self.processor.clone();
 
             workers.execute_to(tx.clone(), Thunk::of(move || {
-                worker_unzip(afile, compressor, processor)
+                worker_unzip(afile, decompressors, processor)
             }));
         }
 
@@ -121,21 +655,235 @@ impl Archiver {
         Ok(without_errors)
     }
 
+    fn unzip_solid(&self, file: &File, output_dir: &Path, afiles_count: usize, encrypted: bool, encrypted_headers: bool, dictionary: Option<Arc<Dictionary>>) -> Result<usize, ArchiveError> {
+        let blocks_count = Archiver::load_blocks_count(file)?;
+
+        let workers = Pool::<ThunkWorker<Result<Vec<ArchiveFile>, ArchiveError>>>::new(self.n_workers);
+        let decompressors = self.primed_decompressors(dictionary.as_deref());
+        let (tx, rx) = channel();
+
+        for _ in 0..blocks_count {
+            let block = self.load_block_header(file, encrypted_headers)?.load_block(file, encrypted)?;
+
+            let decompressors = decompressors.clone();
+            let processor = self.processor.clone();
+
+            workers.execute_to(tx.clone(), Thunk::of(move || {
+                worker_unzip_solid(block, decompressors, processor)
+            }));
+        }
+
+        let mut without_errors = 0;
+        for result in rx.iter().take(blocks_count) {
+            for afile in result? {
+                println!("Files unziped: {}/{}, size: {}, path: {}", without_errors, afiles_count, afile.size(), afile.rel_path);
+                Archiver::store_file(output_dir, afile)?;
+                without_errors += 1;
+            }
+        }
+
+        Ok(without_errors)
+    }
+
+    /// Extracts the entry `rel_path` into `output_dir`. In solid archives only
+    /// the block holding the entry is decompressed, other entries are skipped.
+    pub fn extract(&self, rel_path: &str, output_dir: &Path) -> Result<(), ArchiveError> {
+        let OpenedArchive { mut file, count: afiles_count, flags, dictionary } = self.open_archive()?;
+        let encrypted = flags & FLAG_ENCRYPTED != 0;
+        let encrypted_headers = flags & FLAG_ENCRYPTED_HEADERS != 0;
+
+        if flags & FLAG_SOLID != 0 {
+            for _ in 0..Archiver::load_blocks_count(&file)? {
+                let header = self.load_block_header(&file, encrypted_headers)?;
+                if !header.members.iter().any(|m| m.rel_path == rel_path) {
+                    file.seek(SeekFrom::Current(header.zip_size as i64)).map_err(|_| ArchiveError::FilePathError)?;
+                    continue;
+                }
+
+                let block = header.load_block(&file, encrypted)?;
+                let afile = worker_unzip_solid(block, self.primed_decompressors(dictionary.as_deref()), self.processor.clone())?
+                    .into_iter()
+                    .find(|afile| afile.rel_path == rel_path)
+                    .ok_or(ArchiveError::EntryNotFound)?;
+
+                return Archiver::store_file(output_dir, afile);
+            }
+        } else {
+            for _ in 0..afiles_count {
+                let info = self.load_entry_info(&file, encrypted, encrypted_headers)?;
+                if info.rel_path != rel_path {
+                    file.seek(SeekFrom::Current(info.zip_size as i64)).map_err(|_| ArchiveError::FilePathError)?;
+                    continue;
+                }
+
+                let mut body = vec![0u8; info.zip_size];
+                file.read_exact(&mut body).map_err(|_| ArchiveError::FilePathError)?;
+                let afile = ArchiveFile::new(info.rel_path, info.codec, encrypted, info.mode, info.size, body);
+                let afile = worker_unzip(afile, self.primed_decompressors(dictionary.as_deref()), self.processor.clone())?;
+
+                return Archiver::store_file(output_dir, afile);
+            }
+        }
+
+        Err(ArchiveError::EntryNotFound)
+    }
+
+    /// Reads the entry headers of the archive without decompressing anything.
+    pub fn list(&self) -> Result<Vec<EntryInfo>, ArchiveError> {
+        let mut file = File::open(&self.target_path).map_err(|_| ArchiveError::FilePathError)?;
+        let (afiles_count, flags) = Archiver::load_archive_header(&file)?;
+        let encrypted = flags & FLAG_ENCRYPTED != 0;
+        let encrypted_headers = flags & FLAG_ENCRYPTED_HEADERS != 0;
+        if flags & FLAG_RECIPIENTS != 0 {
+            Archiver::load_envelope_section(&file)?;
+        }
+        if flags & FLAG_KEY_SLOTS != 0 {
+            Archiver::load_key_slots_section(&file)?;
+        }
+        if flags & FLAG_DICTIONARY != 0 {
+            let length = Archiver::load_dictionary_length(&file)?;
+            file.seek(SeekFrom::Current(length as i64)).map_err(|_| ArchiveError::FilePathError)?;
+        }
+
+        let mut entries = Vec::with_capacity(afiles_count);
+        if flags & FLAG_SOLID != 0 {
+            for block in 0..Archiver::load_blocks_count(&file)? {
+                let header = self.load_block_header(&file, encrypted_headers)?;
+                file.seek(SeekFrom::Current(header.zip_size as i64)).map_err(|_| ArchiveError::FilePathError)?;
+
+                entries.extend(header.members.into_iter().map(|m| EntryInfo {
+                    rel_path: m.rel_path,
+                    mode: m.mode,
+                    size: m.size,
+                    zip_size: 0,
+                    codec: header.codec,
+                    encrypted,
+                    block: Some(block),
+                }));
+            }
+
+            return Ok(entries);
+        }
+
+        for _ in 0..afiles_count {
+            let info = self.load_entry_info(&file, encrypted, encrypted_headers)?;
+            file.seek(SeekFrom::Current(info.zip_size as i64)).map_err(|_| ArchiveError::FilePathError)?;
+            entries.push(info);
+        }
+
+        Ok(entries)
+    }
+
     fn store_archive_header(&self, file: &File, afiles_count: usize) -> Result<(), ArchiveError> {
-        let files_count = afiles_count.to_ne_bytes().to_vec();
-        // println!("files_count length: {}", files_count.len());
-        let encrypted: Vec<u8> = if let Some(_) = self.processor {vec![1,0,0,0,0,0,0,0]} else {vec![0,0,0,0,0,0,0,0]};
-        // println!("encrypted length: {}", encrypted.len());
-        // println!("magick length: {}", MAGICK.len());
+        let mut flags = 0u64;
+        if self.processor.is_some() { flags |= FLAG_ENCRYPTED; }
+        if self.dictionary.is_some() { flags |= FLAG_DICTIONARY; }
+        if self.solid_block_size.is_some() { flags |= FLAG_SOLID; }
+        if self.envelope.is_some() { flags |= FLAG_RECIPIENTS; }
+        if self.key_slots.is_some() { flags |= FLAG_KEY_SLOTS; }
+        if self.header_processor().is_some() { flags |= FLAG_ENCRYPTED_HEADERS; }
+        if self.skein_kdf { flags |= FLAG_SKEIN_KDF; }
+        if let Some(mode) = self.cipher_mode.filter(|_| self.processor.is_some()) {
+            flags |= (mode.to_byte() as u64) << MODE_SHIFT;
+        }
+
+        // the dictionary is made of file contents, so it is encrypted like them
+        let dictionary = self.dictionary.as_ref().map(|dictionary| match &self.processor {
+            Some(p) => p.encrypt_blocks(dictionary.content()),
+            None => dictionary.content().to_vec(),
+        });
 
+        Archiver::store_header_sections(file, afiles_count, flags, self.envelope.as_ref(), self.key_slots.as_ref(), dictionary.as_deref())
+    }
+
+    /// Writes the header with the sections `flags` announce, `dictionary` is already encrypted.
+    fn store_header_sections(file: &File, afiles_count: usize, flags: u64, envelope: Option<&Envelope>, key_slots: Option<&KeySlots>, dictionary: Option<&[u8]>) -> Result<(), ArchiveError> {
         Archiver::store_data(file, &MAGICK)?;
-        Archiver::store_data(file, &files_count)?;
-        Archiver::store_data(file, &encrypted)?;
+        Archiver::store_data(file, &afiles_count.to_ne_bytes())?;
+        Archiver::store_data(file, &flags.to_ne_bytes())?;
+
+        if let Some(envelope) = envelope {
+            let content = envelope.to_bytes();
+            Archiver::store_data(file, &content.len().to_ne_bytes())?;
+            Archiver::store_data(file, &content)?;
+        }
+
+        if let Some(key_slots) = key_slots {
+            Archiver::store_data(file, &key_slots.to_bytes())?;
+        }
+
+        if let Some(content) = dictionary {
+            Archiver::store_data(file, &content.len().to_ne_bytes())?;
+            Archiver::store_data(file, content)?;
+        }
 
         Ok(())
     }
 
-    fn load_archive_header(&mut self, mut file: &File) -> Result<(usize, bool), ArchiveError> {
+    fn load_envelope_section(mut file: &File) -> Result<Envelope, ArchiveError> {
+        let mut buffer = [0u8; 8];
+        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let length = LittleEndian::read_u64(&buffer) as usize;
+
+        if length > MAX_ENVELOPE_SIZE || length as u64 > Archiver::remaining(file)? {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        let mut content = vec![0u8; length];
+        file.read_exact(&mut content).map_err(|_| ArchiveError::FilePathError)?;
+
+        Envelope::from_bytes(&content).map_err(|_| ArchiveError::InvalidEnvelope)
+    }
+
+    fn load_key_slots_section(mut file: &File) -> Result<KeySlots, ArchiveError> {
+        let mut buffer = [0u8; 8];
+        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let key_size = LittleEndian::read_u64(&buffer) as usize;
+
+        // content keys are as long as the largest cipher key at most
+        if key_size > 128 || KeySlots::encoded_size(key_size) as u64 - 8 > Archiver::remaining(file)? {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        let mut content = vec![0u8; KeySlots::encoded_size(key_size)];
+        content[..8].copy_from_slice(&buffer);
+        file.read_exact(&mut content[8..]).map_err(|_| ArchiveError::FilePathError)?;
+
+        KeySlots::from_bytes(&content).map_err(|_| ArchiveError::InvalidKeySlot)
+    }
+
+    fn load_dictionary_length(mut file: &File) -> Result<usize, ArchiveError> {
+        let mut buffer = [0u8; 8];
+        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let length = LittleEndian::read_u64(&buffer) as usize;
+
+        // the encrypted content is padded to the cipher block
+        if length > MAX_DICTIONARY_SIZE + 128 || length as u64 > Archiver::remaining(file)? {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        Ok(length)
+    }
+
+    /// Reads the dictionary section. Returns `None` if it is encrypted and there is no key,
+    /// entries depending on it then fail with [`ArchiveError::MissingDictionary`].
+    fn load_dictionary(&self, mut file: &File, encrypted: bool) -> Result<Option<Arc<Dictionary>>, ArchiveError> {
+        let length = Archiver::load_dictionary_length(file)?;
+
+        let mut content = vec![0u8; length];
+        file.read_exact(&mut content).map_err(|_| ArchiveError::FilePathError)?;
+
+        let content = match (&self.processor, encrypted) {
+            (_, false) => content,
+            (Some(p), true) => p.decrypt_blocks(&content).map_err(|_| ArchiveError::DecryptError)?,
+            (None, true) => return Ok(None),
+        };
+
+        Ok(Some(Arc::new(Dictionary::new(content))))
+    }
+
+    /// Returns the number of entries and the header flags.
+    fn load_archive_header(mut file: &File) -> Result<(usize, u64), ArchiveError> {
         let mut buffer = [0u8; 8];
         file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
 
@@ -147,87 +895,233 @@ impl Archiver {
         let files_count = LittleEndian::read_u32(&buffer) as usize;
 
         file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
-        let encrypted = LittleEndian::read_u32(&buffer) != 0;
+        let flags = LittleEndian::read_u64(&buffer);
+
+        // members of solid blocks have no codec and sizes of their own
+        let entry_size = if flags & FLAG_SOLID != 0 { MEMBER_HEADER_SIZE } else { ENTRY_HEADER_SIZE };
+        if files_count as u64 > Archiver::remaining(file)? / entry_size {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        Ok((files_count, flags))
+    }
+
+    fn load_blocks_count(mut file: &File) -> Result<usize, ArchiveError> {
+        let mut buffer = [0u8; 8];
+        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let blocks_count = LittleEndian::read_u64(&buffer);
+
+        if blocks_count > Archiver::remaining(file)? / solid::BLOCK_HEADER_SIZE {
+            return Err(ArchiveError::CorruptedHeader);
+        }
 
-        Ok((files_count, encrypted))
+        Ok(blocks_count as usize)
     }
 
-    fn store_afile(file: &File, afile: ArchiveFile) -> Result<(), ArchiveError> {
+    /// Writes the entry header and the body. With `header_processor` the header
+    /// is encrypted, see [`Archiver::store_header_record`].
+    fn store_afile(file: &File, afile: ArchiveFile, header_processor: Option<&Arc<dyn CipherProcessor>>) -> Result<(), ArchiveError> {
         let mut name: Vec<u8> = afile.rel_path.as_bytes().to_vec();
         while name.len() % 4 != 0 {
             name.push(0);
         }
 
+        let mut header = Zeroizing::new(Vec::with_capacity(ENTRY_HEADER_SIZE as usize + name.len()));
+        header.extend_from_slice(&afile.mode().to_ne_bytes());
+        header.extend_from_slice(&afile.size().to_ne_bytes());
+        header.extend_from_slice(&afile.body_size().to_ne_bytes());
+        header.extend_from_slice(&afile.codec().to_bytes());
+        header.extend_from_slice(&name.len().to_ne_bytes());
+        header.extend_from_slice(&name);
 
-        let name_length: Vec<u8> = name.len().to_ne_bytes().to_vec();
-        // println!("name_length length: {}", name_length.len());
-        // println!("name: {:?}", name);
-        // println!("name_length: {:?}", name_length);
+        let body = afile.take_body();
 
-        let mode: Vec<u8> = afile.mode().to_ne_bytes().to_vec();
-        // println!("mode length: {}", mode.len());
+        Archiver::store_header_record(file, &header, header_processor)?;
+        Archiver::store_data(file, &body)?;
 
-        let size: Vec<u8> = afile.size().to_ne_bytes().to_vec();
-        // println!("size length: {}", size.len());
+        Ok(())
+    }
 
-        let zip_size: Vec<u8> = afile.body_size().to_ne_bytes().to_vec();
-        // println!("zip_size length: {}", zip_size.len());
+    /// Writes an entry or block header. Encrypted headers are stored as the length
+    /// of the ciphertext followed by the ciphertext, so names and sizes stay hidden.
+    /// Every record gets its own IV, headers which start alike still differ.
+    pub(crate) fn store_header_record(file: &File, header: &[u8], header_processor: Option<&Arc<dyn CipherProcessor>>) -> Result<(), ArchiveError> {
+        match header_processor {
+            Some(p) => {
+                let content = p.encrypt_record(header);
+                Archiver::store_data(file, &content.len().to_ne_bytes())?;
+                Archiver::store_data(file, &content)
+            },
+            None => Archiver::store_data(file, header),
+        }
+    }
 
-        let compressed: Vec<u8> = if afile.is_compressed() {vec![1,0,0,0,0,0,0,0]} else {vec![0,0,0,0,0,0,0,0]};
-        
-        let body = afile.take_body();
+    /// Reads and decrypts a header stored by [`Archiver::store_header_record`].
+    pub(crate) fn load_header_record(&self, mut file: &File) -> Result<Zeroizing<Vec<u8>>, ArchiveError> {
+        let processor = self.processor.as_ref().ok_or(ArchiveError::EncryptedHeaders)?;
 
-        Archiver::store_data(file, &mode)?;
-        Archiver::store_data(file, &size)?;
-        Archiver::store_data(file, &zip_size)?;
-        Archiver::store_data(file, &compressed)?;
-        Archiver::store_data(file, &name_length)?;
-        Archiver::store_data(file, &name)?;
-        Archiver::store_data(file, &body)?;
+        let mut buffer = [0u8; 8];
+        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let length = LittleEndian::read_u64(&buffer);
 
-        Ok(())
+        if length > MAX_HEADER_RECORD_SIZE || length > Archiver::remaining(file)? {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        let mut content = vec![0u8; length as usize];
+        file.read_exact(&mut content).map_err(|_| ArchiveError::FilePathError)?;
+
+        processor.decrypt_blocks(&content)
+            .map(Zeroizing::new)
+            .map_err(|_| ArchiveError::DecryptError)
+    }
+
+    /// Reads the next block record up to its body.
+    fn load_block_header(&self, file: &File, encrypted_headers: bool) -> Result<BlockHeader, ArchiveError> {
+        let header = if encrypted_headers {
+            let record = self.load_header_record(file)?;
+            let mut reader = &record[..];
+            let header = BlockHeader::load(&mut reader, record.len() as u64, self.max_entry_size, MAX_NAME_LENGTH)?;
+            if !reader.is_empty() {
+                return Err(ArchiveError::CorruptedHeader);
+            }
+            header
+        } else {
+            BlockHeader::load(file, Archiver::remaining(file)?, self.max_entry_size, MAX_NAME_LENGTH)?
+        };
+
+        if header.zip_size as u64 > Archiver::remaining(file)? {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        Ok(header)
     }
 
-    fn load_afile(mut file: &File, encrypted:bool) -> Result<ArchiveFile, ArchiveError> {
+    /// Processor for entry and block headers, `None` if they are stored in clear.
+    fn header_processor(&self) -> Option<&Arc<dyn CipherProcessor>> {
+        self.processor.as_ref().filter(|_| self.encrypted_headers)
+    }
+
+    /// Returns the length of the signed part of the archive and the signature,
+    /// `None` if the header `flags` do not mark the archive as signed. Unsigned
+    /// archives may end with anything, the magick alone does not make a trailer.
+    fn load_signature_trailer(mut file: &File, flags: u64) -> Result<Option<(u64, [u8; SIGNATURE_SIZE])>, ArchiveError> {
+        if flags & FLAG_SIGNED == 0 {
+            return Ok(None);
+        }
+
+        let len = file.metadata().map_err(|_| ArchiveError::ErrorWithMetadataRead)?.len();
+        if len < SIGNATURE_TRAILER_SIZE {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        let mut trailer = [0u8; SIGNATURE_TRAILER_SIZE as usize];
+        file.seek(SeekFrom::Start(len - SIGNATURE_TRAILER_SIZE)).map_err(|_| ArchiveError::FilePathError)?;
+        file.read_exact(&mut trailer).map_err(|_| ArchiveError::FilePathError)?;
+        if trailer[SIGNATURE_SIZE..] != SIGNATURE_MAGICK {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        Ok(Some((len - SIGNATURE_TRAILER_SIZE, trailer[..SIGNATURE_SIZE].try_into().unwrap())))
+    }
+
+    /// Skein-512 digest of the first `length` bytes of the archive, read in chunks.
+    fn archive_digest(mut file: &File, length: u64) -> Result<Vec<u8>, ArchiveError> {
+        file.seek(SeekFrom::Start(0)).map_err(|_| ArchiveError::FilePathError)?;
+
+        let mut hasher = Skein::new(64, 64).unwrap();
+        let mut reader = file.take(length);
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buffer).map_err(|_| ArchiveError::ErrorWithFileRead)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// Bytes left between the current position and the end of the archive.
+    fn remaining(mut file: &File) -> Result<u64, ArchiveError> {
+        let len = file.metadata().map_err(|_| ArchiveError::ErrorWithMetadataRead)?.len();
+        let pos = file.stream_position().map_err(|_| ArchiveError::FilePathError)?;
+
+        Ok(len.saturating_sub(pos))
+    }
+
+    fn load_entry_info(&self, mut file: &File, encrypted: bool, encrypted_headers: bool) -> Result<EntryInfo, ArchiveError> {
+        if encrypted_headers {
+            let header = self.load_header_record(file)?;
+            let (mode, size, zip_size, codec, name_length) = self.load_entry_fields(&header[..])?;
+
+            let name = header.get(ENTRY_HEADER_SIZE as usize..).ok_or(ArchiveError::CorruptedHeader)?;
+            if name.len() != name_length || zip_size as u64 > Archiver::remaining(file)? {
+                return Err(ArchiveError::CorruptedHeader);
+            }
+
+            let rel_path = String::from_utf8_lossy(name).trim_end_matches('\0').to_string();
+            return Ok(EntryInfo { rel_path, mode, size, zip_size, codec, encrypted, block: None });
+        }
+
+        let (mode, size, zip_size, codec, name_length) = self.load_entry_fields(file)?;
+        if name_length.saturating_add(zip_size) as u64 > Archiver::remaining(file)? {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        // println!("name_length: {}", name_length as u64);
+        // println!("buffer: {:?}", buffer);
+
+        let mut name_buffer = vec![0u8; name_length];
+        file.read_exact(&mut name_buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let rel_path = String::from_utf8_lossy(&name_buffer).to_string().trim_end_matches('\0').to_string();
+
+        Ok(EntryInfo { rel_path, mode, size, zip_size, codec, encrypted, block: None })
+    }
+
+    /// Reads mode, size, zip_size, codec and name_length of an entry header.
+    fn load_entry_fields(&self, mut reader: impl Read) -> Result<(u64, usize, usize, EntryCodec, usize), ArchiveError> {
         let mut buffer = [0u8; 8];
 
-        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
         let mode = LittleEndian::read_u64(&buffer);
 
         // println!("mode: {}", mode);
 
-        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
         let size = LittleEndian::read_u64(&buffer) as usize;
 
         // println!("size: {}", size);
 
-        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
         let zip_size = LittleEndian::read_u64(&buffer) as usize;
 
         // println!("zip_size: {}", zip_size);
         // println!("buffer: {:?}", buffer);
 
-        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
-        let compressed = LittleEndian::read_u64(&buffer) != 0;
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let codec = EntryCodec::from_bytes(buffer)?;
 
-        // println!("compressed: {}", compressed);
         // println!("buffer: {:?}", buffer);
 
-        file.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
         let name_length = LittleEndian::read_u64(&buffer) as usize;
 
-        // println!("name_length: {}", name_length as u64);
-        // println!("buffer: {:?}", buffer);
+        if size > self.max_entry_size || name_length > MAX_NAME_LENGTH {
+            return Err(ArchiveError::CorruptedHeader);
+        }
 
-        let mut name_buffer = vec![0u8; name_length];
-        file.read_exact(&mut name_buffer).map_err(|_| ArchiveError::FilePathError)?;
-        let name = String::from_utf8_lossy(&name_buffer).to_string().trim_end_matches('\0').to_string();
+        Ok((mode, size, zip_size, codec, name_length))
+    }
 
-        let mut body = vec![0u8; zip_size];
-        file.read_exact(&mut body).map_err(|_| ArchiveError::FilePathError)?;
+    fn load_afile(&self, mut file: &File, encrypted: bool, encrypted_headers: bool) -> Result<ArchiveFile, ArchiveError> {
+        let info = self.load_entry_info(file, encrypted, encrypted_headers)?;
 
-        Ok(ArchiveFile::new(name, compressed, encrypted, mode, size, body))
+        let mut body = vec![0u8; info.zip_size];
+        file.read_exact(&mut body).map_err(|_| ArchiveError::FilePathError)?;
 
+        Ok(ArchiveFile::new(info.rel_path, info.codec, encrypted, info.mode, info.size, body))
     }
     
     fn store_data(mut file: &File, data: &[u8]) -> Result<(), ArchiveError>  {
@@ -246,13 +1140,7 @@ impl Archiver {
             let afile = result?;
             println!("Files unziped: {}/{}, size: {}, path: {}", without_errors, afiles_count, afile.size(), afile.rel_path);
 
-            let output_path = output_dir.join(&afile.rel_path);
-
-            let prefix = output_path.parent().unwrap();
-            std::fs::create_dir_all(prefix).unwrap();
-
-            let file = File::create(output_path).map_err(|_| ArchiveError::FilePathError)?;
-            Archiver::store_data(&file, &afile.take_body())?;
+            Archiver::store_file(output_dir, afile)?;
 
             without_errors += 1;
         }
@@ -260,12 +1148,24 @@ impl Archiver {
         Ok(without_errors)
     }
 
+    fn store_file(output_dir: &Path, afile: ArchiveFile) -> Result<(), ArchiveError> {
+        let output_path = output_dir.join(&afile.rel_path);
+
+        let prefix = output_path.parent().unwrap();
+        std::fs::create_dir_all(prefix).unwrap();
+
+        let file = File::create(output_path).map_err(|_| ArchiveError::FilePathError)?;
+        Archiver::store_data(&file, &Zeroizing::new(afile.take_body()))
+    }
+
     fn store_archive(&mut self, output_path: &Path, afiles_count: usize) -> Result<usize, ArchiveError> {
         let file = File::create(output_path).map_err(|_| ArchiveError::FilePathError)?;
 
         self.store_archive_header(&file, afiles_count)?;
 
         let mut without_errors = 0;
+        let registry = self.registry.clone();
+        let header_processor = self.header_processor().cloned();
 
         let rx = self.rx.as_mut().unwrap()
             .iter()
@@ -273,9 +1173,9 @@ impl Archiver {
 
         for result in rx {
             let afile = result?;
-            println!("Files zipped: {}/{}, size: {}, path: {}", without_errors, afiles_count, afile.size(), afile.rel_path);
+            println!("Files zipped: {}/{}, size: {}, codec: {}, path: {}", without_errors, afiles_count, afile.size(), codec_name(&registry, afile.codec()), afile.rel_path);
 
-            Archiver::store_afile(&file, afile)?;
+            Archiver::store_afile(&file, afile, header_processor.as_ref())?;
 
             without_errors += 1;
         }
@@ -285,16 +1185,62 @@ impl Archiver {
 
 }
 
-fn worker_zip(path: &Path, base_dir: &Path, compressor: Option<Arc<dyn Compressor>>, processor: Option<Arc<dyn CipherProcessor>>) -> Result<ArchiveFile, ArchiveError> {
+/// Codec name of an entry as shown to the user.
+pub fn codec_name(registry: &Registry, codec: EntryCodec) -> String {
+    let name = if codec.id == CODEC_NONE {
+        "stored".to_string()
+    } else {
+        registry.name(codec.id)
+            .map(|name| format!("{}:{}", name, codec.level))
+            .unwrap_or_else(|| format!("unknown({})", codec.id))
+    };
+
+    let name = match codec.filter {
+        Filter::None => name,
+        filter => format!("{}+{}", filter, name),
+    };
+    let name = if codec.dictionary { format!("{}+dict", name) } else { name };
+
+    if codec.auto { format!("{} (auto)", name) } else { name }
+}
+
+fn key_slot_error(error: CipherError) -> ArchiveError {
+    match error {
+        CipherError::KeySlotsFull => ArchiveError::KeySlotsFull,
+        CipherError::LastKeySlot => ArchiveError::LastKeySlot,
+        CipherError::NoMatchingKeySlot => ArchiveError::DecryptError,
+        _ => ArchiveError::InvalidKeySlot,
+    }
+}
+
+fn worker_zip(path: &Path, base_dir: &Path, compressor: Option<Arc<dyn Compressor>>, selector: Option<Arc<CodecSelector>>, filter: Filter, processor: Option<Arc<dyn CipherProcessor>>, padding: Padding) -> Result<ArchiveFile, ArchiveError> {
     let afile = ArchiveFile::from_file(&path, &base_dir)?;
 
-    let afile = match compressor {
-        Some(c) => afile.compress(c)?,
+    pack_afile(afile, compressor, selector, filter, processor, padding)
+}
+
+fn worker_zip_solid(paths: &[PathBuf], base_dir: &Path, compressor: Option<Arc<dyn Compressor>>, selector: Option<Arc<CodecSelector>>, filter: Filter, processor: Option<Arc<dyn CipherProcessor>>, padding: Padding) -> Result<SolidBlock, ArchiveError> {
+    let block = SolidBlock::from_files(paths, base_dir)?;
+    let afile = pack_afile(block.afile, compressor, selector, filter, processor, padding)?;
+
+    Ok(SolidBlock { afile, ..block })
+}
+
+/// Filters, compresses and encrypts the body of `afile`.
+fn pack_afile(afile: ArchiveFile, compressor: Option<Arc<dyn Compressor>>, selector: Option<Arc<CodecSelector>>, filter: Filter, processor: Option<Arc<dyn CipherProcessor>>, padding: Padding) -> Result<ArchiveFile, ArchiveError> {
+    let afile = match filter {
+        Filter::None => afile,
+        f => afile.filter(f)?,
+    };
+
+    let afile = match (selector, compressor) {
+        (Some(s), _) => afile.compress_auto(&s)?,
+        (None, Some(c)) => afile.compress(c)?,
         _ => afile,
     };
 
     let afile = match processor {
-        Some(p) => afile.encrypt(p)?,
+        Some(p) => afile.encrypt_padded(p, padding)?,
         None => afile,
     };
 
@@ -302,7 +1248,50 @@ fn worker_zip(path: &Path, base_dir: &Path, compressor: Option<Arc<dyn Compresso
 }
 
 
-fn worker_unzip(afile: ArchiveFile, compressor: Option<Arc<dyn Compressor>>, processor: Option<Arc<dyn CipherProcessor>>) -> Result<ArchiveFile, ArchiveError> {
+/// What [`Archiver::open_archive`] reads before the entries: the file positioned
+/// at the first entry, the number of entries, the header flags and the dictionary.
+struct OpenedArchive {
+    file: File,
+    count: usize,
+    flags: u64,
+    dictionary: Option<Arc<Dictionary>>,
+}
+
+/// Decompressors shared by the unzip workers. Codecs which can use the dictionary
+/// are primed with it once per archive, as [`Archiver::primed_compressors`] does for zip.
+struct Decompressors {
+    registry: Arc<Registry>,
+    primed: BTreeMap<u8, Arc<dyn Compressor>>,
+}
+
+impl Decompressors {
+    fn new(registry: Arc<Registry>, dictionary: Option<&Dictionary>) -> Decompressors {
+        let primed = match dictionary {
+            Some(dictionary) => registry.ids().into_iter()
+                .filter_map(|id| {
+                    let decompressor = registry.get(id, &CompressorOptions::default())?;
+                    decompressor.with_dictionary(dictionary).map(|primed| (id, primed))
+                })
+                .collect(),
+            None => BTreeMap::new(),
+        };
+
+        Decompressors { registry, primed }
+    }
+
+    fn get(&self, codec: EntryCodec) -> Result<Arc<dyn Compressor>, ArchiveError> {
+        let decompressor = self.registry.get(codec.id, &CompressorOptions::default())
+            .ok_or(ArchiveError::UnknownCodec)?;
+        if !codec.dictionary {
+            return Ok(decompressor);
+        }
+
+        self.primed.get(&codec.id).cloned().ok_or(ArchiveError::MissingDictionary)
+    }
+}
+
+
+fn worker_unzip(afile: ArchiveFile, decompressors: Arc<Decompressors>, processor: Option<Arc<dyn CipherProcessor>>) -> Result<ArchiveFile, ArchiveError> {
 
     let afile = match processor {
         Some(p)  if afile.is_encrypted() => afile.decrypt(p)?,
@@ -310,12 +1299,26 @@ fn worker_unzip(afile: ArchiveFile, compressor: Option<Arc<dyn Compressor>>, pro
     };
     // println!("Decrypted: {:?}", afile.rel_path);
 
-    let afile = match compressor {
-        Some(c) if afile.is_compressed() => afile.decompress(c)?,
-        _ => afile,
+    let afile = if afile.is_compressed() {
+        let decompressor = decompressors.get(afile.codec())?;
+        afile.decompress(decompressor)?
+    } else {
+        afile
+    };
+
+    let afile = if afile.is_filtered() {
+        afile.unfilter()?
+    } else {
+        afile
     };
 
     // println!("Decompressed: {:?}", afile.rel_path);
 
     Ok(afile)
-}
\ No newline at end of file
+}
+
+fn worker_unzip_solid(block: SolidBlock, decompressors: Arc<Decompressors>, processor: Option<Arc<dyn CipherProcessor>>) -> Result<Vec<ArchiveFile>, ArchiveError> {
+    let afile = worker_unzip(block.afile, decompressors, processor)?;
+
+    SolidBlock { afile, ..block }.into_files()
+}
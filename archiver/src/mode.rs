@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use super::ArchiveError;
+
+const MODE_CBC: u8 = 1;
+const MODE_CTR: u8 = 2;
+const MODE_XTS: u8 = 3;
+
+/// Mode of the processor entry bodies are encrypted with. It is kept in the
+/// archive header, so an archive is opened without naming the mode again.
+/// Archives made before it was stored have no mode and were mostly CBC.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CipherMode {
+    #[default]
+    Cbc,
+    Ctr,
+    Xts,
+}
+
+impl CipherMode {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            CipherMode::Cbc => MODE_CBC,
+            CipherMode::Ctr => MODE_CTR,
+            CipherMode::Xts => MODE_XTS,
+        }
+    }
+
+    /// Zero is left for archives without a stored mode.
+    pub fn from_byte(byte: u8) -> Result<Option<CipherMode>, ArchiveError> {
+        match byte {
+            0 => Ok(None),
+            MODE_CBC => Ok(Some(CipherMode::Cbc)),
+            MODE_CTR => Ok(Some(CipherMode::Ctr)),
+            MODE_XTS => Ok(Some(CipherMode::Xts)),
+            _ => Err(ArchiveError::UnknownCipherMode),
+        }
+    }
+}
+
+/// Parses `cbc`, `ctr` and `xts`.
+impl FromStr for CipherMode {
+    type Err = ArchiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cbc" => Ok(CipherMode::Cbc),
+            "ctr" => Ok(CipherMode::Ctr),
+            "xts" => Ok(CipherMode::Xts),
+            _ => Err(ArchiveError::UnknownCipherMode),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod mode_test {
+    use super::*;
+
+    #[test]
+    fn byte_res() {
+        for mode in [CipherMode::Cbc, CipherMode::Ctr, CipherMode::Xts] {
+            assert_eq!(CipherMode::from_byte(mode.to_byte()).unwrap(), Some(mode));
+        }
+
+        assert_eq!(CipherMode::from_byte(0).unwrap(), None);
+        assert_eq!(CipherMode::from_byte(4).unwrap_err(), ArchiveError::UnknownCipherMode);
+        assert_eq!("xts".parse::<CipherMode>().unwrap(), CipherMode::Xts);
+        assert_eq!("ecb".parse::<CipherMode>().unwrap_err(), ArchiveError::UnknownCipherMode);
+    }
+}
@@ -0,0 +1,227 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use compressor::Compressor;
+use compressor::filter::Filter;
+use compressor::dictionary::Dictionary;
+
+/// Bytes taken from the start, the middle and the end of a file for the entropy estimate.
+const SAMPLE_CHUNK: usize = 16 * 1024;
+
+/// Files with a higher order-0 entropy (bits per byte) are stored as is.
+const STORED_ENTROPY: f64 = 7.5;
+/// Files with a lower entropy are worth the strong codec.
+const STRONG_ENTROPY: f64 = 6.0;
+
+/// Files smaller than this are stored: codec headers would outweigh any gain.
+const MIN_COMPRESS_SIZE: usize = 64;
+
+const COMPRESSED_EXTENSIONS: [&str; 28] = [
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "avif",
+    "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "lz4", "rz",
+    "mp3", "ogg", "flac", "aac", "m4a", "opus",
+    "mp4", "mkv", "webm", "avi", "mov",
+];
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_MACHINE_386: u16 = 0x03;
+const ELF_MACHINE_X86_64: u16 = 0x3e;
+const ELF_MACHINE_AARCH64: u16 = 0xb7;
+
+const MAGICS: [(usize, &[u8]); 14] = [
+    (0, &[0xff, 0xd8, 0xff]),                         // jpeg
+    (0, &[0x89, b'P', b'N', b'G']),                   // png
+    (0, b"GIF8"),                                     // gif
+    (0, &[b'P', b'K', 0x03, 0x04]),                   // zip, jar, docx
+    (0, &[0x1f, 0x8b]),                               // gzip
+    (0, b"BZh"),                                      // bzip2
+    (0, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),       // xz
+    (0, &[0x28, 0xb5, 0x2f, 0xfd]),                   // zstd
+    (0, &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c]),       // 7z
+    (0, b"Rar!"),                                     // rar
+    (0, b"OggS"),                                     // ogg
+    (0, b"fLaC"),                                     // flac
+    (0, b"ID3"),                                      // mp3
+    (4, b"ftyp"),                                     // mp4, mov, heic
+];
+
+/// Choice is the kind of codec picked for an entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Choice {
+    Stored,
+    Fast,
+    Strong,
+}
+
+/// CodecSelector picks a codec per file from its extension, magic number
+/// and the entropy of a sample of its content.
+pub struct CodecSelector {
+    fast: Arc<dyn Compressor>,
+    strong: Arc<dyn Compressor>,
+}
+
+impl CodecSelector {
+    pub fn new(fast: Arc<dyn Compressor>, strong: Arc<dyn Compressor>) -> CodecSelector {
+        CodecSelector { fast, strong }
+    }
+
+    pub fn choose(&self, path: &Path, body: &[u8]) -> Choice {
+        if body.len() < MIN_COMPRESS_SIZE || is_compressed_extension(path) || has_compressed_magic(body) {
+            return Choice::Stored;
+        }
+
+        let entropy = sample_entropy(body);
+        if entropy >= STORED_ENTROPY {
+            Choice::Stored
+        } else if entropy < STRONG_ENTROPY {
+            Choice::Strong
+        } else {
+            Choice::Fast
+        }
+    }
+
+    /// Returns a selector whose codecs are primed with `dictionary` when they support one.
+    pub fn with_dictionary(&self, dictionary: &Dictionary) -> CodecSelector {
+        let prime = |c: &Arc<dyn Compressor>| c.with_dictionary(dictionary).unwrap_or_else(|| c.clone());
+        CodecSelector::new(prime(&self.fast), prime(&self.strong))
+    }
+
+    /// Picks the branch converter for ELF executables, other files are not filtered.
+    pub fn filter(&self, body: &[u8]) -> Filter {
+        if body.len() < 20 || body[..4] != ELF_MAGIC {
+            return Filter::None;
+        }
+
+        // e_machine follows the data encoding given in EI_DATA
+        let machine = match body[5] {
+            1 => u16::from_le_bytes([body[18], body[19]]),
+            2 => u16::from_be_bytes([body[18], body[19]]),
+            _ => return Filter::None,
+        };
+
+        match machine {
+            ELF_MACHINE_386 | ELF_MACHINE_X86_64 => Filter::X86,
+            ELF_MACHINE_AARCH64 => Filter::Arm64,
+            _ => Filter::None,
+        }
+    }
+
+    /// Returns the compressor for `choice`, `None` means the entry is stored.
+    pub fn compressor(&self, choice: Choice) -> Option<Arc<dyn Compressor>> {
+        match choice {
+            Choice::Stored => None,
+            Choice::Fast => Some(self.fast.clone()),
+            Choice::Strong => Some(self.strong.clone()),
+        }
+    }
+}
+
+fn is_compressed_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| COMPRESSED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn has_compressed_magic(body: &[u8]) -> bool {
+    MAGICS.iter().any(|(offset, magic)| {
+        body.len() >= offset + magic.len() && &body[*offset..offset + magic.len()] == *magic
+    })
+}
+
+/// Order-0 entropy in bits per byte.
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+
+    let total = data.len() as f64;
+    counts.iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Entropy of up to three chunks taken from the start, the middle and the end of `body`.
+pub fn sample_entropy(body: &[u8]) -> f64 {
+    if body.len() <= 3 * SAMPLE_CHUNK {
+        return entropy(body);
+    }
+
+    let middle = body.len() / 2 - SAMPLE_CHUNK / 2;
+    let mut sample = Vec::with_capacity(3 * SAMPLE_CHUNK);
+    sample.extend_from_slice(&body[..SAMPLE_CHUNK]);
+    sample.extend_from_slice(&body[middle..middle + SAMPLE_CHUNK]);
+    sample.extend_from_slice(&body[body.len() - SAMPLE_CHUNK..]);
+
+    entropy(&sample)
+}
+
+
+#[cfg(test)]
+mod select_test {
+    use super::*;
+
+    use compressor::lz77::LZ77;
+    use compressor::bwt::Bwt;
+
+    fn get_selector() -> CodecSelector {
+        CodecSelector::new(Arc::new(LZ77::new()), Arc::new(Bwt::new()))
+    }
+
+    #[test]
+    fn entropy_res() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert_eq!(entropy(&[0, 1, 2, 3]), 2.0);
+
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(sample_entropy(&all.repeat(1000)), 8.0);
+    }
+
+    #[test]
+    fn choose_res() {
+        let selector = get_selector();
+
+        let text = b"lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(10);
+        assert_eq!(selector.choose(Path::new("a.txt"), &text), Choice::Strong);
+        assert_eq!(selector.choose(Path::new("a.zip"), &text), Choice::Stored);
+        assert_eq!(selector.choose(Path::new("a.txt"), &text[..10]), Choice::Stored);
+
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0];
+        jpeg.extend_from_slice(&text);
+        assert_eq!(selector.choose(Path::new("photo"), &jpeg), Choice::Stored);
+
+        let mixed: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 25) as u8).collect();
+        assert_eq!(selector.choose(Path::new("a.bin"), &mixed), Choice::Fast);
+
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        assert_eq!(selector.choose(Path::new("a.bin"), &noise), Choice::Stored);
+    }
+
+    #[test]
+    fn filter_res() {
+        let selector = get_selector();
+
+        let mut elf = vec![0u8; 64];
+        elf[..4].copy_from_slice(&ELF_MAGIC);
+        elf[5] = 1;
+
+        elf[18] = 0x3e;
+        assert_eq!(selector.filter(&elf), Filter::X86);
+        elf[18] = 0xb7;
+        assert_eq!(selector.filter(&elf), Filter::Arm64);
+        elf[18] = 0x28;
+        assert_eq!(selector.filter(&elf), Filter::None);
+
+        assert_eq!(selector.filter(b"lorem ipsum dolor sit amet"), Filter::None);
+    }
+}
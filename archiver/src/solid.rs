@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian};
+use zeroize::Zeroizing;
+
+use crypto::CipherProcessor;
+
+use super::afile::{ArchiveFile, EntryCodec};
+use super::{Archiver, ArchiveError};
+
+/// Solid blocks are filled up to this many bytes unless set with [`Archiver::with_solid`].
+pub const DEFAULT_SOLID_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// member count, codec, size and zip_size of a block record.
+pub(crate) const BLOCK_HEADER_SIZE: u64 = 32;
+/// mode, size, offset and name_length of a member record.
+pub(crate) const MEMBER_HEADER_SIZE: u64 = 32;
+
+/// SolidMember is a file stored inside a solid block, at `offset` of the decompressed block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolidMember {
+    pub rel_path: String,
+    pub mode: u64,
+    pub size: usize,
+    pub offset: usize,
+}
+
+/// SolidBlock is a group of files compressed as one stream. The block body
+/// is an [`ArchiveFile`], so it is filtered, compressed and encrypted like a
+/// single file, while the member table keeps where every file starts.
+///
+/// A block is stored as: member count, codec, size, zip size, the member
+/// records (mode, size, offset, name length, name) and the body.
+#[derive(Debug)]
+pub struct SolidBlock {
+    pub members: Vec<SolidMember>,
+    pub afile: ArchiveFile,
+}
+
+impl SolidBlock {
+    /// Reads `paths` and joins their contents into one body.
+    pub fn from_files(paths: &[PathBuf], base_dir: &Path) -> Result<SolidBlock, ArchiveError> {
+        let mut members = Vec::with_capacity(paths.len());
+        let mut body = Vec::new();
+
+        for path in paths {
+            let afile = ArchiveFile::from_file(path, base_dir)?;
+
+            members.push(SolidMember {
+                rel_path: afile.rel_path.clone(),
+                mode: afile.mode(),
+                size: afile.size(),
+                offset: body.len(),
+            });
+            body.extend_from_slice(&afile.take_body());
+        }
+
+        // the first path names the block, so the codec selector sees its extension
+        let name = members.first().map(|m| m.rel_path.clone()).unwrap_or_default();
+        let afile = ArchiveFile::new(name, EntryCodec::default(), false, 0, body.len(), body);
+
+        Ok(SolidBlock { members, afile })
+    }
+
+    /// Splits a decompressed block back into files.
+    pub fn into_files(self) -> Result<Vec<ArchiveFile>, ArchiveError> {
+        let body = Zeroizing::new(self.afile.take_body());
+
+        self.members.into_iter()
+            .map(|m| {
+                let content = body.get(m.offset..m.offset.saturating_add(m.size)).ok_or(ArchiveError::CorruptedHeader)?;
+                Ok(ArchiveFile::new(m.rel_path, EntryCodec::default(), false, m.mode, m.size, content.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Writes the block record. With `header_processor` everything but the body
+    /// is encrypted, see [`Archiver::store_header_record`].
+    pub(crate) fn store(self, file: &File, header_processor: Option<&Arc<dyn CipherProcessor>>) -> Result<(), ArchiveError> {
+        let mut header = Zeroizing::new(Vec::new());
+        header.extend_from_slice(&self.members.len().to_ne_bytes());
+        header.extend_from_slice(&self.afile.codec().to_bytes());
+        header.extend_from_slice(&self.afile.size().to_ne_bytes());
+        header.extend_from_slice(&self.afile.body_size().to_ne_bytes());
+
+        for member in &self.members {
+            let mut name: Vec<u8> = member.rel_path.as_bytes().to_vec();
+            name.resize(name.len().next_multiple_of(4), 0);
+
+            header.extend_from_slice(&member.mode.to_ne_bytes());
+            header.extend_from_slice(&member.size.to_ne_bytes());
+            header.extend_from_slice(&member.offset.to_ne_bytes());
+            header.extend_from_slice(&name.len().to_ne_bytes());
+            header.extend_from_slice(&name);
+        }
+
+        Archiver::store_header_record(file, &header, header_processor)?;
+        Archiver::store_data(file, &self.afile.take_body())
+    }
+}
+
+/// Block record without its body, as read from an archive.
+#[derive(Debug)]
+pub(crate) struct BlockHeader {
+    pub members: Vec<SolidMember>,
+    pub codec: EntryCodec,
+    pub size: usize,
+    pub zip_size: usize,
+}
+
+impl BlockHeader {
+    /// Reads a block record up to its body, `available` bytes are left in `reader`.
+    pub(crate) fn load(mut reader: impl Read, available: u64, max_size: usize, max_name_length: usize) -> Result<BlockHeader, ArchiveError> {
+        let mut buffer = [0u8; 8];
+
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let members_count = LittleEndian::read_u64(&buffer);
+
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let codec = EntryCodec::from_bytes(buffer)?;
+
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let size = LittleEndian::read_u64(&buffer) as usize;
+
+        reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+        let zip_size = LittleEndian::read_u64(&buffer) as usize;
+
+        if size > max_size || members_count > available.saturating_sub(BLOCK_HEADER_SIZE) / MEMBER_HEADER_SIZE {
+            return Err(ArchiveError::CorruptedHeader);
+        }
+
+        let mut members = Vec::with_capacity(members_count as usize);
+        for _ in 0..members_count {
+            reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+            let mode = LittleEndian::read_u64(&buffer);
+
+            reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+            let member_size = LittleEndian::read_u64(&buffer) as usize;
+
+            reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+            let offset = LittleEndian::read_u64(&buffer) as usize;
+
+            reader.read_exact(&mut buffer).map_err(|_| ArchiveError::FilePathError)?;
+            let name_length = LittleEndian::read_u64(&buffer) as usize;
+
+            if offset.saturating_add(member_size) > size || name_length > max_name_length {
+                return Err(ArchiveError::CorruptedHeader);
+            }
+
+            let mut name_buffer = vec![0u8; name_length];
+            reader.read_exact(&mut name_buffer).map_err(|_| ArchiveError::FilePathError)?;
+            let rel_path = String::from_utf8_lossy(&name_buffer).trim_end_matches('\0').to_string();
+
+            members.push(SolidMember { rel_path, mode, size: member_size, offset });
+        }
+
+        Ok(BlockHeader { members, codec, size, zip_size })
+    }
+
+    pub(crate) fn load_block(self, mut file: &File, encrypted: bool) -> Result<SolidBlock, ArchiveError> {
+        let mut body = vec![0u8; self.zip_size];
+        file.read_exact(&mut body).map_err(|_| ArchiveError::FilePathError)?;
+
+        let name = self.members.first().map(|m| m.rel_path.clone()).unwrap_or_default();
+        let afile = ArchiveFile::new(name, self.codec, encrypted, 0, self.size, body);
+
+        Ok(SolidBlock { members: self.members, afile })
+    }
+}
+
+/// Groups files by extension, then fills blocks of up to `block_size` bytes
+/// with the files of each group ordered by size. Files larger than a block
+/// get a block of their own.
+pub fn group_paths(paths: Vec<PathBuf>, block_size: usize) -> Result<Vec<Vec<PathBuf>>, ArchiveError> {
+    let mut groups: BTreeMap<String, Vec<(u64, PathBuf)>> = BTreeMap::new();
+    for path in paths {
+        let size = std::fs::symlink_metadata(&path).map_err(|_| ArchiveError::ErrorWithMetadataRead)?.len();
+        let extension = path.extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        groups.entry(extension).or_default().push((size, path));
+    }
+
+    let mut blocks = Vec::new();
+    for (_, mut files) in groups {
+        files.sort();
+
+        let mut block: Vec<PathBuf> = Vec::new();
+        let mut block_bytes = 0;
+        for (size, path) in files {
+            if !block.is_empty() && block_bytes + size as usize > block_size {
+                blocks.push(std::mem::take(&mut block));
+                block_bytes = 0;
+            }
+
+            block_bytes += size as usize;
+            block.push(path);
+        }
+
+        if !block.is_empty() {
+            blocks.push(block);
+        }
+    }
+
+    Ok(blocks)
+}
+
+
+#[cfg(test)]
+mod solid_test {
+    use super::*;
+
+    fn get_path(path: &str) -> PathBuf {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+        manifest.join(path)
+    }
+
+    #[test]
+    fn group_paths_res() {
+        let paths: Vec<PathBuf> = ["tests/static/file1.bin", "tests/static/folder1/file2.bin", "tests/static/folder1/file3.txt", "tests/static/text/file4.txt"]
+            .iter()
+            .map(|p| get_path(p))
+            .collect();
+
+        // file2.bin has 512KiB and file1.bin 2MiB, so they do not share a 2MiB block
+        let blocks = group_paths(paths.clone(), 2 << 20).unwrap();
+        assert_eq!(blocks, vec![
+            vec![paths[1].clone()],
+            vec![paths[0].clone()],
+            vec![paths[2].clone(), paths[3].clone()],
+        ]);
+
+        let blocks = group_paths(paths.clone(), 4 << 20).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0], vec![paths[1].clone(), paths[0].clone()]);
+    }
+
+    #[test]
+    fn from_into_files() {
+        let base_dir = get_path("tests/static");
+        let paths = vec![get_path("tests/static/folder1/file3.txt"), get_path("tests/static/text/file4.txt")];
+
+        let block = SolidBlock::from_files(&paths, &base_dir).unwrap();
+        assert_eq!(block.members[0].offset, 0);
+        assert_eq!(block.members[1].offset, block.members[0].size);
+        assert_eq!(block.afile.size(), block.members[0].size + block.members[1].size);
+
+        let files = block.into_files().unwrap();
+        for (afile, path) in files.into_iter().zip(&paths) {
+            assert_eq!(afile.take_body(), std::fs::read(path).unwrap());
+        }
+    }
+}
@@ -13,9 +13,16 @@ use crypto::{
 
 use compressor::{
     Compressor, 
-    lzw::LZW
+    lzw::LZW,
+    lz77::LZ77,
+    registry::{CODEC_NONE, CODEC_LZW, CODEC_LZ77}
 };
 
+use compressor::filter::{Filter, FILTER_DELTA};
+
+use archiver::select::CodecSelector;
+use archiver::padding::Padding;
+
 
 use rand::Rng;
 
@@ -176,6 +183,25 @@ fn decrypt_result() {
     assert_eq!(body1, body2);
 }
 
+#[test]
+fn decrypt_padded_result() {
+    let processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+
+    for (padding, padded_size) in [(Padding::PowerOfTwo, 2048), (Padding::Padme, 1152)] {
+        let body: Vec<u8> = (0..1100).map(|i| i as u8).collect();
+        let afile = ArchiveFile::new("doc.txt".to_string(), EntryCodec::default(), false, 0o644, body.len(), body.clone());
+
+        let afile = afile.encrypt_padded(processor.clone(), padding).unwrap();
+        assert_eq!(afile.codec().padding, padding);
+        // the IV and the CBC padding block come on top
+        assert_eq!(afile.body_size(), padded_size + 64);
+
+        let afile = afile.decrypt(processor.clone()).unwrap();
+        assert_eq!(afile.codec().padding, Padding::None);
+        assert_eq!(afile.take_body(), body);
+    }
+}
+
 #[test]
 fn decompress_errors() {
     let afile = ArchiveFile::from_file(&get_path(TEST_TEXT), Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap();
@@ -212,3 +238,112 @@ fn decompress_result() {
     assert!(!afile.is_compressed());
     assert_eq!(body1, afile.take_body());
 }
+
+#[test]
+fn compress_codec() {
+    let afile = ArchiveFile::from_file(&get_path(TEST_TEXT), Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap();
+    assert_eq!(afile.codec(), EntryCodec::default());
+
+    let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(3));
+    let afile = afile.compress(compressor).unwrap();
+
+    assert_eq!(afile.codec(), EntryCodec { id: CODEC_LZ77, level: 3, auto: false, filter: Filter::None, dictionary: false, padding: Padding::None });
+
+    let er = afile.decompress(Arc::new(LZW::new()));
+    assert!(er.is_err());
+    assert_eq!(er.unwrap_err(), ArchiveError::CodecMismatch);
+}
+
+#[test]
+fn entry_codec_bytes() {
+    // archives written before codec ids stored the compressed flag as 1
+    let codec = EntryCodec::from_bytes([1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+    assert_eq!(codec.id, CODEC_LZW);
+
+    let codec = EntryCodec { id: CODEC_LZ77, level: 9, auto: false, filter: Filter::None, dictionary: false, padding: Padding::None };
+    assert_eq!(EntryCodec::from_bytes(codec.to_bytes()).unwrap(), codec);
+
+    let codec = EntryCodec { id: CODEC_LZ77, level: 9, auto: true, filter: Filter::Delta(4), dictionary: false, padding: Padding::None };
+    assert_eq!(codec.to_bytes(), [CODEC_LZ77, 9, 1, FILTER_DELTA, 4, 0, 0, 0]);
+    assert_eq!(EntryCodec::from_bytes(codec.to_bytes()).unwrap(), codec);
+
+    let codec = EntryCodec { dictionary: true, ..codec };
+    assert_eq!(codec.to_bytes(), [CODEC_LZ77, 9, 1, FILTER_DELTA, 4, 1, 0, 0]);
+    assert_eq!(EntryCodec::from_bytes(codec.to_bytes()).unwrap(), codec);
+
+    let codec = EntryCodec { padding: Padding::Padme, ..codec };
+    assert_eq!(codec.to_bytes(), [CODEC_LZ77, 9, 1, FILTER_DELTA, 4, 1, 2, 0]);
+    assert_eq!(EntryCodec::from_bytes(codec.to_bytes()).unwrap(), codec);
+
+    let r = EntryCodec::from_bytes([1, 0, 0, 200, 0, 0, 0, 0]);
+    assert_eq!(r.unwrap_err(), ArchiveError::UnknownFilter);
+    let r = EntryCodec::from_bytes([1, 0, 0, 0, 0, 0, 9, 0]);
+    assert_eq!(r.unwrap_err(), ArchiveError::UnknownPadding);
+}
+
+#[test]
+fn compress_auto_codec() {
+    let selector = CodecSelector::new(Arc::new(LZ77::new()), Arc::new(LZW::new()));
+
+    let afile = ArchiveFile::from_file(&get_path(TEST_TEXT), Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap();
+    let body = afile.clone_body();
+    let afile = afile.compress_auto(&selector).unwrap();
+
+    assert!(afile.codec().auto);
+    assert!(afile.is_compressed());
+    assert!(afile.body_size() < body.len());
+
+    let afile = afile.decompress(Arc::new(LZW::new())).unwrap();
+    assert_eq!(body, afile.take_body());
+
+    let noise: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+    let afile = ArchiveFile::new("noise.bin".to_string(), EntryCodec::default(), false, 0o644, noise.len(), noise.clone());
+    let afile = afile.compress_auto(&selector).unwrap();
+
+    assert_eq!(afile.codec(), EntryCodec { id: CODEC_NONE, level: 0, auto: true, filter: Filter::None, dictionary: false, padding: Padding::None });
+    assert_eq!(noise, afile.take_body());
+}
+
+#[test]
+fn decompress_output_limit() {
+    let compressor: Arc<dyn Compressor> = Arc::new(LZW::new());
+
+    let afile = ArchiveFile::from_file(&get_path(TEST_TEXT), Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap();
+    let size = afile.size();
+    let afile = afile.compress(compressor.clone()).unwrap();
+    let body = afile.take_body();
+
+    // the recorded size is the default limit
+    let afile = ArchiveFile::new("text.txt".to_string(), EntryCodec { id: CODEC_LZW, level: 6, auto: false, filter: Filter::None, dictionary: false, padding: Padding::None }, false, 0o644, size - 1, body.clone());
+    assert_eq!(afile.decompress(compressor.clone()).unwrap_err(), ArchiveError::OutputLimitExceeded);
+
+    let afile = ArchiveFile::new("text.txt".to_string(), EntryCodec { id: CODEC_LZW, level: 6, auto: false, filter: Filter::None, dictionary: false, padding: Padding::None }, false, 0o644, size, body);
+    let r = afile.decompress_limited(compressor.clone(), 1000);
+    assert_eq!(r.unwrap_err(), ArchiveError::OutputLimitExceeded);
+}
+
+#[test]
+fn filter_compress_res() {
+    let compressor: Arc<dyn Compressor> = Arc::new(LZ77::new());
+
+    // slowly growing 32 bit samples, like a raw sensor dump
+    let body: Vec<u8> = (0..50_000u32).flat_map(|i| (100_000 + i * 7 + i % 5).to_le_bytes()).collect();
+    let afile = ArchiveFile::new("samples.raw".to_string(), EntryCodec::default(), false, 0o644, body.len(), body.clone());
+    let plain_size = compressor.compress(&body).len();
+
+    let afile = afile.filter(Filter::Delta(4)).unwrap();
+    assert!(afile.is_filtered());
+    assert_eq!(afile.filter(Filter::X86).unwrap_err(), ArchiveError::FileAlreadyFiltered);
+
+    let afile = ArchiveFile::new("samples.raw".to_string(), EntryCodec::default(), false, 0o644, body.len(), body.clone());
+    let afile = afile.filter(Filter::Delta(4)).unwrap().compress(compressor.clone()).unwrap();
+    assert_eq!(afile.codec().filter, Filter::Delta(4));
+    assert!(afile.body_size() < plain_size);
+
+    let afile = afile.decompress(compressor.clone()).unwrap();
+    assert!(afile.is_filtered());
+
+    let afile = afile.unfilter().unwrap();
+    assert!(!afile.is_filtered());
+    assert_eq!(afile.take_body(), body);
+}
@@ -7,14 +7,27 @@ use crypto::{
     CipherProcessor,
     CipherBlock,
     threefish256::Cipher256,
-    cbc::CBCProcessor
+    cbc::CBCProcessor,
+    envelope::{Envelope, Identity},
+    keyslot::KeySlots,
+    signing::SigningKey,
+    CipherError
 };
 
 use compressor::{
     Compressor, 
-    lzw::LZW
+    CompressorOptions,
+    lzw::LZW,
+    lz77::LZ77,
+    bwt::Bwt,
+    registry::{Registry, CODEC_LZW}
 };
 
+use archiver::select::CodecSelector;
+use archiver::padding::Padding;
+use archiver::mode::CipherMode;
+use compressor::filter::Filter;
+
 use rand::Rng;
 
 const TEST_FOLDER: &str = "tests/static/";
@@ -26,6 +39,20 @@ fn get_path(path: &str) -> PathBuf {
     manifest.join(path)
 }
 
+fn get_tmp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rzip-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn assert_same_files(expected_dir: &Path, actual_dir: &Path) {
+    for rel_path in ["file1.bin", "folder1/file2.bin", "folder1/file3.txt", "text/file4.txt"] {
+        let expected = std::fs::read(expected_dir.join(rel_path)).unwrap();
+        let actual = std::fs::read(actual_dir.join(rel_path)).unwrap();
+        assert!(expected == actual, "{} differs after unzip", rel_path);
+    }
+}
+
 
 fn get_cbc_processor() -> CBCProcessor {
     let mut rng = rand::thread_rng();
@@ -67,3 +94,676 @@ fn zip_unzip_compress_result() {
     assert_eq!(without_errors, 4);
 }
 
+
+#[test]
+fn zip_unzip_codec_from_entry() {
+    let registry = Registry::new();
+    let n_workers = 4;
+
+    for name in registry.names() {
+        let compressor = registry.get_by_name(name, &CompressorOptions::with_level(1)).unwrap();
+
+        let target_path = get_path(TEST_FOLDER);
+        let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), None);
+
+        let output_path = get_tmp_path(&format!("{}.rz", name));
+        let without_errors = archiver.zip(&output_path).unwrap();
+        assert_eq!(without_errors, 4);
+
+        // the decompressor is picked from the codec id stored in each entry
+        let mut archiver = Archiver::new(&output_path, n_workers, None, None);
+
+        let output_dir = get_tmp_path(&format!("{}-unzip", name));
+        let without_errors = archiver.unzip(&output_dir).unwrap();
+        assert_eq!(without_errors, 4);
+
+        assert_same_files(&target_path, &output_dir);
+    }
+}
+
+#[test]
+fn unzip_unknown_codec() {
+    let compressor: Arc<dyn Compressor> = Arc::new(LZW::new());
+    let n_workers = 4;
+
+    let target_path = get_path(TEST_FOLDER);
+    let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), None);
+
+    let output_path = get_tmp_path("unknown_codec.rz");
+    archiver.zip(&output_path).unwrap();
+
+    let registry = Arc::new(Registry::empty());
+    let mut archiver = Archiver::new(&output_path, n_workers, None, None).with_registry(registry);
+
+    let output_dir = get_tmp_path("unknown_codec-unzip");
+    let r = archiver.unzip(&output_dir);
+    assert_eq!(r.unwrap_err(), ArchiveError::UnknownCodec);
+}
+
+#[test]
+fn zip_unzip_auto_codec() {
+    let selector = Arc::new(CodecSelector::new(Arc::new(LZ77::with_level(1)), Arc::new(Bwt::new())));
+    let n_workers = 4;
+
+    let target_path = get_path(TEST_FOLDER);
+    let mut archiver = Archiver::new(&target_path, n_workers, None, None).with_selector(selector);
+
+    let output_path = get_tmp_path("auto.rz");
+    let without_errors = archiver.zip(&output_path).unwrap();
+    assert_eq!(without_errors, 4);
+
+    let archiver = Archiver::new(&output_path, n_workers, None, None);
+    let entries = archiver.list().unwrap();
+    assert_eq!(entries.len(), 4);
+    assert!(entries.iter().all(|entry| entry.codec.auto));
+
+    let mut archiver = Archiver::new(&output_path, n_workers, None, None);
+    let output_dir = get_tmp_path("auto-unzip");
+    let without_errors = archiver.unzip(&output_dir).unwrap();
+    assert_eq!(without_errors, 4);
+
+    assert_same_files(&target_path, &output_dir);
+}
+
+#[test]
+fn list_entries() {
+    let compressor: Arc<dyn Compressor> = Arc::new(LZW::new());
+    let n_workers = 4;
+
+    let target_path = get_path(TEST_FOLDER);
+    let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), None);
+
+    let output_path = get_tmp_path("list.rz");
+    archiver.zip(&output_path).unwrap();
+
+    let archiver = Archiver::new(&output_path, n_workers, None, None);
+    let mut entries = archiver.list().unwrap();
+    entries.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+
+    let paths: Vec<&str> = entries.iter().map(|entry| entry.rel_path.as_str()).collect();
+    assert_eq!(paths, vec!["file1.bin", "folder1/file2.bin", "folder1/file3.txt", "text/file4.txt"]);
+
+    for entry in entries {
+        let size = std::fs::metadata(target_path.join(&entry.rel_path)).unwrap().len() as usize;
+        assert_eq!(entry.size, size);
+        assert_eq!(entry.codec.id, CODEC_LZW);
+        assert!(!entry.encrypted);
+    }
+}
+
+#[test]
+fn load_corrupted_header() {
+    let compressor: Arc<dyn Compressor> = Arc::new(LZW::new());
+    let n_workers = 4;
+
+    let target_path = get_path(TEST_FOLDER);
+    let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), None);
+
+    let output_path = get_tmp_path("corrupted.rz");
+    archiver.zip(&output_path).unwrap();
+    let data = std::fs::read(&output_path).unwrap();
+
+    // header offsets: files count at 8, first entry size at 32, zip_size at 40, name_length at 56
+    for (offset, value) in [(8, u64::MAX), (32, u64::MAX), (40, 1 << 40), (56, 1 << 40)] {
+        let mut corrupted = data.clone();
+        corrupted[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+
+        let corrupted_path = get_tmp_path(&format!("corrupted-{}.rz", offset));
+        std::fs::write(&corrupted_path, &corrupted).unwrap();
+
+        let archiver = Archiver::new(&corrupted_path, n_workers, None, None);
+        assert_eq!(archiver.list().unwrap_err(), ArchiveError::CorruptedHeader, "offset {}", offset);
+
+        let mut archiver = Archiver::new(&corrupted_path, n_workers, None, None);
+        let r = archiver.unzip(&get_tmp_path("corrupted-unzip"));
+        assert_eq!(r.unwrap_err(), ArchiveError::CorruptedHeader, "offset {}", offset);
+    }
+}
+
+#[test]
+fn unzip_max_entry_size() {
+    let compressor: Arc<dyn Compressor> = Arc::new(LZW::new());
+    let n_workers = 4;
+
+    let target_path = get_path(TEST_FOLDER);
+    let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), None);
+
+    let output_path = get_tmp_path("max_entry_size.rz");
+    archiver.zip(&output_path).unwrap();
+
+    let mut archiver = Archiver::new(&output_path, n_workers, None, None).with_max_entry_size(1024);
+    let r = archiver.unzip(&get_tmp_path("max_entry_size-unzip"));
+    assert_eq!(r.unwrap_err(), ArchiveError::CorruptedHeader);
+}
+
+#[test]
+fn zip_unzip_filter() {
+    let n_workers = 4;
+
+    for filter in [Filter::Delta(2), Filter::X86, Filter::Arm64] {
+        let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+
+        let target_path = get_path(TEST_FOLDER);
+        let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), None).with_filter(filter);
+
+        let output_path = get_tmp_path(&format!("filter-{}.rz", filter));
+        archiver.zip(&output_path).unwrap();
+
+        let archiver = Archiver::new(&output_path, n_workers, None, None);
+        assert!(archiver.list().unwrap().iter().all(|entry| entry.codec.filter == filter));
+
+        let mut archiver = Archiver::new(&output_path, n_workers, None, None);
+        let output_dir = get_tmp_path(&format!("filter-{}-unzip", filter));
+        let without_errors = archiver.unzip(&output_dir).unwrap();
+        assert_eq!(without_errors, 4);
+
+        assert_same_files(&target_path, &output_dir);
+    }
+}
+
+fn create_json_folder(name: &str, n: usize) -> PathBuf {
+    let mut rng = rand::thread_rng();
+    let dir = get_tmp_path(name);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for i in 0..n {
+        let record = format!(
+            "{{\"id\": {}, \"name\": \"sensor-{}\", \"location\": {{\"lat\": {}, \"lon\": {}}}, \"enabled\": true}}",
+            i, rng.gen_range(0..1000), rng.gen_range(-90..90), rng.gen_range(-180..180),
+        );
+        std::fs::write(dir.join(format!("{}.json", i)), record).unwrap();
+    }
+
+    dir
+}
+
+#[test]
+fn zip_unzip_dictionary() {
+    let n_workers = 4;
+    let target_path = create_json_folder("json", 200);
+
+    for encrypt in [false, true] {
+        let compressor: Arc<dyn Compressor> = Arc::new(LZW::new());
+        let processor: Option<Arc<dyn CipherProcessor>> = if encrypt { Some(Arc::new(get_cbc_processor())) } else { None };
+
+        let archiver = Archiver::new(&target_path, n_workers, Some(compressor.clone()), processor.clone());
+        let dictionary = archiver.train_dictionary(4096).unwrap();
+        assert!(!dictionary.is_empty());
+
+        let mut archiver = archiver.with_dictionary(Arc::new(dictionary));
+        let output_path = get_tmp_path(&format!("dictionary-{}.rz", encrypt));
+        assert_eq!(archiver.zip(&output_path).unwrap(), 200);
+
+        let mut plain = Archiver::new(&target_path, n_workers, Some(compressor), processor.clone());
+        let plain_path = get_tmp_path(&format!("no-dictionary-{}.rz", encrypt));
+        plain.zip(&plain_path).unwrap();
+
+        let size = std::fs::metadata(&output_path).unwrap().len();
+        let plain_size = std::fs::metadata(&plain_path).unwrap().len();
+        assert!(size < plain_size, "{} >= {}", size, plain_size);
+
+        let archiver = Archiver::new(&output_path, n_workers, None, None);
+        let entries = archiver.list().unwrap();
+        assert_eq!(entries.len(), 200);
+        assert!(entries.iter().all(|entry| entry.codec.dictionary));
+
+        let mut archiver = Archiver::new(&output_path, n_workers, None, processor);
+        let output_dir = get_tmp_path(&format!("dictionary-{}-unzip", encrypt));
+        assert_eq!(archiver.unzip(&output_dir).unwrap(), 200);
+
+        for i in 0..200 {
+            let name = format!("{}.json", i);
+            assert_eq!(std::fs::read(target_path.join(&name)).unwrap(), std::fs::read(output_dir.join(&name)).unwrap());
+        }
+    }
+}
+
+#[test]
+fn zip_unzip_recipients() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+
+    let content_key: Vec<u8> = (0..32).map(|_| rand::thread_rng().gen()).collect();
+    let processor = |key: &[u8]| -> Arc<dyn CipherProcessor> {
+        let block: Arc<dyn CipherBlock> = Arc::new(Cipher256::new(key, &[0; 16]).unwrap());
+        Arc::new(CBCProcessor::new(block, &[0; 32]).unwrap())
+    };
+
+    let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+    let envelope = Envelope::seal(&content_key, &[alice.recipient(), bob.recipient()]);
+    let archiver = Archiver::new(&target_path, n_workers, Some(compressor), Some(processor(&content_key)));
+    let dictionary = archiver.train_dictionary(4096).unwrap();
+    let mut archiver = archiver.with_dictionary(Arc::new(dictionary)).with_envelope(envelope.clone());
+
+    let output_path = get_tmp_path("recipients.rz");
+    assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+
+    // the envelope is readable and skipped without a key
+    let archiver = Archiver::new(&output_path, n_workers, None, None);
+    assert_eq!(archiver.load_envelope().unwrap(), Some(envelope));
+    assert!(!archiver.has_skein_kdf().unwrap());
+    assert_eq!(archiver.list().unwrap().len(), 4);
+
+    let loaded = archiver.load_envelope().unwrap().unwrap();
+    assert_eq!(loaded.open(&Identity::generate()).unwrap_err(), CipherError::RecipientNotFound);
+    let key = loaded.open(&bob).unwrap();
+
+    let mut archiver = Archiver::new(&output_path, n_workers, None, Some(processor(&key)));
+    let output_dir = get_tmp_path("recipients-unzip");
+    assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+    assert_same_files(&target_path, &output_dir);
+
+    // archives without recipients have no envelope
+    let output_path = get_tmp_path("no-recipients.rz");
+    Archiver::new(&target_path, n_workers, None, None).zip(&output_path).unwrap();
+    assert_eq!(Archiver::new(&output_path, n_workers, None, None).load_envelope().unwrap(), None);
+}
+
+#[test]
+fn zip_unzip_key_slots() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+    let alice = Identity::generate();
+
+    let content_key: Vec<u8> = (0..32).map(|_| rand::thread_rng().gen()).collect();
+    let processor = |key: &[u8]| -> Arc<dyn CipherProcessor> {
+        let block: Arc<dyn CipherBlock> = Arc::new(Cipher256::new(key, &[0; 16]).unwrap());
+        Arc::new(CBCProcessor::new(block, &[0; 32]).unwrap())
+    };
+
+    let mut key_slots = KeySlots::new(32);
+    key_slots.add(&content_key, b"first", 2).unwrap();
+
+    // key slots follow the envelope when an archive has both
+    let envelope = Envelope::seal(&content_key, &[alice.recipient()]);
+    let mut archiver = Archiver::new(&target_path, n_workers, None, Some(processor(&content_key)))
+        .with_envelope(envelope)
+        .with_key_slots(key_slots)
+        .with_skein_kdf();
+    let output_path = get_tmp_path("key-slots.rz");
+    assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+    let archive_size = std::fs::metadata(&output_path).unwrap().len();
+
+    let archiver = Archiver::new(&output_path, n_workers, None, None);
+    assert!(archiver.has_skein_kdf().unwrap());
+    assert_eq!(archiver.add_key_slot(b"second", b"third", 2).unwrap_err(), ArchiveError::DecryptError);
+    assert_eq!(archiver.add_key_slot(b"first", b"second", 2).unwrap(), 1);
+    assert_eq!(archiver.list_key_slots().unwrap(), vec![(0, 2), (1, 2)]);
+
+    assert_eq!(archiver.remove_key_slot(b"second", 5).unwrap_err(), ArchiveError::InvalidKeySlot);
+    archiver.remove_key_slot(b"second", 0).unwrap();
+    assert_eq!(archiver.remove_key_slot(b"second", 1).unwrap_err(), ArchiveError::LastKeySlot);
+    assert_eq!(archiver.list_key_slots().unwrap(), vec![(1, 2)]);
+
+    // the slots are rewritten in place, the entries stay as they were
+    assert_eq!(std::fs::metadata(&output_path).unwrap().len(), archive_size);
+    assert_eq!(archiver.list().unwrap().len(), 4);
+
+    let key_slots = archiver.load_key_slots().unwrap().unwrap();
+    assert!(key_slots.open(b"first").is_err());
+    let (_, key) = key_slots.open(b"second").unwrap();
+    assert_eq!(*archiver.load_envelope().unwrap().unwrap().open(&alice).unwrap(), *key);
+
+    let mut archiver = Archiver::new(&output_path, n_workers, None, Some(processor(&key)));
+    let output_dir = get_tmp_path("key-slots-unzip");
+    assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+    assert_same_files(&target_path, &output_dir);
+
+    // new slots would invalidate the signature
+    let publisher = SigningKey::generate();
+    archiver.sign(&publisher).unwrap();
+    assert_eq!(archiver.add_key_slot(b"second", b"third", 2).unwrap_err(), ArchiveError::SignedArchive);
+    assert_eq!(archiver.verify_signature(&publisher.verifying_key()), Ok(()));
+
+    // archives without key slots have nothing to manage
+    let output_path = get_tmp_path("no-key-slots.rz");
+    Archiver::new(&target_path, n_workers, None, None).zip(&output_path).unwrap();
+    let archiver = Archiver::new(&output_path, n_workers, None, None);
+    assert_eq!(archiver.load_key_slots().unwrap(), None);
+    assert_eq!(archiver.list_key_slots().unwrap_err(), ArchiveError::NoKeySlots);
+    assert_eq!(archiver.store_key_slots(&KeySlots::new(32)).unwrap_err(), ArchiveError::NoKeySlots);
+}
+
+#[test]
+fn rekey() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+
+    for solid in [false, true] {
+        let old_processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+        let new_processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+
+        let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+        let archiver = Archiver::new(&target_path, n_workers, Some(compressor), Some(old_processor.clone()));
+        let dictionary = archiver.train_dictionary(4096).unwrap();
+        let mut archiver = archiver.with_dictionary(Arc::new(dictionary));
+        if solid {
+            archiver = archiver.with_solid(4 << 20);
+        }
+
+        let output_path = get_tmp_path(&format!("rekey-{}.rz", solid));
+        assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+
+        let rekeyed_path = get_tmp_path(&format!("rekeyed-{}.rz", solid));
+        let archiver = Archiver::new(&output_path, n_workers, None, Some(old_processor.clone()));
+        assert_eq!(archiver.rekey(&rekeyed_path, new_processor.clone()).unwrap(), 4);
+
+        // entries keep their metadata, only the key changes
+        let before = Archiver::new(&output_path, n_workers, None, None).list().unwrap();
+        let after = Archiver::new(&rekeyed_path, n_workers, None, None).list().unwrap();
+        assert_eq!(before, after);
+
+        let mut archiver = Archiver::new(&rekeyed_path, n_workers, None, Some(new_processor));
+        let output_dir = get_tmp_path(&format!("rekeyed-{}-unzip", solid));
+        assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+        assert_same_files(&target_path, &output_dir);
+
+        let mut archiver = Archiver::new(&rekeyed_path, n_workers, None, Some(old_processor));
+        assert!(archiver.unzip(&get_tmp_path(&format!("rekeyed-{}-old", solid))).is_err());
+    }
+
+    // there is nothing to rekey in archives without encryption
+    let output_path = get_tmp_path("rekey-plain.rz");
+    Archiver::new(&target_path, n_workers, None, None).zip(&output_path).unwrap();
+    let processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+    let archiver = Archiver::new(&output_path, n_workers, None, Some(processor.clone()));
+    assert_eq!(archiver.rekey(&get_tmp_path("rekeyed-plain.rz"), processor.clone()).unwrap_err(), ArchiveError::FileAlreadyDecrypted);
+    let archiver = Archiver::new(&output_path, n_workers, None, None);
+    assert_eq!(archiver.rekey(&get_tmp_path("rekeyed-plain.rz"), processor).unwrap_err(), ArchiveError::DecryptError);
+}
+
+#[test]
+fn zip_unzip_cipher_mode() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+    let processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+
+    let output_path = get_tmp_path("cipher-mode.rz");
+    let mut archiver = Archiver::new(&target_path, n_workers, None, Some(processor.clone()))
+        .with_cipher_mode(CipherMode::Cbc);
+    assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+    assert_eq!(Archiver::new(&output_path, n_workers, None, None).load_cipher_mode().unwrap(), Some(CipherMode::Cbc));
+
+    // the mode survives a rekey
+    let rekeyed_path = get_tmp_path("cipher-mode-rekeyed.rz");
+    let new_processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+    Archiver::new(&output_path, n_workers, None, Some(processor)).rekey(&rekeyed_path, new_processor.clone()).unwrap();
+    assert_eq!(Archiver::new(&rekeyed_path, n_workers, None, None).load_cipher_mode().unwrap(), Some(CipherMode::Cbc));
+
+    let mut archiver = Archiver::new(&rekeyed_path, n_workers, None, Some(new_processor));
+    let output_dir = get_tmp_path("cipher-mode-unzip");
+    assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+    assert_same_files(&target_path, &output_dir);
+
+    // there is no mode without encryption
+    let plain_path = get_tmp_path("cipher-mode-plain.rz");
+    Archiver::new(&target_path, n_workers, None, None).with_cipher_mode(CipherMode::Xts).zip(&plain_path).unwrap();
+    assert_eq!(Archiver::new(&plain_path, n_workers, None, None).load_cipher_mode().unwrap(), None);
+}
+
+#[test]
+fn sign_verify() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+    let publisher = SigningKey::generate();
+
+    for encrypt in [false, true] {
+        let processor: Option<Arc<dyn CipherProcessor>> = if encrypt { Some(Arc::new(get_cbc_processor())) } else { None };
+        let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+        let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), processor.clone());
+
+        let output_path = get_tmp_path(&format!("signed-{}.rz", encrypt));
+        assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+        let unsigned_size = std::fs::metadata(&output_path).unwrap().len();
+
+        let archiver = Archiver::new(&output_path, n_workers, None, processor.clone());
+        assert_eq!(archiver.verify_signature(&publisher.verifying_key()).unwrap_err(), ArchiveError::NotSigned);
+
+        archiver.sign(&publisher).unwrap();
+        assert_eq!(archiver.verify_signature(&publisher.verifying_key()), Ok(()));
+        assert_eq!(archiver.verify_signature(&SigningKey::generate().verifying_key()).unwrap_err(), ArchiveError::InvalidSignature);
+
+        // signing again replaces the trailer
+        archiver.sign(&publisher).unwrap();
+        let signed_size = std::fs::metadata(&output_path).unwrap().len();
+        assert_eq!(signed_size, unsigned_size + 72);
+
+        // the trailer is ignored when reading the entries
+        assert_eq!(archiver.list().unwrap().len(), 4);
+        let mut archiver = Archiver::new(&output_path, n_workers, None, processor);
+        let output_dir = get_tmp_path(&format!("signed-{}-unzip", encrypt));
+        assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+        assert_same_files(&target_path, &output_dir);
+
+        let mut content = std::fs::read(&output_path).unwrap();
+        content[signed_size as usize / 2] ^= 1;
+        std::fs::write(&output_path, &content).unwrap();
+        assert_eq!(archiver.verify_signature(&publisher.verifying_key()).unwrap_err(), ArchiveError::InvalidSignature);
+    }
+}
+
+#[test]
+fn sign_archive_ending_with_magick() {
+    let n_workers = 4;
+    let publisher = SigningKey::generate();
+
+    // stored without compression, the archive ends like the file, which ends like a signature trailer
+    let target_path = get_tmp_path("trailer-like");
+    std::fs::create_dir_all(&target_path).unwrap();
+    let mut content = vec![7u8; 100];
+    content.extend_from_slice(&[0x52, 0x5a, 0x53, 0x49, 0x47, 0x4e, 0x0, 0x1]);
+    std::fs::write(target_path.join("file.bin"), &content).unwrap();
+
+    let output_path = get_tmp_path("trailer-like.rz");
+    assert_eq!(Archiver::new(&target_path, n_workers, None, None).zip(&output_path).unwrap(), 1);
+    let unsigned_size = std::fs::metadata(&output_path).unwrap().len();
+    assert!(std::fs::read(&output_path).unwrap().ends_with(&content));
+
+    let archiver = Archiver::new(&output_path, n_workers, None, None);
+    assert_eq!(archiver.verify_signature(&publisher.verifying_key()).unwrap_err(), ArchiveError::NotSigned);
+    archiver.sign(&publisher).unwrap();
+    assert_eq!(std::fs::metadata(&output_path).unwrap().len(), unsigned_size + 72);
+    assert_eq!(archiver.verify_signature(&publisher.verifying_key()), Ok(()));
+
+    let mut archiver = Archiver::new(&output_path, n_workers, None, None);
+    let output_dir = get_tmp_path("trailer-like-unzip");
+    assert_eq!(archiver.unzip(&output_dir).unwrap(), 1);
+    assert_eq!(std::fs::read(output_dir.join("file.bin")).unwrap(), content);
+}
+
+#[test]
+fn zip_unzip_encrypted_headers() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+
+    for solid in [false, true] {
+        let processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+        let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+        let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), Some(processor.clone())).with_encrypted_headers();
+        if solid {
+            archiver = archiver.with_solid(4 << 20);
+        }
+
+        let output_path = get_tmp_path(&format!("encrypted-headers-{}.rz", solid));
+        assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+
+        // names are not stored in clear
+        let content = std::fs::read(&output_path).unwrap();
+        assert!(!content.windows(9).any(|w| w == b"file4.txt"));
+
+        assert_eq!(Archiver::new(&output_path, n_workers, None, None).list().unwrap_err(), ArchiveError::EncryptedHeaders);
+        assert!(Archiver::new(&output_path, n_workers, None, Some(Arc::new(get_cbc_processor()))).list().is_err());
+
+        let archiver = Archiver::new(&output_path, n_workers, None, Some(processor.clone()));
+        let mut entries: Vec<String> = archiver.list().unwrap().into_iter().map(|e| e.rel_path).collect();
+        entries.sort();
+        assert_eq!(entries, ["file1.bin", "folder1/file2.bin", "folder1/file3.txt", "text/file4.txt"]);
+
+        let output_dir = get_tmp_path(&format!("encrypted-headers-{}-extract", solid));
+        archiver.extract("text/file4.txt", &output_dir).unwrap();
+        assert_eq!(std::fs::read(output_dir.join("text/file4.txt")).unwrap(), std::fs::read(target_path.join("text/file4.txt")).unwrap());
+
+        // rekeyed archives keep their headers encrypted
+        let new_processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+        let rekeyed_path = get_tmp_path(&format!("encrypted-headers-{}-rekeyed.rz", solid));
+        archiver.rekey(&rekeyed_path, new_processor.clone()).unwrap();
+        assert_eq!(Archiver::new(&rekeyed_path, n_workers, None, None).list().unwrap_err(), ArchiveError::EncryptedHeaders);
+
+        let mut archiver = Archiver::new(&rekeyed_path, n_workers, None, Some(new_processor));
+        let output_dir = get_tmp_path(&format!("encrypted-headers-{}-unzip", solid));
+        assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+        assert_same_files(&target_path, &output_dir);
+    }
+}
+
+#[test]
+fn zip_unzip_padding() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+
+    for solid in [false, true] {
+        let processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+        let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+        let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), Some(processor.clone())).with_padding(Padding::PowerOfTwo);
+        if solid {
+            archiver = archiver.with_solid(4 << 20);
+        }
+
+        let output_path = get_tmp_path(&format!("padded-{}.rz", solid));
+        assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+
+        let entries = Archiver::new(&output_path, n_workers, None, None).list().unwrap();
+        assert!(entries.iter().all(|e| e.codec.padding == Padding::PowerOfTwo));
+        if !solid {
+            // a power of two, then the IV and the CBC padding block
+            assert!(entries.iter().all(|e| (e.zip_size - 64).is_power_of_two()));
+        }
+
+        // rekeying keeps the padding
+        let new_processor: Arc<dyn CipherProcessor> = Arc::new(get_cbc_processor());
+        let rekeyed_path = get_tmp_path(&format!("padded-{}-rekeyed.rz", solid));
+        Archiver::new(&output_path, n_workers, None, Some(processor)).rekey(&rekeyed_path, new_processor.clone()).unwrap();
+        assert_eq!(Archiver::new(&rekeyed_path, n_workers, None, None).list().unwrap(), entries);
+
+        let mut archiver = Archiver::new(&rekeyed_path, n_workers, None, Some(new_processor));
+        let output_dir = get_tmp_path(&format!("padded-{}-unzip", solid));
+        assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+        assert_same_files(&target_path, &output_dir);
+    }
+}
+
+#[test]
+fn zip_unzip_solid() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+
+    for encrypt in [false, true] {
+        let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+        let processor: Option<Arc<dyn CipherProcessor>> = if encrypt { Some(Arc::new(get_cbc_processor())) } else { None };
+
+        let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), processor.clone()).with_solid(4 << 20);
+        let output_path = get_tmp_path(&format!("solid-{}.rz", encrypt));
+        assert_eq!(archiver.zip(&output_path).unwrap(), 4);
+
+        // bin and txt files end up in one block each
+        let archiver = Archiver::new(&output_path, n_workers, None, None);
+        let mut entries = archiver.list().unwrap();
+        entries.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].block, entries[1].block);
+        assert_eq!(entries[2].block, entries[3].block);
+        assert_ne!(entries[0].block, entries[2].block);
+        assert!(entries.iter().all(|entry| entry.encrypted == encrypt && entry.zip_size == 0));
+
+        let mut archiver = Archiver::new(&output_path, n_workers, None, processor);
+        let output_dir = get_tmp_path(&format!("solid-{}-unzip", encrypt));
+        assert_eq!(archiver.unzip(&output_dir).unwrap(), 4);
+
+        assert_same_files(&target_path, &output_dir);
+    }
+}
+
+#[test]
+fn extract_entry() {
+    let n_workers = 4;
+    let target_path = get_path(TEST_FOLDER);
+
+    for solid in [false, true] {
+        let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+        let archiver = Archiver::new(&target_path, n_workers, Some(compressor), None);
+        let mut archiver = if solid { archiver.with_solid(1 << 20) } else { archiver };
+
+        let output_path = get_tmp_path(&format!("extract-{}.rz", solid));
+        archiver.zip(&output_path).unwrap();
+
+        let archiver = Archiver::new(&output_path, n_workers, None, None);
+        let output_dir = get_tmp_path(&format!("extract-{}-unzip", solid));
+        archiver.extract("folder1/file3.txt", &output_dir).unwrap();
+
+        let expected = std::fs::read(target_path.join("folder1/file3.txt")).unwrap();
+        assert_eq!(std::fs::read(output_dir.join("folder1/file3.txt")).unwrap(), expected);
+        assert!(!output_dir.join("text/file4.txt").exists());
+
+        assert_eq!(archiver.extract("missing.txt", &output_dir).unwrap_err(), ArchiveError::EntryNotFound);
+    }
+}
+
+#[test]
+fn solid_smaller_than_separate() {
+    let n_workers = 4;
+    let target_path = create_json_folder("json-solid", 200);
+
+    let compressor: Arc<dyn Compressor> = Arc::new(LZ77::new());
+    let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor.clone()), None).with_solid(1 << 20);
+    let output_path = get_tmp_path("json-solid.rz");
+    assert_eq!(archiver.zip(&output_path).unwrap(), 200);
+
+    let mut separate = Archiver::new(&target_path, n_workers, Some(compressor), None);
+    let separate_path = get_tmp_path("json-separate.rz");
+    separate.zip(&separate_path).unwrap();
+
+    let size = std::fs::metadata(&output_path).unwrap().len();
+    let separate_size = std::fs::metadata(&separate_path).unwrap().len();
+    assert!(size * 2 < separate_size, "{} >= {} / 2", size, separate_size);
+
+    let mut archiver = Archiver::new(&output_path, n_workers, None, None);
+    let output_dir = get_tmp_path("json-solid-unzip");
+    assert_eq!(archiver.unzip(&output_dir).unwrap(), 200);
+
+    for i in 0..200 {
+        let name = format!("{}.json", i);
+        assert_eq!(std::fs::read(target_path.join(&name)).unwrap(), std::fs::read(output_dir.join(&name)).unwrap());
+    }
+}
+
+#[test]
+fn load_corrupted_solid_header() {
+    let compressor: Arc<dyn Compressor> = Arc::new(LZ77::with_level(1));
+    let n_workers = 4;
+
+    let target_path = get_path(TEST_FOLDER);
+    let mut archiver = Archiver::new(&target_path, n_workers, Some(compressor), None).with_solid(4 << 20);
+
+    let output_path = get_tmp_path("corrupted-solid.rz");
+    archiver.zip(&output_path).unwrap();
+    let data = std::fs::read(&output_path).unwrap();
+
+    // blocks count at 24, first block: members count at 32, size at 48, zip_size at 56,
+    // first member: size at 72, offset at 80, name_length at 88
+    for (offset, value) in [(24, u64::MAX), (32, u64::MAX), (48, u64::MAX), (56, 1 << 40), (72, 1 << 40), (80, 1 << 40), (88, 1 << 40)] {
+        let mut corrupted = data.clone();
+        corrupted[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+
+        let corrupted_path = get_tmp_path(&format!("corrupted-solid-{}.rz", offset));
+        std::fs::write(&corrupted_path, &corrupted).unwrap();
+
+        let archiver = Archiver::new(&corrupted_path, n_workers, None, None);
+        assert_eq!(archiver.list().unwrap_err(), ArchiveError::CorruptedHeader, "offset {}", offset);
+
+        let mut archiver = Archiver::new(&corrupted_path, n_workers, None, None);
+        let r = archiver.unzip(&get_tmp_path("corrupted-solid-unzip"));
+        assert_eq!(r.unwrap_err(), ArchiveError::CorruptedHeader, "offset {}", offset);
+    }
+}
@@ -0,0 +1,154 @@
+use super::CompressorError;
+
+/// BitWriter packs values into bytes, most significant bit first.
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    buffer: Vec<u8>,
+    acc: u64,
+    filled: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter::default()
+    }
+
+    /// Creates a writer which appends bits after the bytes already stored in `buffer`.
+    pub fn with_buffer(buffer: Vec<u8>) -> BitWriter {
+        BitWriter { buffer, acc: 0, filled: 0 }
+    }
+
+    /// Writes the `count` lowest bits of `value`. `count` must not exceed 32.
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        assert!(count <= 32, "Can't write more than 32 bits at once");
+        if count == 0 {
+            return;
+        }
+
+        let mask = (1u64 << count) - 1;
+        self.acc = (self.acc << count) | (value as u64 & mask);
+        self.filled += count;
+
+        while self.filled >= 8 {
+            self.filled -= 8;
+            self.buffer.push((self.acc >> self.filled) as u8);
+        }
+        self.acc &= (1u64 << self.filled) - 1;
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(bit as u32, 1);
+    }
+
+    /// Number of bits written so far.
+    pub fn len(&self) -> usize {
+        self.buffer.len() * 8 + self.filled as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Flushes the last partial byte (padded with zeros) and returns the buffer.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.filled > 0 {
+            self.buffer.push((self.acc << (8 - self.filled)) as u8);
+        }
+        self.buffer
+    }
+}
+
+/// BitReader reads values written by [`BitWriter`].
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    src: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(src: &'a [u8]) -> BitReader<'a> {
+        BitReader { src, pos: 0, bit: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Result<u32, CompressorError> {
+        if self.pos >= self.src.len() {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+
+        let bit = (self.src[self.pos] >> (7 - self.bit)) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+
+        Ok(bit as u32)
+    }
+
+    /// Reads `count` bits (at most 32) and returns them as the lowest bits of the result.
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, CompressorError> {
+        assert!(count <= 32, "Can't read more than 32 bits at once");
+
+        let mut value: u64 = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+
+        Ok(value as u32)
+    }
+
+    /// Skips the rest of the current byte.
+    pub fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    /// Position of the next unread byte, counting a partially read byte as consumed.
+    pub fn byte_position(&self) -> usize {
+        if self.bit == 0 { self.pos } else { self.pos + 1 }
+    }
+}
+
+
+#[cfg(test)]
+mod bits_test {
+    use super::*;
+
+    #[test]
+    fn write_res() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0b11110000, 8);
+        writer.write_bit(true);
+
+        assert_eq!(writer.len(), 12);
+        assert_eq!(writer.finish(), vec![0b10111110, 0b00010000]);
+    }
+
+    #[test]
+    fn read_write_same_value() {
+        let mut writer = BitWriter::new();
+        let values: Vec<(u32, u32)> = (1..=32).map(|n| (0x9e3779b9u32 >> (32 - n), n)).collect();
+        for &(value, count) in &values {
+            writer.write_bits(value, count);
+        }
+
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        for &(value, count) in &values {
+            assert_eq!(reader.read_bits(count).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn read_errors() {
+        let data = vec![0xffu8];
+        let mut reader = BitReader::new(&data);
+
+        assert_eq!(reader.read_bits(8).unwrap(), 0xff);
+        assert_eq!(reader.read_bit().unwrap_err(), CompressorError::IncorrectSrcValue);
+    }
+}
@@ -0,0 +1,526 @@
+use super::bits::{BitReader, BitWriter};
+use super::huffman::{HuffmanDecoder, HuffmanEncoder};
+use super::CompressorError;
+use super::Compressor;
+use super::CompressorOptions;
+use super::check_output_size;
+use super::registry::CODEC_BWT;
+
+use std::thread;
+
+const BWT_MARKER: u8 = 0xf3;
+const STORED_MARKER: u8 = 0xff;
+
+pub const MIN_BLOCK_SIZE: usize = 1 << 10;
+pub const MAX_BLOCK_SIZE: usize = 1 << 23;
+pub const DEFAULT_BLOCK_SIZE: usize = 900 * 1000;
+
+// symbols of the entropy stage: zero runs are written in bijective base 2
+// with RUNA/RUNB, other MTF values are shifted by one
+const RUNA: usize = 0;
+const RUNB: usize = 1;
+const EOB: usize = 257;
+const ALPHABET_SIZE: usize = 258;
+
+/// Bwt is a block-sorting compressor: Burrows–Wheeler transform, move-to-front,
+/// zero run-length encoding and a Huffman stage. Blocks are independent,
+/// so they are compressed and decompressed in parallel when threads > 1.
+#[derive(Debug)]
+pub struct Bwt {
+    block_size: usize,
+    threads: usize,
+}
+
+impl Bwt {
+    pub fn new() -> Bwt {
+        Bwt::with_block_size(DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Creates a compressor splitting input into blocks of `block_size` bytes,
+    /// clamped to [`MIN_BLOCK_SIZE`]..=[`MAX_BLOCK_SIZE`].
+    pub fn with_block_size(block_size: usize) -> Bwt {
+        Bwt {
+            block_size: block_size.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE),
+            threads: 1,
+        }
+    }
+
+    /// Sets the number of threads used to process blocks of a single stream.
+    pub fn with_threads(self, threads: usize) -> Bwt {
+        Bwt {
+            threads: threads.max(1),
+            ..self
+        }
+    }
+
+    /// Uses `options.block_size` if given, otherwise 100 kB per level as bzip2 does.
+    pub fn from_options(options: &CompressorOptions) -> Bwt {
+        let block_size = options.block_size.unwrap_or(options.level as usize * 100 * 1000);
+
+        Bwt::with_block_size(block_size).with_threads(options.threads)
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn compress(&self, src: &[u8]) -> Vec<u8> {
+        Compressor::compress(self, src)
+    }
+
+    pub fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        Compressor::decompress(self, src)
+    }
+}
+
+impl Default for Bwt {
+    fn default() -> Self {
+        Bwt::new()
+    }
+}
+
+impl Compressor for Bwt {
+    fn options(&self) -> CompressorOptions {
+        CompressorOptions {
+            block_size: Some(self.block_size),
+            threads: self.threads,
+            ..CompressorOptions::default()
+        }
+    }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_BWT
+    }
+
+    fn compress(&self, src: &[u8]) -> Vec<u8> {
+        if src.is_empty() {
+            return Vec::new();
+        }
+
+        let blocks: Vec<&[u8]> = src.chunks(self.block_size).collect();
+        let encoded = map_parallel(&blocks, self.threads, |block| encode_block(block));
+
+        let mut result = Vec::with_capacity(src.len() / 2);
+        result.push(BWT_MARKER);
+        result.extend_from_slice(&(self.block_size as u32).to_le_bytes());
+        result.extend_from_slice(&(src.len() as u64).to_le_bytes());
+        for block in encoded {
+            result.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            result.extend_from_slice(&block);
+        }
+
+        if result.len() > src.len() {
+            let mut dst: Vec<u8> = Vec::with_capacity(1 + src.len());
+            dst.push(STORED_MARKER);
+            dst.extend_from_slice(src);
+
+            return dst;
+        }
+
+        result
+    }
+
+    fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        self.decompress_limited(src, usize::MAX)
+    }
+
+    fn decompress_limited(&self, src: &[u8], max_size: usize) -> Result<Vec<u8>, CompressorError> {
+        if src.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if src[0] == STORED_MARKER {
+            check_output_size(src.len() - 1, max_size)?;
+            return Ok(src[1..].to_vec());
+        } else if src[0] != BWT_MARKER {
+            return Err(CompressorError::DecompressErrorWithCode);
+        }
+
+        if src.len() < 13 {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+        let block_size = u32::from_le_bytes(src[1..5].try_into().unwrap()) as usize;
+        let length = u64::from_le_bytes(src[5..13].try_into().unwrap()) as usize;
+        check_output_size(length, max_size)?;
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+
+        let mut blocks: Vec<&[u8]> = Vec::new();
+        let mut pos = 13;
+        while pos < src.len() {
+            if pos + 4 > src.len() {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            let block_len = u32::from_le_bytes(src[pos..pos+4].try_into().unwrap()) as usize;
+            pos += 4;
+
+            if pos + block_len > src.len() {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            blocks.push(&src[pos..pos+block_len]);
+            pos += block_len;
+        }
+
+        if blocks.len() != length.div_ceil(block_size) {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+
+        let decoded = map_parallel(&blocks, self.threads, |block| decode_block(block, block_size));
+
+        let mut result = Vec::with_capacity(length);
+        for block in decoded {
+            result.extend_from_slice(&block?);
+        }
+
+        if result.len() != length {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Applies `f` to every item, splitting the items between `threads` scoped threads.
+fn map_parallel<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if threads <= 1 || items.len() <= 1 {
+        return items.iter().map(&f).collect();
+    }
+
+    let chunk = items.len().div_ceil(threads);
+    thread::scope(|s| {
+        let handles: Vec<_> = items.chunks(chunk)
+            .map(|part| {
+                let f = &f;
+                s.spawn(move || part.iter().map(f).collect::<Vec<R>>())
+            })
+            .collect();
+
+        handles.into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+/// Sorts all cyclic rotations of `data` with prefix doubling and radix sort.
+/// Returns the start index of each rotation in sorted order.
+pub fn sort_rotations(data: &[u8]) -> Vec<usize> {
+    let n = data.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let mut count = vec![0usize; n.max(256)];
+    let mut p = vec![0usize; n];
+    let mut c = vec![0usize; n];
+
+    for &b in data {
+        count[b as usize] += 1;
+    }
+    for i in 1..256 {
+        count[i] += count[i - 1];
+    }
+    for i in (0..n).rev() {
+        count[data[i] as usize] -= 1;
+        p[count[data[i] as usize]] = i;
+    }
+
+    let mut classes = 1;
+    for i in 1..n {
+        if data[p[i]] != data[p[i - 1]] {
+            classes += 1;
+        }
+        c[p[i]] = classes - 1;
+    }
+
+    let mut pn = vec![0usize; n];
+    let mut cn = vec![0usize; n];
+    let mut shift = 1;
+    while shift < n && classes < n {
+        // rotations sorted by their second half give the order for the first half
+        for i in 0..n {
+            pn[i] = (p[i] + n - shift) % n;
+        }
+
+        count[..classes].iter_mut().for_each(|x| *x = 0);
+        for &i in &pn {
+            count[c[i]] += 1;
+        }
+        for i in 1..classes {
+            count[i] += count[i - 1];
+        }
+        for &i in pn.iter().rev() {
+            count[c[i]] -= 1;
+            p[count[c[i]]] = i;
+        }
+
+        cn[p[0]] = 0;
+        classes = 1;
+        for i in 1..n {
+            let cur = (c[p[i]], c[(p[i] + shift) % n]);
+            let prev = (c[p[i - 1]], c[(p[i - 1] + shift) % n]);
+            if cur != prev {
+                classes += 1;
+            }
+            cn[p[i]] = classes - 1;
+        }
+
+        std::mem::swap(&mut c, &mut cn);
+        shift <<= 1;
+    }
+
+    p
+}
+
+/// Forward transform. Returns the last column and the row of the original data.
+pub fn bwt_forward(data: &[u8]) -> (Vec<u8>, usize) {
+    let n = data.len();
+    let rotations = sort_rotations(data);
+
+    let mut primary = 0;
+    let last: Vec<u8> = rotations.iter()
+        .enumerate()
+        .map(|(row, &start)| {
+            if start == 0 {
+                primary = row;
+            }
+            data[(start + n - 1) % n]
+        })
+        .collect();
+
+    (last, primary)
+}
+
+pub fn bwt_inverse(last: &[u8], primary: usize) -> Result<Vec<u8>, CompressorError> {
+    let n = last.len();
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    if primary >= n {
+        return Err(CompressorError::IncorrectSrcValue);
+    }
+
+    let mut starts = [0usize; 256];
+    for &b in last {
+        starts[b as usize] += 1;
+    }
+    let mut sum = 0;
+    for s in starts.iter_mut() {
+        let count = *s;
+        *s = sum;
+        sum += count;
+    }
+
+    let mut next = vec![0usize; n];
+    for (i, &b) in last.iter().enumerate() {
+        next[starts[b as usize]] = i;
+        starts[b as usize] += 1;
+    }
+
+    let mut result = Vec::with_capacity(n);
+    let mut idx = next[primary];
+    for _ in 0..n {
+        result.push(last[idx]);
+        idx = next[idx];
+    }
+
+    Ok(result)
+}
+
+pub fn mtf_encode(data: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=255).collect();
+
+    data.iter()
+        .map(|&b| {
+            let pos = list.iter().position(|&x| x == b).unwrap();
+            list.copy_within(0..pos, 1);
+            list[0] = b;
+            pos as u8
+        })
+        .collect()
+}
+
+pub fn mtf_decode(data: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=255).collect();
+
+    data.iter()
+        .map(|&pos| {
+            let pos = pos as usize;
+            let b = list[pos];
+            list.copy_within(0..pos, 1);
+            list[0] = b;
+            b
+        })
+        .collect()
+}
+
+fn push_zero_run(symbols: &mut Vec<u16>, mut run: usize) {
+    while run > 0 {
+        run -= 1;
+        symbols.push(if run & 1 == 0 { RUNA as u16 } else { RUNB as u16 });
+        run >>= 1;
+    }
+}
+
+fn encode_block(block: &[u8]) -> Vec<u8> {
+    let (last, primary) = bwt_forward(block);
+    let mtf = mtf_encode(&last);
+
+    let mut symbols: Vec<u16> = Vec::with_capacity(mtf.len() + 1);
+    let mut run = 0;
+    for &value in &mtf {
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        push_zero_run(&mut symbols, run);
+        run = 0;
+        symbols.push(value as u16 + 1);
+    }
+    push_zero_run(&mut symbols, run);
+    symbols.push(EOB as u16);
+
+    let mut freqs = [0u32; ALPHABET_SIZE];
+    for &s in &symbols {
+        freqs[s as usize] += 1;
+    }
+    let encoder = HuffmanEncoder::from_frequencies(&freqs);
+
+    let mut writer = BitWriter::with_buffer((primary as u32).to_le_bytes().to_vec());
+    encoder.write_table(&mut writer);
+    for &s in &symbols {
+        encoder.encode(s as usize, &mut writer);
+    }
+
+    writer.finish()
+}
+
+fn decode_block(src: &[u8], block_size: usize) -> Result<Vec<u8>, CompressorError> {
+    if src.len() < 4 {
+        return Err(CompressorError::IncorrectSrcValue);
+    }
+    let primary = u32::from_le_bytes(src[..4].try_into().unwrap()) as usize;
+
+    let mut reader = BitReader::new(&src[4..]);
+    let decoder = HuffmanDecoder::read_table(&mut reader, ALPHABET_SIZE)?;
+
+    let mut mtf: Vec<u8> = Vec::new();
+    let mut run = 0;
+    let mut weight = 1;
+    loop {
+        let symbol = decoder.decode(&mut reader)?;
+
+        if symbol == RUNA || symbol == RUNB {
+            run += weight << symbol;
+            weight <<= 1;
+            if run > block_size {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            continue;
+        }
+
+        mtf.resize(mtf.len() + run, 0);
+        run = 0;
+        weight = 1;
+
+        if symbol == EOB {
+            break;
+        }
+        mtf.push((symbol - 1) as u8);
+
+        if mtf.len() > block_size {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+    }
+
+    if mtf.len() > block_size {
+        return Err(CompressorError::IncorrectSrcValue);
+    }
+
+    let last = mtf_decode(&mtf);
+    bwt_inverse(&last, primary)
+}
+
+
+#[cfg(test)]
+mod bwt_test {
+    use super::*;
+
+    #[test]
+    fn bwt_forward_res() {
+        let (last, primary) = bwt_forward(b"banana");
+
+        assert_eq!(last, b"nnbaaa");
+        assert_eq!(primary, 3);
+    }
+
+    #[test]
+    fn bwt_inverse_res() {
+        let data = bwt_inverse(b"nnbaaa", 3).unwrap();
+        assert_eq!(data, b"banana");
+
+        let r = bwt_inverse(b"nnbaaa", 6);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+    }
+
+    #[test]
+    fn sort_rotations_periodic() {
+        let data = b"abababab";
+        let (last, primary) = bwt_forward(data);
+
+        assert_eq!(sort_rotations(b"aaaa").len(), 4);
+        assert_eq!(bwt_inverse(&last, primary).unwrap(), data);
+    }
+
+    #[test]
+    fn mtf_res() {
+        let encoded = mtf_encode(b"bananaaa");
+        assert_eq!(encoded, vec![98, 98, 110, 1, 1, 1, 0, 0]);
+        assert_eq!(mtf_decode(&encoded), b"bananaaa");
+    }
+
+    #[test]
+    fn zero_runs_res() {
+        for run in 1..40 {
+            let mut symbols = Vec::new();
+            push_zero_run(&mut symbols, run);
+
+            let decoded: usize = symbols.iter()
+                .enumerate()
+                .map(|(i, &s)| 1 << (i + s as usize))
+                .sum();
+            assert_eq!(decoded, run);
+        }
+    }
+
+    #[test]
+    fn compress_res() {
+        let bwt = Bwt::with_block_size(MIN_BLOCK_SIZE).with_threads(4);
+
+        let data = b"she sells sea shells on the sea shore. ".repeat(100);
+        let compressed = bwt.compress(&data);
+
+        assert_eq!(compressed[0], BWT_MARKER);
+        assert!(compressed.len() < data.len() / 4);
+        assert_eq!(bwt.decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn decompress_errors() {
+        let bwt = Bwt::new();
+
+        let r = bwt.decompress(&[BWT_MARKER, 0, 0, 0, 0]);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let r = bwt.decompress(&[BWT_MARKER, 0, 4, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0]);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let r = bwt.decompress(&[0x01, 2]);
+        assert_eq!(r.unwrap_err(), CompressorError::DecompressErrorWithCode);
+    }
+}
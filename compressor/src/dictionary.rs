@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+/// Length of the substrings counted while training.
+const DMER_LEN: usize = 8;
+/// Length of the segments copied from the samples into the dictionary.
+const SEGMENT_LEN: usize = 64;
+
+pub const DEFAULT_DICTIONARY_SIZE: usize = 32 * 1024;
+
+/// Dictionary is content shared by many small files. Codecs which support it
+/// start every stream as if the dictionary had just been compressed, so
+/// strings common to all files are matched from the first byte.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dictionary {
+    content: Vec<u8>,
+    id: u32,
+}
+
+impl Dictionary {
+    pub fn new(content: Vec<u8>) -> Dictionary {
+        let id = fnv1a(&content);
+        Dictionary { content, id }
+    }
+
+    /// Builds a dictionary of at most `max_size` bytes from the segments of
+    /// `samples` which share the most substrings with other samples.
+    ///
+    /// Every substring of `DMER_LEN` bytes is counted once per sample. The
+    /// samples are then split into `max_size / SEGMENT_LEN` epochs and the best
+    /// scoring segment of each one is taken, after which its substrings no
+    /// longer count, so the segments do not repeat each other. The best
+    /// segments end up at the back of the dictionary, closest to the data.
+    pub fn train(samples: &[&[u8]], max_size: usize) -> Dictionary {
+        let mut freqs: HashMap<u64, u32> = HashMap::new();
+        for sample in samples {
+            let mut seen: Vec<u64> = dmers(sample).collect();
+            seen.sort_unstable();
+            seen.dedup();
+
+            for dmer in seen {
+                *freqs.entry(dmer).or_insert(0) += 1;
+            }
+        }
+
+        // a substring seen in a single sample helps nobody
+        freqs.retain(|_, count| *count > 1);
+
+        let joined: Vec<u8> = samples.concat();
+        let segments = max_size / SEGMENT_LEN;
+        if segments == 0 || joined.len() < SEGMENT_LEN || freqs.is_empty() {
+            return Dictionary::new(Vec::new());
+        }
+
+        let epoch_len = (joined.len() / segments).max(SEGMENT_LEN);
+        let mut chosen: Vec<(u64, &[u8])> = Vec::new();
+
+        for epoch in joined.chunks(epoch_len) {
+            if let Some((score, start)) = best_segment(epoch, &freqs) {
+                let segment = &epoch[start..start + SEGMENT_LEN];
+                for dmer in dmers(segment) {
+                    freqs.remove(&dmer);
+                }
+                chosen.push((score, segment));
+            }
+        }
+
+        chosen.sort_by_key(|&(score, _)| score);
+        let content: Vec<u8> = chosen.iter()
+            .flat_map(|(_, segment)| segment.iter().copied())
+            .collect();
+
+        Dictionary::new(content)
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Checksum of the content. Streams compressed with a dictionary store it,
+    /// so they are never decoded with a different one.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+}
+
+fn dmers(data: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    data.windows(DMER_LEN).map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+}
+
+/// Score and start of the `SEGMENT_LEN` bytes of `epoch` with the highest sum of
+/// substring frequencies, or `None` if no substring of the epoch is frequent.
+fn best_segment(epoch: &[u8], freqs: &HashMap<u64, u32>) -> Option<(u64, usize)> {
+    if epoch.len() < SEGMENT_LEN {
+        return None;
+    }
+
+    let scores: Vec<u64> = dmers(epoch)
+        .map(|dmer| freqs.get(&dmer).copied().unwrap_or(0) as u64)
+        .collect();
+    let window = SEGMENT_LEN - DMER_LEN + 1;
+
+    let mut score: u64 = scores[..window].iter().sum();
+    let mut best = (score, 0);
+    for start in 1..=epoch.len() - SEGMENT_LEN {
+        score = score + scores[start + window - 1] - scores[start - 1];
+        if score > best.0 {
+            best = (score, start);
+        }
+    }
+
+    if best.0 == 0 { None } else { Some(best) }
+}
+
+fn fnv1a(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c9dc5u32, |hash, &b| (hash ^ b as u32).wrapping_mul(0x01000193))
+}
+
+
+#[cfg(test)]
+mod dictionary_test {
+    use super::*;
+
+    #[test]
+    fn train_res() {
+        let samples: Vec<Vec<u8>> = (0..200)
+            .map(|i| format!("{{\"id\": {}, \"status\": \"active\", \"created_at\": \"2024-01-{:02}\"}}", i * 37, i % 28 + 1).into_bytes())
+            .collect();
+        let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_slice()).collect();
+
+        let dictionary = Dictionary::train(&samples, 1024);
+
+        assert!(!dictionary.is_empty());
+        assert!(dictionary.len() <= 1024);
+
+        let content = String::from_utf8_lossy(dictionary.content());
+        assert!(content.contains("\"status\": \"active\""));
+    }
+
+    #[test]
+    fn train_unrelated() {
+        let a = b"0123456789abcdefghijklmnopqrstuvwxyz".repeat(4);
+        let b = b"ZYXWVUTSRQPONMLKJIHGFEDCBA!@#$%^&*()".repeat(4);
+
+        assert!(Dictionary::train(&[&a, &b], 1024).is_empty());
+        assert!(Dictionary::train(&[], 1024).is_empty());
+        assert!(Dictionary::train(&[&a, &a], 10).is_empty());
+    }
+
+    #[test]
+    fn id_res() {
+        let a = Dictionary::new(b"lorem ipsum".to_vec());
+        let b = Dictionary::new(b"lorem ipsun".to_vec());
+
+        assert_eq!(a.id(), Dictionary::new(b"lorem ipsum".to_vec()).id());
+        assert_ne!(a.id(), b.id());
+    }
+}
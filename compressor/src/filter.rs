@@ -0,0 +1,252 @@
+use super::CompressorError;
+
+use std::fmt;
+use std::str::FromStr;
+
+pub const FILTER_NONE: u8 = 0;
+pub const FILTER_DELTA: u8 = 1;
+pub const FILTER_X86: u8 = 2;
+pub const FILTER_ARM64: u8 = 3;
+
+/// x86 calls and jumps with a displacement inside +-16MiB are converted.
+const X86_MASK: u32 = 0x01ff_ffff;
+/// ARM64 BL keeps a 26 bit word offset, ADRP a 21 bit page offset.
+const ARM64_BL_MASK: u32 = 0x03ff_ffff;
+const ARM64_ADRP_MASK: u32 = 0x001f_ffff;
+
+/// Filter is a reversible transform run on the data before a compressor.
+/// It does not change the length of the data, so it can be undone in place.
+///
+/// * `Delta(stride)` stores every byte as the difference with the byte
+///   `stride` positions back, which turns arrays of slowly changing numbers
+///   into runs of small values.
+/// * `X86` and `Arm64` convert relative branch targets of executables into
+///   absolute ones, so calls of the same function become equal byte strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    #[default]
+    None,
+    Delta(u8),
+    X86,
+    Arm64,
+}
+
+impl Filter {
+    /// Filter identifier and parameter as stored in archives.
+    pub fn to_parts(self) -> (u8, u8) {
+        match self {
+            Filter::None => (FILTER_NONE, 0),
+            Filter::Delta(stride) => (FILTER_DELTA, stride),
+            Filter::X86 => (FILTER_X86, 0),
+            Filter::Arm64 => (FILTER_ARM64, 0),
+        }
+    }
+
+    pub fn from_parts(id: u8, param: u8) -> Result<Filter, CompressorError> {
+        match (id, param) {
+            (FILTER_NONE, _) => Ok(Filter::None),
+            (FILTER_DELTA, stride) if stride > 0 => Ok(Filter::Delta(stride)),
+            (FILTER_X86, _) => Ok(Filter::X86),
+            (FILTER_ARM64, _) => Ok(Filter::Arm64),
+            _ => Err(CompressorError::UnknownFilter),
+        }
+    }
+
+    pub fn encode(&self, data: &mut [u8]) {
+        match *self {
+            Filter::None => {},
+            Filter::Delta(stride) => delta_encode(data, stride as usize),
+            Filter::X86 => x86_convert(data, true),
+            Filter::Arm64 => arm64_convert(data, true),
+        }
+    }
+
+    pub fn decode(&self, data: &mut [u8]) {
+        match *self {
+            Filter::None => {},
+            Filter::Delta(stride) => delta_decode(data, stride as usize),
+            Filter::X86 => x86_convert(data, false),
+            Filter::Arm64 => arm64_convert(data, false),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::None => write!(f, "none"),
+            Filter::Delta(stride) => write!(f, "delta:{}", stride),
+            Filter::X86 => write!(f, "x86"),
+            Filter::Arm64 => write!(f, "arm64"),
+        }
+    }
+}
+
+/// Parses `none`, `x86`, `arm64`, `delta` (stride 1) and `delta:N`.
+impl FromStr for Filter {
+    type Err = CompressorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Filter::None),
+            "x86" => Ok(Filter::X86),
+            "arm64" => Ok(Filter::Arm64),
+            "delta" => Ok(Filter::Delta(1)),
+            _ => {
+                let stride = s.strip_prefix("delta:")
+                    .and_then(|stride| stride.parse::<u8>().ok())
+                    .ok_or(CompressorError::UnknownFilter)?;
+                Filter::from_parts(FILTER_DELTA, stride)
+            }
+        }
+    }
+}
+
+fn delta_encode(data: &mut [u8], stride: usize) {
+    for i in (stride..data.len()).rev() {
+        data[i] = data[i].wrapping_sub(data[i - stride]);
+    }
+}
+
+fn delta_decode(data: &mut [u8], stride: usize) {
+    for i in stride..data.len() {
+        data[i] = data[i].wrapping_add(data[i - stride]);
+    }
+}
+
+/// Converts the rel32 operand of `call` (E8) and `jmp` (E9) when its top byte
+/// is 0x00 or 0xff. The result is sign extended from bit 24, so the top byte
+/// stays 0x00 or 0xff and the decoder converts exactly the same operands.
+/// The operand is skipped even when it is left as is: its bytes would be
+/// changed by the encoder if taken for an opcode, and the decoder could not
+/// tell those positions apart.
+fn x86_convert(data: &mut [u8], encode: bool) {
+    let mut i = 0;
+    while i + 5 <= data.len() {
+        let opcode = data[i];
+        if opcode != 0xe8 && opcode != 0xe9 {
+            i += 1;
+            continue;
+        }
+
+        let top = data[i + 4];
+        if top == 0x00 || top == 0xff {
+            let operand = u32::from_le_bytes(data[i + 1..i + 5].try_into().unwrap());
+            let pos = (i + 5) as u32;
+            let converted = if encode { operand.wrapping_add(pos) } else { operand.wrapping_sub(pos) };
+
+            let converted = converted & X86_MASK;
+            let converted = if converted & 0x0100_0000 != 0 { converted | !X86_MASK } else { converted };
+            data[i + 1..i + 5].copy_from_slice(&converted.to_le_bytes());
+        }
+
+        i += 5;
+    }
+}
+
+/// Converts the targets of `bl` and `adrp` in 4 byte aligned instructions.
+fn arm64_convert(data: &mut [u8], encode: bool) {
+    for (n, word) in data.chunks_exact_mut(4).enumerate() {
+        let pos = (n * 4) as u32;
+        let instr = u32::from_le_bytes((&*word).try_into().unwrap());
+
+        let converted = if instr >> 26 == 0x25 {
+            let pc = pos >> 2;
+            let imm = instr & ARM64_BL_MASK;
+            let imm = if encode { imm.wrapping_add(pc) } else { imm.wrapping_sub(pc) };
+
+            (instr & !ARM64_BL_MASK) | (imm & ARM64_BL_MASK)
+        } else if instr & 0x9f00_0000 == 0x9000_0000 {
+            let page = pos >> 12;
+            let imm = ((instr >> 5) & 0x7ffff) << 2 | (instr >> 29) & 3;
+            let imm = if encode { imm.wrapping_add(page) } else { imm.wrapping_sub(page) } & ARM64_ADRP_MASK;
+
+            (instr & 0x9f00_001f) | (imm & 3) << 29 | (imm >> 2) << 5
+        } else {
+            continue;
+        };
+
+        word.copy_from_slice(&converted.to_le_bytes());
+    }
+}
+
+
+#[cfg(test)]
+mod filter_test {
+    use super::*;
+
+    fn round_trip(filter: Filter, data: &[u8]) -> Vec<u8> {
+        let mut encoded = data.to_vec();
+        filter.encode(&mut encoded);
+
+        let mut decoded = encoded.clone();
+        filter.decode(&mut decoded);
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn delta_res() {
+        let data: Vec<u8> = (0..16u16).flat_map(|i| (0x1200 + i * 3).to_le_bytes()).collect();
+        let encoded = round_trip(Filter::Delta(2), &data);
+
+        assert_eq!(&encoded[..2], &data[..2]);
+        assert!(encoded[2..].chunks(2).all(|pair| pair == [3, 0]));
+    }
+
+    #[test]
+    fn x86_res() {
+        // call +0x10 at offset 3 and at offset 19 point to the same absolute target after the filter
+        let mut data = vec![0x90u8; 40];
+        data[3..8].copy_from_slice(&[0xe8, 0x10, 0, 0, 0]);
+        data[19..24].copy_from_slice(&[0xe8, 0x00, 0, 0, 0]);
+        data[30..35].copy_from_slice(&[0xe9, 0xf0, 0xff, 0xff, 0xff]);
+
+        let encoded = round_trip(Filter::X86, &data);
+        assert_eq!(&encoded[3..8], &[0xe8, 0x18, 0, 0, 0]);
+        assert_eq!(&encoded[19..24], &[0xe8, 0x18, 0, 0, 0]);
+        assert_eq!(&encoded[30..35], &[0xe9, 0x13, 0, 0, 0]);
+
+        let noise: Vec<u8> = (0..10000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        round_trip(Filter::X86, &noise);
+
+        // converting a call inside the operand of the first one would turn its 0xfe into 0xff
+        let data = [0xe8, 0xe8, 0xfa, 0xff, 0xfe, 0x00, 0x90, 0x90];
+        let encoded = round_trip(Filter::X86, &data);
+        assert_eq!(encoded, data);
+
+        let ones: Vec<u8> = (0..4096u32).map(|i| [0xe8, 0xff, 0x00, 0x01][(i * 7 % 13 % 4) as usize]).collect();
+        round_trip(Filter::X86, &ones);
+    }
+
+    #[test]
+    fn arm64_res() {
+        // bl with the same target from two places, an adrp and some other instruction
+        let instrs: [u32; 4] = [0x9400_0010, 0x9400_000f, 0xb000_0001, 0xd503_201f];
+        let data: Vec<u8> = instrs.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        let encoded = round_trip(Filter::Arm64, &data);
+        assert_eq!(&encoded[0..4], &encoded[4..8]);
+        assert_eq!(&encoded[12..16], &data[12..16]);
+
+        let noise: Vec<u8> = (0..10001u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        round_trip(Filter::Arm64, &noise);
+        round_trip(Filter::Arm64, &[]);
+    }
+
+    #[test]
+    fn parse_res() {
+        for filter in [Filter::None, Filter::Delta(4), Filter::X86, Filter::Arm64] {
+            assert_eq!(filter.to_string().parse::<Filter>().unwrap(), filter);
+
+            let (id, param) = filter.to_parts();
+            assert_eq!(Filter::from_parts(id, param).unwrap(), filter);
+        }
+
+        assert_eq!("delta".parse::<Filter>().unwrap(), Filter::Delta(1));
+        assert_eq!("delta:0".parse::<Filter>().unwrap_err(), CompressorError::UnknownFilter);
+        assert_eq!("sparc".parse::<Filter>().unwrap_err(), CompressorError::UnknownFilter);
+        assert_eq!(Filter::from_parts(9, 0).unwrap_err(), CompressorError::UnknownFilter);
+    }
+}
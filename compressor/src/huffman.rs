@@ -0,0 +1,451 @@
+use super::bits::{BitReader, BitWriter};
+use super::CompressorError;
+use super::Compressor;
+use super::CompressorOptions;
+use super::check_output_size;
+use super::registry::CODEC_HUFFMAN;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Longest code length a table may contain. Lengths are serialized as nibbles.
+pub const MAX_CODE_LEN: usize = 15;
+
+const HUFFMAN_MARKER: u8 = 0xf1;
+const STORED_MARKER: u8 = 0xff;
+
+/// HuffmanEncoder holds a canonical Huffman code for an alphabet of arbitrary size.
+#[derive(Debug, Clone)]
+pub struct HuffmanEncoder {
+    lengths: Vec<u8>,
+    codes: Vec<u32>,
+}
+
+impl HuffmanEncoder {
+    /// Builds a length-limited code for the given symbol frequencies.
+    /// Symbols with zero frequency get no code.
+    pub fn from_frequencies(freqs: &[u32]) -> HuffmanEncoder {
+        let lengths = build_lengths(freqs);
+        HuffmanEncoder::from_lengths(&lengths)
+    }
+
+    /// Builds the canonical code for already known code lengths.
+    pub fn from_lengths(lengths: &[u8]) -> HuffmanEncoder {
+        HuffmanEncoder {
+            lengths: lengths.to_vec(),
+            codes: canonical_codes(lengths),
+        }
+    }
+
+    pub fn lengths(&self) -> &[u8] {
+        &self.lengths
+    }
+
+    /// Serializes the code lengths. The alphabet size is not stored,
+    /// the decoder must know it in advance.
+    pub fn write_table(&self, writer: &mut BitWriter) {
+        let mut i = 0;
+        while i < self.lengths.len() {
+            if self.lengths[i] != 0 {
+                writer.write_bits(self.lengths[i] as u32, 4);
+                i += 1;
+                continue;
+            }
+
+            // a zero nibble is followed by the length of the zero run
+            let mut run = 1;
+            while run < 16 && i + run < self.lengths.len() && self.lengths[i + run] == 0 {
+                run += 1;
+            }
+            writer.write_bits(0, 4);
+            writer.write_bits(run as u32 - 1, 4);
+            i += run;
+        }
+    }
+
+    /// Writes the code of `symbol`. Panics if the symbol has no code.
+    pub fn encode(&self, symbol: usize, writer: &mut BitWriter) {
+        let len = self.lengths[symbol];
+        assert!(len != 0, "Symbol {} has no code", symbol);
+
+        writer.write_bits(self.codes[symbol], len as u32);
+    }
+
+    /// Total size in bits of the data described by `freqs` encoded with this code.
+    pub fn encoded_bits(&self, freqs: &[u32]) -> usize {
+        freqs.iter()
+            .zip(self.lengths.iter())
+            .map(|(&f, &l)| f as usize * l as usize)
+            .sum()
+    }
+}
+
+/// HuffmanDecoder decodes symbols written by [`HuffmanEncoder`].
+#[derive(Debug, Clone)]
+pub struct HuffmanDecoder {
+    count: [u16; MAX_CODE_LEN + 1],
+    symbols: Vec<u16>,
+}
+
+impl HuffmanDecoder {
+    pub fn from_lengths(lengths: &[u8]) -> Result<HuffmanDecoder, CompressorError> {
+        let mut count = [0u16; MAX_CODE_LEN + 1];
+        for &len in lengths {
+            if len as usize > MAX_CODE_LEN {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            count[len as usize] += 1;
+        }
+        count[0] = 0;
+
+        // reject over-subscribed tables
+        let mut left: i64 = 1;
+        for &c in count.iter().skip(1) {
+            left <<= 1;
+            left -= c as i64;
+            if left < 0 {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+        }
+
+        let mut offsets = [0usize; MAX_CODE_LEN + 2];
+        for len in 1..=MAX_CODE_LEN {
+            offsets[len + 1] = offsets[len] + count[len] as usize;
+        }
+
+        let mut symbols = vec![0u16; offsets[MAX_CODE_LEN + 1]];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize]] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(HuffmanDecoder { count, symbols })
+    }
+
+    /// Reads a table written by [`HuffmanEncoder::write_table`].
+    pub fn read_table(reader: &mut BitReader, alphabet_size: usize) -> Result<HuffmanDecoder, CompressorError> {
+        let mut lengths: Vec<u8> = Vec::with_capacity(alphabet_size);
+
+        while lengths.len() < alphabet_size {
+            let len = reader.read_bits(4)? as u8;
+            if len != 0 {
+                lengths.push(len);
+                continue;
+            }
+
+            let run = reader.read_bits(4)? as usize + 1;
+            if lengths.len() + run > alphabet_size {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            lengths.resize(lengths.len() + run, 0);
+        }
+
+        HuffmanDecoder::from_lengths(&lengths)
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> Result<usize, CompressorError> {
+        let mut code: i64 = 0;
+        let mut first: i64 = 0;
+        let mut index: i64 = 0;
+
+        for len in 1..=MAX_CODE_LEN {
+            code |= reader.read_bit()? as i64;
+            let count = self.count[len] as i64;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(CompressorError::DecompressErrorWithCode)
+    }
+}
+
+fn tree_lengths(freqs: &[u64]) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+
+    let used: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        }
+        _ => (),
+    }
+
+    let mut parents = vec![usize::MAX; used.len() * 2 - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = used.iter()
+        .enumerate()
+        .map(|(node, &symbol)| Reverse((freqs[symbol], node)))
+        .collect();
+
+    let mut next = used.len();
+    while heap.len() > 1 {
+        let Reverse((f1, n1)) = heap.pop().unwrap();
+        let Reverse((f2, n2)) = heap.pop().unwrap();
+
+        parents[n1] = next;
+        parents[n2] = next;
+        heap.push(Reverse((f1 + f2, next)));
+        next += 1;
+    }
+
+    for (node, &symbol) in used.iter().enumerate() {
+        let mut depth = 0;
+        let mut n = node;
+        while parents[n] != usize::MAX {
+            n = parents[n];
+            depth += 1;
+        }
+        lengths[symbol] = depth.min(u8::MAX as usize) as u8;
+    }
+
+    lengths
+}
+
+fn build_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut freqs: Vec<u64> = freqs.iter().map(|&f| f as u64).collect();
+
+    loop {
+        let lengths = tree_lengths(&freqs);
+        if lengths.iter().all(|&len| len as usize <= MAX_CODE_LEN) {
+            return lengths;
+        }
+
+        // flatten the distribution until the tree fits into MAX_CODE_LEN
+        freqs.iter_mut()
+            .filter(|f| **f > 0)
+            .for_each(|f| *f = (*f >> 1) | 1);
+    }
+}
+
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut bl_count = [0u32; MAX_CODE_LEN + 1];
+    for &len in lengths {
+        if len != 0 {
+            bl_count[len as usize] += 1;
+        }
+    }
+
+    let mut next_code = [0u32; MAX_CODE_LEN + 1];
+    let mut code = 0;
+    for bits in 1..=MAX_CODE_LEN {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths.iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+
+/// Huffman is a byte oriented canonical Huffman coder.
+#[derive(Debug, Default)]
+pub struct Huffman {}
+
+impl Huffman {
+    pub fn new() -> Huffman {
+        Huffman {}
+    }
+
+    /// Huffman has no tuning knobs, the options are accepted for uniformity.
+    pub fn from_options(_options: &CompressorOptions) -> Huffman {
+        Huffman::new()
+    }
+
+    pub fn compress(&self, src: &[u8]) -> Vec<u8> {
+        Compressor::compress(self, src)
+    }
+
+    pub fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        Compressor::decompress(self, src)
+    }
+}
+
+impl Compressor for Huffman {
+    fn options(&self) -> CompressorOptions {
+        CompressorOptions::default()
+    }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_HUFFMAN
+    }
+
+    fn compress(&self, src: &[u8]) -> Vec<u8> {
+        if src.is_empty() {
+            return Vec::new();
+        }
+
+        let mut freqs = [0u32; 256];
+        for &symbol in src {
+            freqs[symbol as usize] = freqs[symbol as usize].saturating_add(1);
+        }
+
+        let encoder = HuffmanEncoder::from_frequencies(&freqs);
+
+        let mut header = Vec::with_capacity(9);
+        header.push(HUFFMAN_MARKER);
+        header.extend_from_slice(&(src.len() as u64).to_le_bytes());
+
+        let mut writer = BitWriter::with_buffer(header);
+        encoder.write_table(&mut writer);
+        for &symbol in src {
+            encoder.encode(symbol as usize, &mut writer);
+        }
+
+        let result = writer.finish();
+        if result.len() > src.len() {
+            let mut dst: Vec<u8> = Vec::with_capacity(1 + src.len());
+            dst.push(STORED_MARKER);
+            dst.extend_from_slice(src);
+
+            return dst;
+        }
+
+        result
+    }
+
+    fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        self.decompress_limited(src, usize::MAX)
+    }
+
+    fn decompress_limited(&self, src: &[u8], max_size: usize) -> Result<Vec<u8>, CompressorError> {
+        if src.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if src[0] == STORED_MARKER {
+            check_output_size(src.len() - 1, max_size)?;
+            return Ok(src[1..].to_vec());
+        } else if src[0] != HUFFMAN_MARKER {
+            return Err(CompressorError::DecompressErrorWithCode);
+        }
+
+        if src.len() < 9 {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+        let length = u64::from_le_bytes(src[1..9].try_into().unwrap()) as usize;
+        check_output_size(length, max_size)?;
+
+        let mut reader = BitReader::new(&src[9..]);
+        let decoder = HuffmanDecoder::read_table(&mut reader, 256)?;
+
+        // every symbol takes at least one bit
+        if length > (src.len() - 9) * 8 {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+
+        let mut result = Vec::with_capacity(length);
+        for _ in 0..length {
+            result.push(decoder.decode(&mut reader)? as u8);
+        }
+
+        Ok(result)
+    }
+}
+
+
+#[cfg(test)]
+mod huffman_test {
+    use super::*;
+
+    #[test]
+    fn lengths_res() {
+        let freqs = [5u32, 9, 12, 13, 16, 45];
+        let encoder = HuffmanEncoder::from_frequencies(&freqs);
+
+        assert_eq!(encoder.lengths(), &[4, 4, 3, 3, 3, 1]);
+        assert_eq!(encoder.encoded_bits(&freqs), 224);
+    }
+
+    #[test]
+    fn lengths_limited() {
+        // fibonacci frequencies produce a degenerate tree deeper than MAX_CODE_LEN
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            let n = freqs[freqs.len() - 1] + freqs[freqs.len() - 2];
+            freqs.push(n);
+        }
+
+        let encoder = HuffmanEncoder::from_frequencies(&freqs);
+
+        assert!(encoder.lengths().iter().all(|&l| l > 0 && l as usize <= MAX_CODE_LEN));
+    }
+
+    #[test]
+    fn canonical_codes_res() {
+        let encoder = HuffmanEncoder::from_lengths(&[3, 3, 3, 3, 3, 2, 4, 4]);
+
+        assert_eq!(encoder.codes, vec![0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]);
+    }
+
+    #[test]
+    fn table_res() {
+        let mut lengths = vec![0u8; 300];
+        lengths[3] = 2;
+        lengths[40] = 1;
+        lengths[299] = 2;
+
+        let encoder = HuffmanEncoder::from_lengths(&lengths);
+        let mut writer = BitWriter::new();
+        encoder.write_table(&mut writer);
+        let table = writer.finish();
+
+        let mut reader = BitReader::new(&table);
+        let decoder = HuffmanDecoder::read_table(&mut reader, 300).unwrap();
+
+        let mut writer = BitWriter::new();
+        for symbol in [3, 40, 299, 40] {
+            encoder.encode(symbol, &mut writer);
+        }
+        let data = writer.finish();
+
+        let mut reader = BitReader::new(&data);
+        for symbol in [3, 40, 299, 40] {
+            assert_eq!(decoder.decode(&mut reader).unwrap(), symbol);
+        }
+    }
+
+    #[test]
+    fn decoder_errors() {
+        let r = HuffmanDecoder::from_lengths(&[1, 1, 1]);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let huffman = Huffman::new();
+        let r = huffman.decompress(&[0xf1, 1, 0]);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let r = huffman.decompress(&[0xf7, 1, 0]);
+        assert_eq!(r.unwrap_err(), CompressorError::DecompressErrorWithCode);
+    }
+
+    #[test]
+    fn compress_res() {
+        let huffman = Huffman::new();
+
+        let uncompressed = b"aaaaaaaaaaaaaaaabbbbbbbbccccdd".repeat(10);
+        let compressed = huffman.compress(&uncompressed);
+
+        assert_eq!(compressed[0], HUFFMAN_MARKER);
+        assert!(compressed.len() < uncompressed.len());
+        assert_eq!(huffman.decompress(&compressed).unwrap(), uncompressed);
+
+        let single = vec![7u8; 100];
+        let compressed = huffman.compress(&single);
+        assert_eq!(huffman.decompress(&compressed).unwrap(), single);
+    }
+}
@@ -1,13 +1,101 @@
 pub mod lzw;
+pub mod huffman;
+pub mod range;
+pub mod bwt;
+pub mod lz77;
+pub mod filter;
+pub mod dictionary;
+pub mod bits;
+pub mod registry;
 
+use dictionary::Dictionary;
+
+use std::sync::Arc;
 
 #[derive(Debug, PartialEq)]
 pub enum CompressorError {
     DecompressErrorWithCode,
     IncorrectSrcValue,
+    CodecAlreadyRegistered,
+    /// The stream expands to more bytes than the caller allowed.
+    OutputLimitExceeded,
+    UnknownFilter,
+    /// The stream was compressed with another dictionary, or without one.
+    DictionaryMismatch,
+}
+
+/// CompressorOptions tunes a compressor. Codec specific knobs left as `None`
+/// are derived from `level`. Everything the decoder needs is stored in the
+/// stream header, so these options only matter for compression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorOptions {
+    /// Compression level from 1 (fastest) to 9 (strongest).
+    pub level: u8,
+    /// LZ77 window size as a power of two.
+    pub window_log: Option<u8>,
+    /// BWT block size in bytes.
+    pub block_size: Option<usize>,
+    /// Context order of the range coder.
+    pub order: Option<u8>,
+    /// Threads a single stream may use, for codecs with independent blocks.
+    pub threads: usize,
+}
+
+impl CompressorOptions {
+    pub fn with_level(level: u8) -> CompressorOptions {
+        CompressorOptions {
+            level: level.clamp(1, 9),
+            ..CompressorOptions::default()
+        }
+    }
+}
+
+impl Default for CompressorOptions {
+    fn default() -> Self {
+        CompressorOptions {
+            level: 6,
+            window_log: None,
+            block_size: None,
+            order: None,
+            threads: 1,
+        }
+    }
+}
+
+pub(crate) fn check_output_size(size: usize, max_size: usize) -> Result<(), CompressorError> {
+    if size > max_size {
+        return Err(CompressorError::OutputLimitExceeded);
+    }
+
+    Ok(())
 }
 
 pub trait Compressor: Send + Sync {
     fn compress(&self, src: &[u8]) -> Vec<u8>;
     fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError>;
+    /// Decompresses `src`, failing with [`CompressorError::OutputLimitExceeded`]
+    /// instead of producing more than `max_size` bytes. Untrusted streams should
+    /// be decoded with this method.
+    ///
+    /// The default implementation only checks the result, codecs of this crate
+    /// stop before allocating past the limit.
+    fn decompress_limited(&self, src: &[u8], max_size: usize) -> Result<Vec<u8>, CompressorError> {
+        let result = self.decompress(src)?;
+        check_output_size(result.len(), max_size)?;
+
+        Ok(result)
+    }
+    /// Options describing how this compressor was configured.
+    fn options(&self) -> CompressorOptions;
+    /// Identifier of the stream format, see [`registry`].
+    fn codec_id(&self) -> u8;
+    /// Returns a copy of this compressor primed with `dictionary`,
+    /// or `None` if the codec can not use one.
+    fn with_dictionary(&self, _dictionary: &Dictionary) -> Option<Arc<dyn Compressor>> {
+        None
+    }
+    /// Identifier of the dictionary the compressor is primed with.
+    fn dictionary_id(&self) -> Option<u32> {
+        None
+    }
 }
\ No newline at end of file
@@ -0,0 +1,491 @@
+use super::CompressorError;
+use super::Compressor;
+use super::CompressorOptions;
+use super::check_output_size;
+use super::registry::CODEC_LZ77;
+use super::dictionary::Dictionary;
+
+use std::sync::Arc;
+
+const LZ77_MARKER: u8 = 0xf4;
+/// Streams compressed with a dictionary carry its id after the length.
+const LZ77_DICT_MARKER: u8 = 0xe4;
+const STORED_MARKER: u8 = 0xff;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = MIN_MATCH + 255;
+
+const HASH_BITS: u32 = 15;
+const NIL: usize = usize::MAX;
+
+pub const MIN_WINDOW_LOG: u8 = 10;
+pub const MAX_WINDOW_LOG: u8 = 24;
+pub const DEFAULT_WINDOW_LOG: u8 = 16;
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 9;
+pub const DEFAULT_LEVEL: u8 = 6;
+
+/// Match finder parameters of a compression level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LevelConfig {
+    /// Maximum number of hash chain entries checked per position.
+    max_chain: usize,
+    /// Stop searching once a match of this length is found.
+    nice_length: usize,
+    /// Matches shorter than this are deferred in favour of a longer one at the next byte.
+    /// Zero disables lazy matching.
+    max_lazy: usize,
+}
+
+const LEVELS: [LevelConfig; 9] = [
+    LevelConfig { max_chain: 6,    nice_length: 16,  max_lazy: 0 },
+    LevelConfig { max_chain: 8,    nice_length: 16,  max_lazy: 0 },
+    LevelConfig { max_chain: 32,   nice_length: 32,  max_lazy: 0 },
+    LevelConfig { max_chain: 16,   nice_length: 16,  max_lazy: 4 },
+    LevelConfig { max_chain: 32,   nice_length: 32,  max_lazy: 16 },
+    LevelConfig { max_chain: 128,  nice_length: 128, max_lazy: 16 },
+    LevelConfig { max_chain: 256,  nice_length: 128, max_lazy: 32 },
+    LevelConfig { max_chain: 1024, nice_length: 258, max_lazy: 128 },
+    LevelConfig { max_chain: 4096, nice_length: 258, max_lazy: 258 },
+];
+
+/// LZ77 is an LZSS sliding window compressor with a hash chain match finder.
+/// Tokens are grouped by eight behind a flag byte: a literal is one byte,
+/// a match is its length followed by a 2 or 3 byte offset depending on the window.
+#[derive(Debug)]
+pub struct LZ77 {
+    level: u8,
+    window_log: u8,
+    dictionary: Option<Arc<Dictionary>>,
+}
+
+impl LZ77 {
+    pub fn new() -> LZ77 {
+        LZ77::with_level(DEFAULT_LEVEL)
+    }
+
+    /// Creates a compressor with the given level, clamped to [`MIN_LEVEL`]..=[`MAX_LEVEL`].
+    pub fn with_level(level: u8) -> LZ77 {
+        LZ77 {
+            level: level.clamp(MIN_LEVEL, MAX_LEVEL),
+            window_log: DEFAULT_WINDOW_LOG,
+            dictionary: None,
+        }
+    }
+
+    /// Sets the window size to 2^`window_log` bytes, clamped to
+    /// [`MIN_WINDOW_LOG`]..=[`MAX_WINDOW_LOG`].
+    pub fn with_window_log(self, window_log: u8) -> LZ77 {
+        LZ77 {
+            window_log: window_log.clamp(MIN_WINDOW_LOG, MAX_WINDOW_LOG),
+            ..self
+        }
+    }
+
+    /// Uses `dictionary` as data preceding every stream, so matches may point into it.
+    /// Only the last 2^`window_log` bytes of the dictionary are reachable.
+    pub fn with_dictionary(self, dictionary: Arc<Dictionary>) -> LZ77 {
+        LZ77 {
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    pub fn from_options(options: &CompressorOptions) -> LZ77 {
+        LZ77::with_level(options.level)
+            .with_window_log(options.window_log.unwrap_or(DEFAULT_WINDOW_LOG))
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn window_log(&self) -> u8 {
+        self.window_log
+    }
+
+    pub fn compress(&self, src: &[u8]) -> Vec<u8> {
+        Compressor::compress(self, src)
+    }
+
+    pub fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        Compressor::decompress(self, src)
+    }
+}
+
+impl Default for LZ77 {
+    fn default() -> Self {
+        LZ77::new()
+    }
+}
+
+fn offset_size(window_log: u8) -> usize {
+    if window_log <= 16 { 2 } else { 3 }
+}
+
+/// Shortest match worth a token. With 3 byte offsets a match of [`MIN_MATCH`]
+/// bytes takes more space than the literals it replaces.
+fn min_length(window_log: u8) -> usize {
+    MIN_MATCH + offset_size(window_log) - 2
+}
+
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    window: usize,
+    min_length: usize,
+    config: LevelConfig,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(data: &'a [u8], window_log: u8, config: LevelConfig) -> MatchFinder<'a> {
+        let window = 1usize << window_log;
+
+        MatchFinder {
+            data,
+            head: vec![NIL; 1 << HASH_BITS],
+            prev: vec![NIL; window.min(data.len().next_power_of_two())],
+            window,
+            min_length: min_length(window_log),
+            config,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let v = (self.data[pos] as u32) << 16 | (self.data[pos + 1] as u32) << 8 | self.data[pos + 2] as u32;
+        (v.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH > self.data.len() {
+            return;
+        }
+
+        let h = self.hash(pos);
+        let mask = self.prev.len() - 1;
+        self.prev[pos & mask] = self.head[h];
+        self.head[h] = pos;
+    }
+
+    /// Returns (length, distance) of the longest match for `pos` among inserted positions.
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        let data = self.data;
+        if pos + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+
+        let max_len = MAX_MATCH.min(data.len() - pos);
+        let mask = self.prev.len() - 1;
+
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        let mut chain = self.config.max_chain;
+
+        while candidate != NIL && chain > 0 {
+            let distance = pos - candidate;
+            if distance >= self.window || distance > mask {
+                break;
+            }
+
+            if data[candidate + best.0] == data[pos + best.0] {
+                let len = data[candidate..candidate + max_len].iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if len > best.0 {
+                    best = (len, distance);
+                    if len >= self.config.nice_length || len == max_len {
+                        break;
+                    }
+                }
+            }
+
+            let next = self.prev[candidate & mask];
+            if next == NIL || next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+
+        if best.0 < self.min_length { (0, 0) } else { best }
+    }
+}
+
+struct TokenWriter {
+    dst: Vec<u8>,
+    flag_pos: usize,
+    flag_bit: u32,
+    offset_size: usize,
+}
+
+impl TokenWriter {
+    fn new(dst: Vec<u8>, offset_size: usize) -> TokenWriter {
+        TokenWriter { flag_pos: dst.len(), dst, flag_bit: 8, offset_size }
+    }
+
+    fn next_flag(&mut self, is_match: bool) {
+        if self.flag_bit == 8 {
+            self.flag_pos = self.dst.len();
+            self.dst.push(0);
+            self.flag_bit = 0;
+        }
+        if is_match {
+            self.dst[self.flag_pos] |= 1 << self.flag_bit;
+        }
+        self.flag_bit += 1;
+    }
+
+    fn literal(&mut self, byte: u8) {
+        self.next_flag(false);
+        self.dst.push(byte);
+    }
+
+    fn copy(&mut self, len: usize, distance: usize) {
+        self.next_flag(true);
+        self.dst.push((len - MIN_MATCH) as u8);
+        self.dst.extend_from_slice(&((distance - 1) as u32).to_le_bytes()[..self.offset_size]);
+    }
+}
+
+impl Compressor for LZ77 {
+    fn options(&self) -> CompressorOptions {
+        CompressorOptions {
+            level: self.level,
+            window_log: Some(self.window_log),
+            ..CompressorOptions::default()
+        }
+    }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_LZ77
+    }
+
+    fn with_dictionary(&self, dictionary: &Dictionary) -> Option<Arc<dyn Compressor>> {
+        let lz77 = LZ77::with_level(self.level)
+            .with_window_log(self.window_log)
+            .with_dictionary(Arc::new(dictionary.clone()));
+
+        Some(Arc::new(lz77))
+    }
+
+    fn dictionary_id(&self) -> Option<u32> {
+        self.dictionary.as_ref().map(|d| d.id())
+    }
+
+    fn compress(&self, src: &[u8]) -> Vec<u8> {
+        if src.is_empty() {
+            return Vec::new();
+        }
+
+        let prefix = self.dictionary.as_ref().map(|d| d.content()).unwrap_or(&[]);
+        let data = [prefix, src].concat();
+
+        let config = LEVELS[(self.level - 1) as usize];
+        let mut finder = MatchFinder::new(&data, self.window_log, config);
+        for pos in 0..prefix.len() {
+            finder.insert(pos);
+        }
+
+        let mut header = Vec::with_capacity(src.len() / 2);
+        header.push(if self.dictionary.is_some() { LZ77_DICT_MARKER } else { LZ77_MARKER });
+        header.push(self.window_log);
+        header.extend_from_slice(&(src.len() as u64).to_le_bytes());
+        if let Some(dictionary) = &self.dictionary {
+            header.extend_from_slice(&dictionary.id().to_le_bytes());
+        }
+        let mut writer = TokenWriter::new(header, offset_size(self.window_log));
+
+        let mut pos = prefix.len();
+        while pos < data.len() {
+            let (len, distance) = finder.longest_match(pos);
+            finder.insert(pos);
+
+            if len == 0 {
+                writer.literal(data[pos]);
+                pos += 1;
+                continue;
+            }
+
+            if len < config.max_lazy {
+                let (next_len, _) = finder.longest_match(pos + 1);
+                if next_len > len {
+                    writer.literal(data[pos]);
+                    pos += 1;
+                    continue;
+                }
+            }
+
+            writer.copy(len, distance);
+            for p in pos + 1..pos + len {
+                finder.insert(p);
+            }
+            pos += len;
+        }
+
+        let result = writer.dst;
+        if result.len() > src.len() {
+            let mut dst: Vec<u8> = Vec::with_capacity(1 + src.len());
+            dst.push(STORED_MARKER);
+            dst.extend_from_slice(src);
+
+            return dst;
+        }
+
+        result
+    }
+
+    fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        self.decompress_limited(src, usize::MAX)
+    }
+
+    fn decompress_limited(&self, src: &[u8], max_size: usize) -> Result<Vec<u8>, CompressorError> {
+        if src.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if src[0] == STORED_MARKER {
+            check_output_size(src.len() - 1, max_size)?;
+            return Ok(src[1..].to_vec());
+        } else if src[0] != LZ77_MARKER && src[0] != LZ77_DICT_MARKER {
+            return Err(CompressorError::DecompressErrorWithCode);
+        }
+
+        if src.len() < 10 || !(MIN_WINDOW_LOG..=MAX_WINDOW_LOG).contains(&src[1]) {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+        let offset_size = offset_size(src[1]);
+        let length = u64::from_le_bytes(src[2..10].try_into().unwrap()) as usize;
+        check_output_size(length, max_size)?;
+
+        let mut pos = 10;
+        let prefix = if src[0] == LZ77_DICT_MARKER {
+            if src.len() < 14 {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            let id = u32::from_le_bytes(src[10..14].try_into().unwrap());
+            pos = 14;
+
+            match &self.dictionary {
+                Some(dictionary) if dictionary.id() == id => dictionary.content(),
+                _ => return Err(CompressorError::DictionaryMismatch),
+            }
+        } else if self.dictionary.is_some() {
+            return Err(CompressorError::DictionaryMismatch);
+        } else {
+            &[]
+        };
+        let length = length.checked_add(prefix.len()).ok_or(CompressorError::IncorrectSrcValue)?;
+
+        // each source byte yields at most MAX_MATCH output bytes
+        let mut result: Vec<u8> = Vec::with_capacity(length.min(prefix.len() + src.len() * MAX_MATCH));
+        result.extend_from_slice(prefix);
+        let mut flags = 0u32;
+        let mut flag_bit = 8;
+
+        while result.len() < length {
+            if flag_bit == 8 {
+                flags = *src.get(pos).ok_or(CompressorError::IncorrectSrcValue)? as u32;
+                pos += 1;
+                flag_bit = 0;
+            }
+            let is_match = (flags >> flag_bit) & 1 == 1;
+            flag_bit += 1;
+
+            if !is_match {
+                result.push(*src.get(pos).ok_or(CompressorError::IncorrectSrcValue)?);
+                pos += 1;
+                continue;
+            }
+
+            if pos + 1 + offset_size > src.len() {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            let len = src[pos] as usize + MIN_MATCH;
+            let mut offset = [0u8; 4];
+            offset[..offset_size].copy_from_slice(&src[pos + 1..pos + 1 + offset_size]);
+            let distance = u32::from_le_bytes(offset) as usize + 1;
+            pos += 1 + offset_size;
+
+            if distance > result.len() || result.len() + len > length {
+                return Err(CompressorError::DecompressErrorWithCode);
+            }
+
+            let start = result.len() - distance;
+            for i in 0..len {
+                result.push(result[start + i]);
+            }
+        }
+
+        result.drain(..prefix.len());
+        Ok(result)
+    }
+}
+
+
+#[cfg(test)]
+mod lz77_test {
+    use super::*;
+
+    #[test]
+    fn compress_res() {
+        let lz77 = LZ77::with_level(1);
+
+        let mut data = b"abc".repeat(20);
+        data.push(b'x');
+
+        let compressed = lz77.compress(&data);
+        let expected = vec![
+            LZ77_MARKER, 16, 61, 0, 0, 0, 0, 0, 0, 0,
+            0b1000, b'a', b'b', b'c', 54, 2, 0, b'x',
+        ];
+
+        assert_eq!(compressed, expected);
+        assert_eq!(lz77.decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn compress_levels() {
+        let data = b"to be or not to be, that is the question; to be or not to be".repeat(40);
+
+        for level in MIN_LEVEL..=MAX_LEVEL {
+            for window_log in [MIN_WINDOW_LOG, 16, 20] {
+                let lz77 = LZ77::with_level(level).with_window_log(window_log);
+                let compressed = lz77.compress(&data);
+
+                assert_eq!(compressed[1], window_log);
+                assert!(compressed.len() < data.len() / 4);
+                assert_eq!(lz77.decompress(&compressed).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn clamp_params() {
+        let lz77 = LZ77::with_level(0).with_window_log(40);
+
+        assert_eq!(lz77.level(), MIN_LEVEL);
+        assert_eq!(lz77.window_log(), MAX_WINDOW_LOG);
+    }
+
+    #[test]
+    fn decompress_errors() {
+        let lz77 = LZ77::new();
+
+        // match pointing before the start of the output
+        let r = lz77.decompress(&[LZ77_MARKER, 16, 5, 0, 0, 0, 0, 0, 0, 0, 0b10, b'a', 0, 3, 0]);
+        assert_eq!(r.unwrap_err(), CompressorError::DecompressErrorWithCode);
+
+        let r = lz77.decompress(&[LZ77_MARKER, 16, 5, 0, 0, 0, 0, 0, 0, 0, 0, b'a']);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let r = lz77.decompress(&[LZ77_MARKER, 30, 1, 0, 0, 0, 0, 0, 0, 0, 0, b'a']);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let r = lz77.decompress(&[0x02]);
+        assert_eq!(r.unwrap_err(), CompressorError::DecompressErrorWithCode);
+    }
+}
@@ -1,11 +1,21 @@
 use super::CompressorError;
 use super::Compressor;
+use super::CompressorOptions;
+use super::check_output_size;
+use super::registry::CODEC_LZW;
+use super::dictionary::Dictionary;
 
 use std::collections::HashMap;
+use std::sync::Arc;
+
+const LZW_MARKER: u8 = 0xf0;
+/// Streams compressed with a dictionary carry its id after the marker.
+const LZW_DICT_MARKER: u8 = 0xe0;
 
 #[derive(Debug)]
 pub struct LZW {
     init_dict: HashMap<Vec<u8>, u32>,
+    dictionary_id: Option<u32>,
 }
 
 impl LZW {
@@ -22,9 +32,49 @@ impl LZW {
     pub fn new() -> LZW {
         LZW{
             init_dict: LZW::get_default_dict(),
+            dictionary_id: None,
+        }
+    }
+
+    /// Starts from the words LZW learns while compressing `dictionary`,
+    /// instead of the 256 single bytes.
+    pub fn primed(dictionary: &Dictionary) -> LZW {
+        let mut dict = LZW::get_default_dict();
+
+        let mut key: Vec<u8> = Vec::new();
+        for &symbol in dictionary.content() {
+            let mut word = key.clone();
+            word.push(symbol);
+
+            if dict.contains_key(&word) {
+                key = word;
+            } else {
+                dict.insert(word, dict.len() as u32);
+                key = vec![symbol];
+            }
+        }
+
+        LZW {
+            init_dict: dict,
+            dictionary_id: Some(dictionary.id()),
         }
     }
 
+    /// Words of `init_dict` ordered by their code.
+    fn get_decode_dict(&self) -> Vec<Vec<u8>> {
+        let mut dict: Vec<Vec<u8>> = vec![Vec::new(); self.init_dict.len()];
+        for (word, &code) in &self.init_dict {
+            dict[code as usize] = word.clone();
+        }
+
+        dict
+    }
+
+    /// LZW has no tuning knobs, the options are accepted for uniformity.
+    pub fn from_options(_options: &CompressorOptions) -> LZW {
+        LZW::new()
+    }
+
     pub fn compress(&self, src: &[u8]) -> Vec<u8> {
         Compressor::compress(self, src)
     }
@@ -35,6 +85,22 @@ impl LZW {
 }
 
 impl Compressor for LZW {
+    fn options(&self) -> CompressorOptions {
+        CompressorOptions::default()
+    }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_LZW
+    }
+
+    fn with_dictionary(&self, dictionary: &Dictionary) -> Option<Arc<dyn Compressor>> {
+        Some(Arc::new(LZW::primed(dictionary)))
+    }
+
+    fn dictionary_id(&self) -> Option<u32> {
+        self.dictionary_id
+    }
+
     fn compress(&self, src: &[u8]) -> Vec<u8> {
         if src.len() == 0{
             return Vec::new();
@@ -80,7 +146,8 @@ impl Compressor for LZW {
         }
 
         let size = (pow + 7) / 8;
-        let result_len = (writes * size + 2) as usize;
+        let header_len = if self.dictionary_id.is_some() { 6 } else { 2 };
+        let result_len = (writes * size + header_len) as usize;
         if result_len > src.len() {
             let mut dst: Vec<u8> = Vec::with_capacity(1+src.len());
             dst.push(0xff);
@@ -90,7 +157,13 @@ impl Compressor for LZW {
         }
 
         let mut result = Vec::with_capacity(result_len);
-        result.push(0xf0);
+        match self.dictionary_id {
+            Some(id) => {
+                result.push(LZW_DICT_MARKER);
+                result.extend_from_slice(&id.to_le_bytes());
+            },
+            None => result.push(LZW_MARKER),
+        }
         result.push(size as u8);
         for i in 0..writes as usize{
             for j in 0..size as usize {
@@ -103,30 +176,51 @@ impl Compressor for LZW {
     }
 
     fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        self.decompress_limited(src, usize::MAX)
+    }
+
+    fn decompress_limited(&self, src: &[u8], max_size: usize) -> Result<Vec<u8>, CompressorError> {
         if src.len() == 0{
             return Ok(Vec::new());
         }
 
         if src[0] == 0xff {
+            check_output_size(src.len() - 1, max_size)?;
             let dst: Vec<u8> = src[1..].to_vec();
         
             return Ok(dst)
-        } else if src[0] != 0xf0 {
+        } else if src[0] != LZW_MARKER && src[0] != LZW_DICT_MARKER {
             return Err(CompressorError::DecompressErrorWithCode)
         }
+
+        // the code size byte and the codes follow the marker and the dictionary id
+        let body = if src[0] == LZW_DICT_MARKER {
+            if src.len() < 5 {
+                return Err(CompressorError::IncorrectSrcValue);
+            }
+            let id = u32::from_le_bytes(src[1..5].try_into().unwrap());
+            if self.dictionary_id != Some(id) {
+                return Err(CompressorError::DictionaryMismatch);
+            }
+            &src[5..]
+        } else if self.dictionary_id.is_some() {
+            return Err(CompressorError::DictionaryMismatch);
+        } else {
+            &src[1..]
+        };
         
-        let size: usize = src[1] as usize;
-        if (src.len() - 2) % size != 0{
+        if body.len() < 2 {
             return Err(CompressorError::IncorrectSrcValue);
         }
 
-        let mut dict: Vec<Vec<u8>> = Vec::with_capacity(256);
-    
-        for word in 0..256 {
-            dict.push(vec![word as u8]);
+        let size: usize = body[0] as usize;
+        if !(1..=4).contains(&size) || (body.len() - 1) % size != 0{
+            return Err(CompressorError::IncorrectSrcValue);
         }
 
-        let mut src = src[2..]
+        let mut dict = self.get_decode_dict();
+
+        let mut src = body[1..]
             .chunks(size)
             .map(|chunk| {
                 let mut array = [0u8; 4];
@@ -135,7 +229,9 @@ impl Compressor for LZW {
             });
     
         let first_code = src.next().unwrap();
-        let mut var = dict[first_code as usize].clone();
+        let mut var = dict.get(first_code as usize)
+            .ok_or(CompressorError::DecompressErrorWithCode)?
+            .clone();
         let mut result: Vec<u8> = var.clone();
     
         for code in src {
@@ -150,6 +246,7 @@ impl Compressor for LZW {
                 return Err(CompressorError::DecompressErrorWithCode);
             }
     
+            check_output_size(result.len() + entry.len(), max_size)?;
             result.extend(&entry);
     
             let mut new_entry: Vec<u8> = var.clone();
@@ -202,6 +299,14 @@ mod lzw_test {
         assert!(r.is_err());
         assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
 
+        let compressed: Vec<u8> = vec![0xf0, 2,0,1];
+        let r = lzw.decompress(&compressed);
+        assert_eq!(r.unwrap_err(), CompressorError::DecompressErrorWithCode);
+
+        let compressed: Vec<u8> = vec![0xf0, 0];
+        let r = lzw.decompress(&compressed);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
         let compressed: Vec<u8> = vec![0xf0, 3,1,0,0];
         let r = lzw.decompress(&compressed);
 
@@ -0,0 +1,633 @@
+use super::CompressorError;
+use super::Compressor;
+use super::CompressorOptions;
+use super::check_output_size;
+use super::registry::CODEC_RANGE;
+
+const RANGE_MARKER: u8 = 0xf2;
+const STORED_MARKER: u8 = 0xff;
+
+const PROB_BITS: u32 = 11;
+/// Initial value of the probabilities passed to [`RangeEncoder::encode_bit`].
+pub const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+const MOVE_BITS: u32 = 5;
+const TOP: u32 = 1 << 24;
+
+/// Precision of the probabilities the model drives the coder with.
+const MODEL_BITS: u32 = 12;
+/// Bounds (log2) of the tables of the hashed orders, sized from the input length.
+const MIN_HASH_BITS: u32 = 12;
+const MAX_HASH_BITS: u32 = 22;
+/// Updates after which a counter stops slowing down.
+const COUNTER_LIMIT: u32 = 255;
+/// A counter at one half which has seen no bits.
+const COUNTER_INIT: u32 = 1 << 31;
+/// Initial weight of every order in the mixer, a quarter in 16.16 fixed point.
+const WEIGHT_INIT: i32 = 1 << 14;
+/// Learning rate of the mixer.
+const MIXER_SHIFT: u32 = 11;
+
+pub const MAX_ORDER: u8 = 6;
+pub const DEFAULT_ORDER: u8 = 4;
+
+/// RangeEncoder is an adaptive binary range coder.
+/// Probabilities are 11 bit estimates of the bit being zero.
+#[derive(Debug)]
+pub struct RangeEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    dst: Vec<u8>,
+}
+
+impl RangeEncoder {
+    pub fn new() -> RangeEncoder {
+        RangeEncoder::with_buffer(Vec::new())
+    }
+
+    /// Creates an encoder which appends its output to `dst`.
+    pub fn with_buffer(dst: Vec<u8>) -> RangeEncoder {
+        RangeEncoder {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            dst,
+        }
+    }
+
+    /// Encodes `bit` and adapts `prob` towards it.
+    pub fn encode_bit(&mut self, prob: &mut u16, bit: u32) {
+        let bound = (self.range >> PROB_BITS) * (*prob as u32);
+
+        if bit == 0 {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+            *prob -= *prob >> MOVE_BITS;
+        }
+
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    /// Encodes `bit` with `prob`, a 12 bit probability of a zero bit given by a model.
+    pub fn encode_bit_with(&mut self, prob: u32, bit: u32) {
+        let bound = (self.range >> MODEL_BITS) * prob.clamp(1, (1 << MODEL_BITS) - 1);
+
+        if bit == 0 {
+            self.range = bound;
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+        }
+
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    /// Encodes the `count` lowest bits of `value` with fixed equal probabilities.
+    pub fn encode_direct(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 == 1 {
+                self.low += self.range as u64;
+            }
+
+            while self.range < TOP {
+                self.range <<= 8;
+                self.shift_low();
+            }
+        }
+    }
+
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xff000000 || (self.low >> 32) != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut temp = self.cache;
+            loop {
+                self.dst.push(temp.wrapping_add(carry));
+                temp = 0xff;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+
+        self.cache_size += 1;
+        self.low = (self.low & 0x00ffffff) << 8;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.dst
+    }
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        RangeEncoder::new()
+    }
+}
+
+/// RangeDecoder reads a stream written by [`RangeEncoder`].
+#[derive(Debug)]
+pub struct RangeDecoder<'a> {
+    code: u32,
+    range: u32,
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RangeDecoder<'a> {
+    pub fn new(src: &'a [u8]) -> Result<RangeDecoder<'a>, CompressorError> {
+        if src.len() < 5 || src[0] != 0 {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+
+        let mut decoder = RangeDecoder {
+            code: 0,
+            range: u32::MAX,
+            src,
+            pos: 1,
+        };
+        for _ in 0..4 {
+            decoder.code = (decoder.code << 8) | decoder.next_byte()? as u32;
+        }
+
+        Ok(decoder)
+    }
+
+    fn next_byte(&mut self) -> Result<u8, CompressorError> {
+        let byte = *self.src.get(self.pos).ok_or(CompressorError::IncorrectSrcValue)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn normalize(&mut self) -> Result<(), CompressorError> {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte()? as u32;
+        }
+        Ok(())
+    }
+
+    pub fn decode_bit(&mut self, prob: &mut u16) -> Result<u32, CompressorError> {
+        let bound = (self.range >> PROB_BITS) * (*prob as u32);
+
+        let bit = if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+            0
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            *prob -= *prob >> MOVE_BITS;
+            1
+        };
+
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    /// Decodes a bit encoded with [`RangeEncoder::encode_bit_with`].
+    pub fn decode_bit_with(&mut self, prob: u32) -> Result<u32, CompressorError> {
+        let bound = (self.range >> MODEL_BITS) * prob.clamp(1, (1 << MODEL_BITS) - 1);
+
+        let bit = if self.code < bound {
+            self.range = bound;
+            0
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    pub fn decode_direct(&mut self, count: u32) -> Result<u32, CompressorError> {
+        let mut value = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+            value = (value << 1) | bit;
+
+            self.normalize()?;
+        }
+        Ok(value)
+    }
+}
+
+
+/// Probabilities of a bit being one, in 12 bits, at the stretched points -2047,
+/// -1919, ..., 2047. The values in between are interpolated.
+const SQUASH_POINTS: [i32; 33] = [
+    1, 2, 3, 6, 10, 16, 27, 45, 73, 120, 194, 310, 488, 747, 1101, 1546,
+    2047, 2549, 2994, 3348, 3607, 3785, 3901, 3975, 4022, 4050, 4068, 4079,
+    4085, 4089, 4092, 4093, 4094,
+];
+
+/// Logistic function mapping a stretched probability in `-2047..=2047` to 12 bits.
+fn squash(d: i32) -> i32 {
+    if d > 2047 {
+        return 4095;
+    }
+    if d < -2047 {
+        return 1;
+    }
+
+    let w = d & 127;
+    let i = ((d >> 7) + 16) as usize;
+    (SQUASH_POINTS[i] * (128 - w) + SQUASH_POINTS[i + 1] * w + 64) >> 7
+}
+
+/// Inverse of [`squash`], `ln(p / (1 - p))` for every 12 bit probability.
+fn stretch_table() -> Vec<i16> {
+    let mut table = vec![2047i16; 1 << MODEL_BITS];
+    let mut next = 0;
+    for d in -2047..=2047 {
+        let p = squash(d) as usize;
+        for entry in table.iter_mut().take(p + 1).skip(next) {
+            *entry = d as i16;
+        }
+        next = next.max(p + 1);
+    }
+    table
+}
+
+/// Adaptation rates of the counters, `2^16 / (n + 1.5)` after `n` updates.
+fn rate_table() -> Vec<i32> {
+    (0..=COUNTER_LIMIT).map(|n| 131072 / (2 * n as i32 + 3)).collect()
+}
+
+/// Probability of a one bit in 12 bits. A counter holds the probability in its
+/// high 22 bits and the number of updates in the low 10 bits.
+fn counter_p(counter: u32) -> usize {
+    (counter >> (32 - MODEL_BITS)) as usize
+}
+
+/// Moves the counter towards `bit`, fast at first and by about
+/// `1 / COUNTER_LIMIT` once it has seen that many bits.
+fn counter_update(counter: &mut u32, bit: u32, rates: &[i32]) {
+    let n = *counter & 1023;
+    let p = (*counter >> 10) as i64;
+    let target = if bit == 1 { (1 << 22) - 1 } else { 0 };
+    let p = p + (((target - p) * rates[n as usize] as i64) >> 16);
+    *counter = ((p as u32) << 10) | (n + 1).min(COUNTER_LIMIT);
+}
+
+/// ContextModel predicts every bit of the next byte from the contexts of
+/// orders 0 to `order`: the bits of the byte seen so far, preceded by up to
+/// `order` bytes. Each order keeps an adaptive counter per context and a
+/// mixer weighs their stretched predictions, so long contexts are trusted
+/// where they have been right and short ones fill in where they are new.
+///
+/// Orders 0 and 1 are indexed directly. Longer contexts are hashed once per
+/// half byte into buckets of 16 counters, their tables grow with the input.
+struct ContextModel {
+    order: usize,
+    tables: Vec<Vec<u32>>,
+    hash_bits: u32,
+    /// Bucket of every hashed order for the current half byte.
+    buckets: [usize; MAX_ORDER as usize + 1],
+    /// Counter of every order used for the current bit.
+    indices: [usize; MAX_ORDER as usize + 1],
+    inputs: [i32; MAX_ORDER as usize + 2],
+    /// One set of weights for every partial byte.
+    weights: Vec<i32>,
+    history: u64,
+    /// Bits of the current byte behind a leading one.
+    partial: usize,
+    p: i32,
+    stretch: Vec<i16>,
+    rates: Vec<i32>,
+}
+
+impl ContextModel {
+    /// Creates a model for an input of `length` bytes.
+    fn new(order: u8, length: usize) -> ContextModel {
+        let order = order.min(MAX_ORDER) as usize;
+        // about two buckets per byte and order, each byte starts two of them
+        let hash_bits = (length.max(1) * 32).next_power_of_two().trailing_zeros().clamp(MIN_HASH_BITS, MAX_HASH_BITS);
+
+        let tables = (0..=order)
+            .map(|k| match k {
+                0 => vec![COUNTER_INIT; 256],
+                1 => vec![COUNTER_INIT; 1 << 16],
+                _ => vec![COUNTER_INIT; 1 << hash_bits],
+            })
+            .collect();
+
+        let mut model = ContextModel {
+            order,
+            tables,
+            hash_bits,
+            buckets: [0; MAX_ORDER as usize + 1],
+            indices: [0; MAX_ORDER as usize + 1],
+            inputs: [0; MAX_ORDER as usize + 2],
+            weights: vec![WEIGHT_INIT; 256 * (order + 2)],
+            history: 0,
+            partial: 1,
+            p: 2048,
+            stretch: stretch_table(),
+            rates: rate_table(),
+        };
+        model.hash_buckets();
+        model
+    }
+
+    /// Finds the buckets of the hashed orders for the current half byte.
+    fn hash_buckets(&mut self) {
+        let nibble = if self.partial >= 16 { self.partial as u64 } else { 0 };
+        for k in 2..=self.order {
+            let context = if k >= 8 { self.history } else { self.history & ((1 << (8 * k)) - 1) };
+            let hash = (context ^ (nibble << 56) ^ ((k as u64) << 60)).wrapping_mul(0x9e3779b97f4a7c15);
+            self.buckets[k] = ((hash >> (64 - (self.hash_bits - 4))) << 4) as usize;
+        }
+    }
+
+    /// Probability of the next bit being one, in 12 bits.
+    fn predict(&mut self) -> i32 {
+        // the bits of the current half byte behind a leading one
+        let node = if self.partial >= 16 {
+            let known = usize::BITS - self.partial.leading_zeros() - 5;
+            (1 << known) | (self.partial & ((1 << known) - 1))
+        } else {
+            self.partial
+        };
+        self.indices[0] = self.partial;
+        if self.order >= 1 {
+            self.indices[1] = ((self.history as usize & 0xff) << 8) | self.partial;
+        }
+        for k in 2..=self.order {
+            self.indices[k] = self.buckets[k] + node;
+        }
+
+        let n = self.order + 2;
+        for k in 0..=self.order {
+            self.inputs[k] = self.stretch[counter_p(self.tables[k][self.indices[k]])] as i32;
+        }
+        self.inputs[self.order + 1] = 256;
+
+        let weights = &self.weights[self.partial * n..(self.partial + 1) * n];
+        let dot: i64 = self.inputs[..n].iter().zip(weights).map(|(&x, &w)| x as i64 * w as i64).sum();
+        self.p = squash((dot >> 16).clamp(-2047, 2047) as i32);
+        self.p
+    }
+
+    fn update(&mut self, bit: u32) {
+        let n = self.order + 2;
+        let error = ((bit as i32) << MODEL_BITS) - self.p;
+        let weights = &mut self.weights[self.partial * n..(self.partial + 1) * n];
+        for (w, &x) in weights.iter_mut().zip(&self.inputs[..n]) {
+            *w += (x * error) >> MIXER_SHIFT;
+        }
+
+        for k in 0..=self.order {
+            counter_update(&mut self.tables[k][self.indices[k]], bit, &self.rates);
+        }
+
+        self.partial = (self.partial << 1) | bit as usize;
+        if self.partial >= 256 {
+            self.history = (self.history << 8) | (self.partial & 0xff) as u64;
+            self.partial = 1;
+            self.hash_buckets();
+        } else if self.partial >= 16 && self.partial < 32 {
+            self.hash_buckets();
+        }
+    }
+
+    fn encode(&mut self, encoder: &mut RangeEncoder, byte: u8) {
+        for i in (0..8).rev() {
+            let bit = ((byte >> i) & 1) as u32;
+            let p = self.predict();
+            encoder.encode_bit_with((1 << MODEL_BITS) - p as u32, bit);
+            self.update(bit);
+        }
+    }
+
+    fn decode(&mut self, decoder: &mut RangeDecoder) -> Result<u8, CompressorError> {
+        for _ in 0..8 {
+            let p = self.predict();
+            let bit = decoder.decode_bit_with((1 << MODEL_BITS) - p as u32)?;
+            self.update(bit);
+        }
+
+        Ok(self.history as u8)
+    }
+}
+
+
+/// RangeCoder compresses bytes with adaptive context models of orders 0 to N
+/// mixed into one prediction, which drives a binary range coder. The order is
+/// stored in the stream header.
+#[derive(Debug)]
+pub struct RangeCoder {
+    order: u8,
+}
+
+impl RangeCoder {
+    pub fn new() -> RangeCoder {
+        RangeCoder { order: DEFAULT_ORDER }
+    }
+
+    /// Creates a coder with the given context order, clamped to [`MAX_ORDER`].
+    pub fn with_order(order: u8) -> RangeCoder {
+        RangeCoder { order: order.min(MAX_ORDER) }
+    }
+
+    /// Uses `options.order` if given, otherwise higher levels mix longer contexts.
+    /// Every order adds to the ones below it, so higher levels are slower but never worse.
+    pub fn from_options(options: &CompressorOptions) -> RangeCoder {
+        let order = options.order.unwrap_or(match options.level {
+            0..=1 => 1,
+            2..=3 => 2,
+            4..=5 => 3,
+            6..=7 => 4,
+            8 => 5,
+            _ => 6,
+        });
+
+        RangeCoder::with_order(order)
+    }
+
+    pub fn order(&self) -> u8 {
+        self.order
+    }
+
+    pub fn compress(&self, src: &[u8]) -> Vec<u8> {
+        Compressor::compress(self, src)
+    }
+
+    pub fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        Compressor::decompress(self, src)
+    }
+}
+
+impl Default for RangeCoder {
+    fn default() -> Self {
+        RangeCoder::new()
+    }
+}
+
+impl Compressor for RangeCoder {
+    fn options(&self) -> CompressorOptions {
+        CompressorOptions {
+            order: Some(self.order),
+            ..CompressorOptions::default()
+        }
+    }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_RANGE
+    }
+
+    fn compress(&self, src: &[u8]) -> Vec<u8> {
+        if src.is_empty() {
+            return Vec::new();
+        }
+
+        let mut header = Vec::with_capacity(10);
+        header.push(RANGE_MARKER);
+        header.push(self.order);
+        header.extend_from_slice(&(src.len() as u64).to_le_bytes());
+
+        let mut model = ContextModel::new(self.order, src.len());
+        let mut encoder = RangeEncoder::with_buffer(header);
+        for &byte in src {
+            model.encode(&mut encoder, byte);
+        }
+
+        let result = encoder.finish();
+        if result.len() > src.len() {
+            let mut dst: Vec<u8> = Vec::with_capacity(1 + src.len());
+            dst.push(STORED_MARKER);
+            dst.extend_from_slice(src);
+
+            return dst;
+        }
+
+        result
+    }
+
+    fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, CompressorError> {
+        self.decompress_limited(src, usize::MAX)
+    }
+
+    fn decompress_limited(&self, src: &[u8], max_size: usize) -> Result<Vec<u8>, CompressorError> {
+        if src.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if src[0] == STORED_MARKER {
+            check_output_size(src.len() - 1, max_size)?;
+            return Ok(src[1..].to_vec());
+        } else if src[0] != RANGE_MARKER {
+            return Err(CompressorError::DecompressErrorWithCode);
+        }
+
+        if src.len() < 10 || src[1] > MAX_ORDER {
+            return Err(CompressorError::IncorrectSrcValue);
+        }
+        let order = src[1];
+        let length = u64::from_le_bytes(src[2..10].try_into().unwrap()) as usize;
+        check_output_size(length, max_size)?;
+
+        let mut model = ContextModel::new(order, length);
+        let mut decoder = RangeDecoder::new(&src[10..])?;
+
+        let mut result = Vec::with_capacity(length.min(src.len() * 64));
+        for _ in 0..length {
+            result.push(model.decode(&mut decoder)?);
+        }
+
+        Ok(result)
+    }
+}
+
+
+#[cfg(test)]
+mod range_test {
+    use super::*;
+
+    #[test]
+    fn encode_decode_bits() {
+        let bits: Vec<u32> = (0..1000u32).map(|i| ((i * 7) % 13 < 3) as u32).collect();
+
+        let mut encoder = RangeEncoder::new();
+        let mut prob = PROB_INIT;
+        for &bit in &bits {
+            encoder.encode_bit(&mut prob, bit);
+        }
+        encoder.encode_direct(0x2a5, 10);
+        let data = encoder.finish();
+
+        assert!(data.len() < bits.len() / 8);
+
+        let mut decoder = RangeDecoder::new(&data).unwrap();
+        let mut prob = PROB_INIT;
+        for &bit in &bits {
+            assert_eq!(decoder.decode_bit(&mut prob).unwrap(), bit);
+        }
+        assert_eq!(decoder.decode_direct(10).unwrap(), 0x2a5);
+    }
+
+    #[test]
+    fn compress_res() {
+        let text = b"the quick brown fox jumps over the lazy dog. ".repeat(20);
+
+        for order in 0..=MAX_ORDER {
+            let coder = RangeCoder::with_order(order);
+            let compressed = coder.compress(&text);
+
+            assert_eq!(compressed[0], RANGE_MARKER);
+            assert_eq!(compressed[1], order);
+            assert!(compressed.len() < text.len() * 3 / 4);
+            assert_eq!(coder.decompress(&compressed).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn hash_table_size() {
+        // small inputs get small tables, large ones stop growing
+        let model = ContextModel::new(MAX_ORDER, 10);
+        assert_eq!(model.tables.len(), MAX_ORDER as usize + 1);
+        assert_eq!(model.tables[2].len(), 1 << MIN_HASH_BITS);
+
+        assert_eq!(ContextModel::new(3, 1 << 16).tables[3].len(), 1 << 21);
+        assert_eq!(ContextModel::new(3, 1 << 30).tables[3].len(), 1 << MAX_HASH_BITS);
+        assert_eq!(ContextModel::new(1, 1 << 30).tables.len(), 2);
+    }
+
+    #[test]
+    fn decompress_errors() {
+        let coder = RangeCoder::new();
+
+        let r = coder.decompress(&[RANGE_MARKER, 9, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let r = coder.decompress(&[RANGE_MARKER, 2, 100, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4]);
+        assert_eq!(r.unwrap_err(), CompressorError::IncorrectSrcValue);
+
+        let r = coder.decompress(&[0x12, 2]);
+        assert_eq!(r.unwrap_err(), CompressorError::DecompressErrorWithCode);
+    }
+}
@@ -0,0 +1,140 @@
+use super::{Compressor, CompressorError, CompressorOptions};
+use super::lzw::LZW;
+use super::huffman::Huffman;
+use super::range::RangeCoder;
+use super::bwt::Bwt;
+use super::lz77::LZ77;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Reserved for data stored without compression.
+pub const CODEC_NONE: u8 = 0;
+/// LZW keeps id 1, so archives written with the old `compressed` flag stay readable.
+pub const CODEC_LZW: u8 = 1;
+pub const CODEC_HUFFMAN: u8 = 2;
+pub const CODEC_RANGE: u8 = 3;
+pub const CODEC_BWT: u8 = 4;
+pub const CODEC_LZ77: u8 = 5;
+
+pub type CompressorFactory = Arc<dyn Fn(&CompressorOptions) -> Arc<dyn Compressor> + Send + Sync>;
+
+struct Entry {
+    name: String,
+    factory: CompressorFactory,
+}
+
+/// Registry maps codec identifiers stored in archives to compressor factories.
+pub struct Registry {
+    codecs: BTreeMap<u8, Entry>,
+}
+
+impl Registry {
+    /// Creates a registry without any codecs.
+    pub fn empty() -> Registry {
+        Registry { codecs: BTreeMap::new() }
+    }
+
+    /// Creates a registry with every codec of this crate.
+    pub fn new() -> Registry {
+        let mut registry = Registry::empty();
+
+        let builtin: [(u8, &str, CompressorFactory); 5] = [
+            (CODEC_LZW, "lzw", Arc::new(|o| Arc::new(LZW::from_options(o)))),
+            (CODEC_HUFFMAN, "huffman", Arc::new(|o| Arc::new(Huffman::from_options(o)))),
+            (CODEC_RANGE, "range", Arc::new(|o| Arc::new(RangeCoder::from_options(o)))),
+            (CODEC_BWT, "bwt", Arc::new(|o| Arc::new(Bwt::from_options(o)))),
+            (CODEC_LZ77, "lz77", Arc::new(|o| Arc::new(LZ77::from_options(o)))),
+        ];
+        for (id, name, factory) in builtin {
+            registry.register(id, name, factory).unwrap();
+        }
+
+        registry
+    }
+
+    /// Adds a codec. Fails if the id is [`CODEC_NONE`] or the id or name is already taken.
+    pub fn register(&mut self, id: u8, name: &str, factory: CompressorFactory) -> Result<(), CompressorError> {
+        if id == CODEC_NONE || self.codecs.contains_key(&id) || self.id(name).is_some() {
+            return Err(CompressorError::CodecAlreadyRegistered);
+        }
+
+        self.codecs.insert(id, Entry { name: name.to_string(), factory });
+        Ok(())
+    }
+
+    pub fn get(&self, id: u8, options: &CompressorOptions) -> Option<Arc<dyn Compressor>> {
+        self.codecs.get(&id).map(|entry| (entry.factory)(options))
+    }
+
+    pub fn get_by_name(&self, name: &str, options: &CompressorOptions) -> Option<Arc<dyn Compressor>> {
+        self.get(self.id(name)?, options)
+    }
+
+    pub fn id(&self, name: &str) -> Option<u8> {
+        self.codecs.iter()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(&id, _)| id)
+    }
+
+    pub fn name(&self, id: u8) -> Option<&str> {
+        self.codecs.get(&id).map(|entry| entry.name.as_str())
+    }
+
+    /// Identifiers of all registered codecs in order.
+    pub fn ids(&self) -> Vec<u8> {
+        self.codecs.keys().copied().collect()
+    }
+
+    /// Names of all registered codecs ordered by id.
+    pub fn names(&self) -> Vec<&str> {
+        self.codecs.values().map(|entry| entry.name.as_str()).collect()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+
+#[cfg(test)]
+mod registry_test {
+    use super::*;
+
+    #[test]
+    fn builtin_res() {
+        let registry = Registry::new();
+        let options = CompressorOptions::default();
+
+        assert_eq!(registry.names(), vec!["lzw", "huffman", "range", "bwt", "lz77"]);
+        assert_eq!(registry.ids(), vec![CODEC_LZW, CODEC_HUFFMAN, CODEC_RANGE, CODEC_BWT, CODEC_LZ77]);
+        for id in [CODEC_LZW, CODEC_HUFFMAN, CODEC_RANGE, CODEC_BWT, CODEC_LZ77] {
+            let compressor = registry.get(id, &options).unwrap();
+            assert_eq!(compressor.codec_id(), id);
+            assert_eq!(registry.id(registry.name(id).unwrap()), Some(id));
+        }
+
+        assert!(registry.get(CODEC_NONE, &options).is_none());
+        assert!(registry.get_by_name("zstd", &options).is_none());
+    }
+
+    #[test]
+    fn register_errors() {
+        let mut registry = Registry::new();
+        let factory: CompressorFactory = Arc::new(|o| Arc::new(LZW::from_options(o)));
+
+        let r = registry.register(CODEC_NONE, "none", factory.clone());
+        assert_eq!(r.unwrap_err(), CompressorError::CodecAlreadyRegistered);
+
+        let r = registry.register(CODEC_LZW, "lzw2", factory.clone());
+        assert_eq!(r.unwrap_err(), CompressorError::CodecAlreadyRegistered);
+
+        let r = registry.register(100, "lzw", factory.clone());
+        assert_eq!(r.unwrap_err(), CompressorError::CodecAlreadyRegistered);
+
+        registry.register(100, "lzw2", factory).unwrap();
+        assert_eq!(registry.get_by_name("lzw2", &CompressorOptions::default()).unwrap().codec_id(), CODEC_LZW);
+    }
+}
@@ -1,10 +1,20 @@
 use rand::Rng;
 
-use compressor::Compressor;
+use compressor::{Compressor, CompressorError, CompressorOptions};
+use compressor::registry::Registry;
+use compressor::filter::Filter;
+use compressor::dictionary::Dictionary;
 use compressor::lzw::LZW;
+use compressor::huffman::Huffman;
+use compressor::range::RangeCoder;
+use compressor::bwt::Bwt;
+use compressor::lz77::LZ77;
 
+use std::path::Path;
 use std::sync::Arc;
 
+const TEXT_PATH: &str = "tests/static/sherlock.txt";
+
 #[test]
 fn compress_decompress_same_value() {
     let mut rng = rand::thread_rng();
@@ -38,3 +48,281 @@ fn compress_decompress_compressor() {
         assert_eq!(uncompressed1, uncompressed2);
     }
 }
+
+#[test]
+fn huffman_compress_decompress_same_value() {
+    let mut rng = rand::thread_rng();
+
+    let huffman: Arc<dyn Compressor> = Arc::new(Huffman::new());
+
+    for _ in 0..50 {
+        let length = rng.gen_range(0..2000);
+        let alphabet = rng.gen_range(1..=255u8);
+        let uncompressed1: Vec<u8> = (0..length).map(|_| rng.gen_range(0..=alphabet)).collect();
+
+        let compressed = huffman.compress(&uncompressed1);
+        let uncompressed2 = huffman.decompress(&compressed).unwrap();
+        assert_eq!(uncompressed1, uncompressed2);
+    }
+}
+
+fn random_text(rng: &mut impl Rng, words: usize) -> Vec<u8> {
+    let dictionary = [
+        "archive", "compress", "the", "of", "sensor", "value", "timestamp", "and",
+        "error", "warning", "temperature", "pressure", "device", "status", "ok", "a",
+    ];
+
+    let mut text = Vec::new();
+    for i in 0..words {
+        text.extend_from_slice(dictionary[rng.gen_range(0..dictionary.len())].as_bytes());
+        text.push(if i % 12 == 11 { b'\n' } else { b' ' });
+    }
+    text
+}
+
+#[test]
+fn range_compress_decompress_same_value() {
+    let mut rng = rand::thread_rng();
+
+    for order in 0..=4 {
+        let coder: Arc<dyn Compressor> = Arc::new(RangeCoder::with_order(order));
+
+        for _ in 0..10 {
+            let length = rng.gen_range(0..2000);
+            let uncompressed1: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+
+            let compressed = coder.compress(&uncompressed1);
+            let uncompressed2 = coder.decompress(&compressed).unwrap();
+            assert_eq!(uncompressed1, uncompressed2);
+        }
+    }
+}
+
+fn read_text() -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join(TEXT_PATH)).unwrap()
+}
+
+#[test]
+fn range_better_than_lzw_on_text() {
+    let text = read_text();
+
+    let lzw = LZW::new().compress(&text);
+    // the fastest level, the others are at least as good
+    let coder = RangeCoder::from_options(&CompressorOptions::with_level(1));
+    let range = coder.compress(&text);
+
+    assert!(range.len() * 10 < lzw.len() * 8, "range: {}, lzw: {}", range.len(), lzw.len());
+    assert_eq!(coder.decompress(&range).unwrap(), text);
+
+    let range = RangeCoder::new().compress(&text);
+    assert!(range.len() * 10 < lzw.len() * 6, "range: {}, lzw: {}", range.len(), lzw.len());
+}
+
+#[test]
+fn range_levels_never_worse() {
+    let text = read_text();
+
+    let sizes: Vec<usize> = (0..=9)
+        .map(|level| RangeCoder::from_options(&CompressorOptions { level, ..CompressorOptions::default() }).compress(&text).len())
+        .collect();
+
+    for level in 1..=9 {
+        assert!(sizes[level] <= sizes[level - 1], "level {}: {:?}", level, sizes);
+    }
+}
+
+#[test]
+fn bwt_compress_decompress_same_value() {
+    let mut rng = rand::thread_rng();
+
+    let bwt: Arc<dyn Compressor> = Arc::new(Bwt::with_block_size(4096).with_threads(4));
+
+    for _ in 0..20 {
+        let length = rng.gen_range(0..20000);
+        let alphabet = rng.gen_range(1..=255u8);
+        let uncompressed1: Vec<u8> = (0..length).map(|_| rng.gen_range(0..=alphabet)).collect();
+
+        let compressed = bwt.compress(&uncompressed1);
+        let uncompressed2 = bwt.decompress(&compressed).unwrap();
+        assert_eq!(uncompressed1, uncompressed2);
+    }
+
+    let text = random_text(&mut rng, 5000);
+    let compressed = bwt.compress(&text);
+    assert_eq!(bwt.decompress(&compressed).unwrap(), text);
+}
+
+#[test]
+fn lz77_compress_decompress_same_value() {
+    let mut rng = rand::thread_rng();
+
+    for level in 1..=9 {
+        let lz77: Arc<dyn Compressor> = Arc::new(LZ77::with_level(level).with_window_log(rng.gen_range(10..=20)));
+
+        for _ in 0..10 {
+            let length = rng.gen_range(0..5000);
+            let alphabet = rng.gen_range(1..=255u8);
+            let uncompressed1: Vec<u8> = (0..length).map(|_| rng.gen_range(0..=alphabet)).collect();
+
+            let compressed = lz77.compress(&uncompressed1);
+            let uncompressed2 = lz77.decompress(&compressed).unwrap();
+            assert_eq!(uncompressed1, uncompressed2);
+        }
+
+        let text = random_text(&mut rng, 5000);
+        let compressed = lz77.compress(&text);
+        assert!(compressed.len() < text.len() / 2);
+        assert_eq!(lz77.decompress(&compressed).unwrap(), text);
+    }
+}
+
+#[test]
+fn options_from_level() {
+    let options = CompressorOptions::with_level(1);
+
+    assert_eq!(LZ77::from_options(&options).level(), 1);
+    assert_eq!(RangeCoder::from_options(&options).order(), 1);
+    assert_eq!(Bwt::from_options(&options).block_size(), 100 * 1000);
+
+    let options = CompressorOptions {
+        window_log: Some(20),
+        block_size: Some(4096),
+        order: Some(3),
+        ..CompressorOptions::with_level(12)
+    };
+
+    assert_eq!(options.level, 9);
+    assert_eq!(LZ77::from_options(&options).options().window_log, Some(20));
+    assert_eq!(RangeCoder::from_options(&options).options().order, Some(3));
+    assert_eq!(Bwt::from_options(&options).options().block_size, Some(4096));
+}
+
+#[test]
+fn options_not_needed_for_decompress() {
+    let mut rng = rand::thread_rng();
+    let text = random_text(&mut rng, 3000);
+
+    let options = CompressorOptions {
+        window_log: Some(12),
+        block_size: Some(2048),
+        order: Some(4),
+        ..CompressorOptions::with_level(9)
+    };
+    let pairs: Vec<(Arc<dyn Compressor>, Arc<dyn Compressor>)> = vec![
+        (Arc::new(LZ77::from_options(&options)), Arc::new(LZ77::new())),
+        (Arc::new(RangeCoder::from_options(&options)), Arc::new(RangeCoder::new())),
+        (Arc::new(Bwt::from_options(&options)), Arc::new(Bwt::new())),
+    ];
+
+    for (tuned, default) in pairs {
+        let compressed = tuned.compress(&text);
+        assert_eq!(default.decompress(&compressed).unwrap(), text);
+    }
+}
+
+#[test]
+fn decompress_limited_output() {
+    let mut rng = rand::thread_rng();
+    let text = random_text(&mut rng, 2000);
+    let noise: Vec<u8> = (0..500).map(|_| rng.gen()).collect();
+
+    let registry = Registry::new();
+    for name in registry.names() {
+        let compressor = registry.get_by_name(name, &CompressorOptions::default()).unwrap();
+
+        for data in [&text, &noise] {
+            let compressed = compressor.compress(data);
+
+            assert_eq!(&compressor.decompress_limited(&compressed, data.len()).unwrap(), data);
+
+            let r = compressor.decompress_limited(&compressed, data.len() - 1);
+            assert_eq!(r.unwrap_err(), CompressorError::OutputLimitExceeded, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn decompress_limited_bomb() {
+    // every code repeats the previous word plus one byte, so n codes expand to n*(n+1)/2 bytes
+    let mut bomb = vec![0xf0, 4];
+    for code in 0..20_000u32 {
+        let code = if code == 0 { 0 } else { 255 + code };
+        bomb.extend_from_slice(&code.to_ne_bytes());
+    }
+
+    let r = LZW::new().decompress_limited(&bomb, 1 << 20);
+    assert_eq!(r.unwrap_err(), CompressorError::OutputLimitExceeded);
+
+    // a range coded stream of a few bytes declaring an exabyte of output
+    let mut bomb = vec![0xf2, 0];
+    bomb.extend_from_slice(&(1u64 << 60).to_le_bytes());
+    bomb.extend_from_slice(&[0; 8]);
+
+    let r = RangeCoder::new().decompress_limited(&bomb, 1 << 20);
+    assert_eq!(r.unwrap_err(), CompressorError::OutputLimitExceeded);
+}
+
+#[test]
+fn filter_executable_same_value() {
+    let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+    let lz77 = LZ77::with_level(1);
+
+    for filter in [Filter::X86, Filter::Arm64, Filter::Delta(4)] {
+        let mut filtered = exe.clone();
+        filter.encode(&mut filtered);
+
+        let mut unfiltered = lz77.decompress(&lz77.compress(&filtered)).unwrap();
+        filter.decode(&mut unfiltered);
+        assert!(unfiltered == exe, "{}", filter);
+    }
+}
+
+fn json_records(rng: &mut impl Rng, n: usize) -> Vec<Vec<u8>> {
+    let statuses = ["active", "disabled", "pending"];
+
+    (0..n).map(|_| {
+        format!(
+            "{{\"id\": {}, \"user\": {{\"name\": \"user{}\", \"email\": \"user{}@example.com\"}}, \"status\": \"{}\", \"tags\": [\"alpha\", \"beta\"]}}",
+            rng.gen_range(0..100000), rng.gen_range(0..1000), rng.gen_range(0..1000), statuses[rng.gen_range(0..3)],
+        ).into_bytes()
+    }).collect()
+}
+
+#[test]
+fn dictionary_small_files() {
+    let mut rng = rand::thread_rng();
+
+    let samples = json_records(&mut rng, 500);
+    let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_slice()).collect();
+    let dictionary = Dictionary::train(&samples, 4096);
+    assert!(!dictionary.is_empty());
+
+    let files = json_records(&mut rng, 50);
+    let compressors: Vec<Arc<dyn Compressor>> = vec![Arc::new(LZW::new()), Arc::new(LZ77::new())];
+
+    for compressor in compressors {
+        let primed = compressor.with_dictionary(&dictionary).unwrap();
+        assert_eq!(primed.dictionary_id(), Some(dictionary.id()));
+
+        let mut plain_size = 0;
+        let mut primed_size = 0;
+        for file in &files {
+            let compressed = primed.compress(file);
+            assert_eq!(&primed.decompress(&compressed).unwrap(), file);
+            assert_eq!(compressor.decompress(&compressed).unwrap_err(), CompressorError::DictionaryMismatch);
+
+            plain_size += compressor.compress(file).len();
+            primed_size += compressed.len();
+        }
+
+        assert!(primed_size < plain_size * 3 / 4, "{} >= {}", primed_size, plain_size);
+
+        let other = compressor.with_dictionary(&Dictionary::new(b"other dictionary".to_vec())).unwrap();
+        let compressed = primed.compress(&files[0]);
+        assert_eq!(other.decompress(&compressed).unwrap_err(), CompressorError::DictionaryMismatch);
+    }
+
+    let huffman = Huffman::new();
+    assert!(huffman.with_dictionary(&dictionary).is_none());
+    assert!(huffman.dictionary_id().is_none());
+}
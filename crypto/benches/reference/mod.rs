@@ -0,0 +1,259 @@
+//! The Threefish core as it was before the allocation-free rewrite, kept only
+//! so the benchmarks can compare both side by side. Every round builds a
+//! `Mix` with a variable shift, the round keys are walked through a slice of
+//! runtime length, and the key schedule and tweak go through a `Vec`.
+
+use crypto::{CipherBlock, CipherError};
+
+const TWEAK_SIZE: usize = 16;
+const C240: u64 = 0x1bd11bdaa9fc1a22;
+
+const R4: [[i64; 2]; 8] = [
+    [14, 16],
+    [52, 57],
+    [23, 40],
+    [5,  37],
+    [25, 33],
+    [46, 12],
+    [58, 22],
+    [32, 32],
+];
+
+const R8: [[i64; 4]; 8] = [
+    [46, 36, 19, 37],
+    [33, 27, 14, 42],
+    [17, 49, 36, 39],
+    [44,  9, 54, 56],
+    [39, 30, 34, 24],
+    [13, 50, 10, 17],
+    [25, 29, 39, 43],
+    [ 8, 35, 56, 22],
+];
+
+const R16: [[i64; 8]; 8] = [
+    [24, 13,  8, 47,  8, 17, 22, 37],
+    [38, 19, 10, 55, 49, 18, 23, 52],
+    [33,  4, 51, 13, 34, 41, 59, 17],
+    [ 5, 20, 48, 41, 47, 28, 16, 25],
+    [41,  9, 37, 31, 12, 47, 44, 30],
+    [16, 34, 56, 51,  4, 53, 42, 41],
+    [31, 44, 47, 46, 19, 42, 44, 25],
+    [ 9, 48, 35, 52, 23, 31, 37, 20],
+];
+
+const PI4: [usize; 4] = [0, 3, 2, 1];
+const PI8: [usize; 8] = [2, 1, 4, 7, 6, 5, 0, 3];
+const PI16: [usize; 16] = [0, 9, 2, 13, 6, 11, 4, 15, 10, 7, 12, 3, 14, 5, 8, 1];
+
+pub type Reference256 = Cipher<4, 2, 19>;
+pub type Reference512 = Cipher<8, 4, 19>;
+pub type Reference1024 = Cipher<16, 8, 21>;
+
+struct Mix {
+    s: i64,
+}
+
+// The shifts are kept exactly as the previous core wrote them.
+#[allow(clippy::manual_rotate)]
+impl Mix {
+    fn with_rotations<const H: usize>(rotations: &[[i64; H]; 8], d: usize, j: usize) -> Mix {
+        Mix { s: rotations[d % 8][j] }
+    }
+
+    fn mix(&self, x0: u64, x1: u64) -> (u64, u64) {
+        let s = self.s;
+        let y0 = x0.wrapping_add(x1);
+        let y1 = ((x1 << s) | (x1 >> (64 - s))) ^ y0;
+        (y0, y1)
+    }
+
+    fn demix(&self, y0: u64, y1: u64) -> (u64, u64) {
+        let s = self.s;
+        let x1 = ((y1 ^ y0) << (64 - s)) | ((y1 ^ y0) >> s);
+        let x0 = y0.wrapping_sub(x1);
+        (x0, x1)
+    }
+}
+
+fn load_word(src: &[u8]) -> u64 {
+    u64::from_le_bytes(src.try_into().expect("Slice length is not 8"))
+}
+
+fn calculate_tweak(tweak: &[u8]) -> Result<Vec<u64>, CipherError> {
+    if tweak.len() != TWEAK_SIZE {
+        return Err(CipherError::InvalidTweakLength);
+    }
+
+    let word0 = load_word(&tweak[0..8]);
+    let word1 = load_word(&tweak[8..16]);
+    Ok(vec![word0, word1, word0 ^ word1])
+}
+
+fn expand_key<const N: usize, const R: usize>(key: &[u8], t: &[u64]) -> [[u64; N]; R] {
+    let mut k = vec![0; N + 1];
+    k[N] = C240;
+    for i in 0..N {
+        k[i] = load_word(&key[i*8..(i+1)*8]);
+        k[N] ^= k[i];
+    }
+
+    let mut ks = [[0; N]; R];
+    for (s, round_key) in ks.iter_mut().enumerate() {
+        for (i, word) in round_key.iter_mut().enumerate() {
+            let last_add = if i == N - 3 {
+                t[s%3]
+            } else if i == N - 2 {
+                t[(s+1)%3]
+            } else if i == N - 1 {
+                s as u64
+            } else {
+                0
+            };
+            *word = k[(s+i)%(N+1)].wrapping_add(last_add);
+        }
+    }
+
+    ks
+}
+
+impl Reference256 {
+    pub fn new(key: &[u8], tweak: &[u8]) -> Result<Self, CipherError> {
+        Cipher::with_tables(key, tweak, &R4, &PI4)
+    }
+}
+
+impl Reference512 {
+    pub fn new(key: &[u8], tweak: &[u8]) -> Result<Self, CipherError> {
+        Cipher::with_tables(key, tweak, &R8, &PI8)
+    }
+}
+
+impl Reference1024 {
+    pub fn new(key: &[u8], tweak: &[u8]) -> Result<Self, CipherError> {
+        Cipher::with_tables(key, tweak, &R16, &PI16)
+    }
+}
+
+/// Threefish with `N` words, `H` pairs and `R` round keys on the previous core.
+pub struct Cipher<const N: usize, const H: usize, const R: usize> {
+    ks: [[u64; N]; R],
+    rotations: &'static [[i64; H]; 8],
+    permutation: &'static [usize; N],
+}
+
+impl<const N: usize, const H: usize, const R: usize> Cipher<N, H, R> {
+    fn with_tables(key: &[u8], tweak: &[u8], rotations: &'static [[i64; H]; 8], permutation: &'static [usize; N]) -> Result<Self, CipherError> {
+        if key.len() != N * 8 {
+            return Err(CipherError::InvalidKeyLength);
+        }
+
+        let t = calculate_tweak(tweak)?;
+        let ks = expand_key(key, &t);
+
+        Ok(Cipher { ks, rotations, permutation })
+    }
+
+    fn encrypt_words(&self, words: &mut [u64; N]) {
+        let ks: &[[u64; N]] = &self.ks;
+        let last = ks.len() - 1;
+
+        for (d, round_key) in ks[..last].iter().enumerate() {
+            for (word, key) in words.iter_mut().zip(round_key) {
+                *word = word.wrapping_add(*key);
+            }
+
+            for i in 0..4 {
+                for j in 0..H {
+                    let mix = Mix::with_rotations(self.rotations, d*4+i, j);
+                    (words[2*j], words[2*j+1]) = mix.mix(words[2*j], words[2*j+1]);
+                }
+
+                let mixed = *words;
+                for (word, &p) in words.iter_mut().zip(self.permutation) {
+                    *word = mixed[p];
+                }
+            }
+        }
+
+        for (word, key) in words.iter_mut().zip(&ks[last]) {
+            *word = word.wrapping_add(*key);
+        }
+    }
+
+    fn decrypt_words(&self, words: &mut [u64; N]) {
+        let ks: &[[u64; N]] = &self.ks;
+        let last = ks.len() - 1;
+
+        for (word, key) in words.iter_mut().zip(&ks[last]) {
+            *word = word.wrapping_sub(*key);
+        }
+
+        for (d, round_key) in ks[..last].iter().enumerate().rev() {
+            for i in (0..4).rev() {
+                let permuted = *words;
+                for (&word, &p) in permuted.iter().zip(self.permutation) {
+                    words[p] = word;
+                }
+
+                for j in (0..H).rev() {
+                    let mix = Mix::with_rotations(self.rotations, d*4+i, j);
+                    (words[2*j], words[2*j+1]) = mix.demix(words[2*j], words[2*j+1]);
+                }
+            }
+
+            for (word, key) in words.iter_mut().zip(round_key) {
+                *word = word.wrapping_sub(*key);
+            }
+        }
+    }
+}
+
+impl<const N: usize, const H: usize, const R: usize> CipherBlock for Cipher<N, H, R> {
+    fn encrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), CipherError> {
+        if src.len() != N * 8 {
+            return Err(CipherError::InvalidPlaintextLength);
+        }
+        if dst.len() < N * 8 {
+            return Err(CipherError::InvalidCiphertextLength);
+        }
+
+        let mut words = [0u64; N];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = load_word(&src[i*8..(i+1)*8]);
+        }
+
+        self.encrypt_words(&mut words);
+
+        for (i, word) in words.iter().enumerate() {
+            dst[i*8..(i+1)*8].copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn decrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), CipherError> {
+        if src.len() != N * 8 {
+            return Err(CipherError::InvalidCiphertextLength);
+        }
+        if dst.len() < N * 8 {
+            return Err(CipherError::InvalidPlaintextLength);
+        }
+
+        let mut words = [0u64; N];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = load_word(&src[i*8..(i+1)*8]);
+        }
+
+        self.decrypt_words(&mut words);
+
+        for (i, word) in words.iter().enumerate() {
+            dst[i*8..(i+1)*8].copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn get_block_size(&self) -> usize {
+        N * 8
+    }
+}
@@ -0,0 +1,115 @@
+mod reference;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use crypto::cbc::CBCProcessor;
+use crypto::ctr::CTRProcessor;
+use crypto::threefish256::Cipher256;
+use crypto::threefish512::Cipher512;
+use crypto::threefish1024::Cipher1024;
+use crypto::{CipherBlock, CipherProcessor};
+
+use reference::{Reference256, Reference512, Reference1024};
+
+use std::hint::black_box;
+use std::sync::Arc;
+
+const DATA_SIZE: usize = 1 << 20;
+
+type Block = Arc<dyn CipherBlock>;
+
+/// Panics unless `reference` encrypts like `cipher`, so both sides of a
+/// comparison compute the same thing.
+fn assert_same_cipher(cipher: &dyn CipherBlock, reference: &dyn CipherBlock) {
+    let bs = cipher.get_block_size();
+    let src: Vec<u8> = (0..bs).map(|i| (i * 7) as u8).collect();
+    let mut expected = vec![0u8; bs];
+    let mut actual = vec![0u8; bs];
+
+    cipher.encrypt(&src, &mut expected).unwrap();
+    reference.encrypt(&src, &mut actual).unwrap();
+    assert_eq!(actual, expected, "reference core differs for block size {}", bs);
+
+    reference.decrypt(&actual, &mut expected).unwrap();
+    assert_eq!(expected, src, "reference core does not decrypt for block size {}", bs);
+}
+
+fn blocks(c: &mut Criterion) {
+    let tweak = [7u8; 16];
+    let ciphers: [(&str, Block, Block); 3] = [
+        ("256", Arc::new(Cipher256::new(&[1; 32], &tweak).unwrap()), Arc::new(Reference256::new(&[1; 32], &tweak).unwrap())),
+        ("512", Arc::new(Cipher512::new(&[1; 64], &tweak).unwrap()), Arc::new(Reference512::new(&[1; 64], &tweak).unwrap())),
+        ("1024", Arc::new(Cipher1024::new(&[1; 128], &tweak).unwrap()), Arc::new(Reference1024::new(&[1; 128], &tweak).unwrap())),
+    ];
+
+    let mut group = c.benchmark_group("block");
+    for (name, cipher, reference) in &ciphers {
+        let bs = cipher.get_block_size();
+        let src = vec![3u8; bs];
+        let mut dst = vec![0u8; bs];
+        assert_same_cipher(cipher.as_ref(), reference.as_ref());
+        group.throughput(Throughput::Bytes(bs as u64));
+
+        group.bench_function(BenchmarkId::new("encrypt", name), |b| {
+            b.iter(|| cipher.encrypt(black_box(&src), &mut dst).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("encrypt_reference", name), |b| {
+            b.iter(|| reference.encrypt(black_box(&src), &mut dst).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("decrypt", name), |b| {
+            b.iter(|| cipher.decrypt(black_box(&src), &mut dst).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("decrypt_reference", name), |b| {
+            b.iter(|| reference.decrypt(black_box(&src), &mut dst).unwrap())
+        });
+    }
+    group.finish();
+
+    let cipher = Cipher256::new(&[1; 32], &tweak).unwrap();
+    let mut block = [3u8; 32];
+    let mut batch = vec![3u8; DATA_SIZE];
+
+    let mut group = c.benchmark_group("in_place");
+    group.throughput(Throughput::Bytes(32));
+    group.bench_function("encrypt_block/256", |b| b.iter(|| cipher.encrypt_block(black_box(&mut block))));
+    group.bench_function("decrypt_block/256", |b| b.iter(|| cipher.decrypt_block(black_box(&mut block))));
+
+    group.throughput(Throughput::Bytes(DATA_SIZE as u64));
+    group.sample_size(20);
+    group.bench_function("encrypt_batch/256", |b| b.iter(|| cipher.encrypt_batch(black_box(&mut batch)).unwrap()));
+    group.bench_function("decrypt_batch/256", |b| b.iter(|| cipher.decrypt_batch(black_box(&mut batch)).unwrap()));
+    group.finish();
+}
+
+fn processors(c: &mut Criterion) {
+    let block = Arc::new(Cipher256::new(&[1; 32], &[7; 16]).unwrap());
+    let cbc = CBCProcessor::new(block.clone(), &[9; 32]).unwrap();
+    let ctr = CTRProcessor::new(block);
+
+    // The reference block only has the per-block encrypt and decrypt, so the
+    // processors fall back to one call and one buffer copy per block.
+    let reference = Arc::new(Reference256::new(&[1; 32], &[7; 16]).unwrap());
+    let cbc_reference = CBCProcessor::new(reference.clone(), &[9; 32]).unwrap();
+    let ctr_reference = CTRProcessor::new(reference);
+
+    let src: Vec<u8> = (0..DATA_SIZE).map(|i| ((i * 31) >> 3) as u8).collect();
+    let cbc_ciphertext = cbc.encrypt_blocks(&src);
+    let ctr_ciphertext = ctr.encrypt_blocks(&src);
+
+    let mut group = c.benchmark_group("processor");
+    group.throughput(Throughput::Bytes(DATA_SIZE as u64));
+    group.sample_size(20);
+
+    group.bench_function("cbc_encrypt", |b| b.iter(|| cbc.encrypt_blocks(black_box(&src))));
+    group.bench_function("cbc_encrypt_reference", |b| b.iter(|| cbc_reference.encrypt_blocks(black_box(&src))));
+    group.bench_function("cbc_decrypt", |b| b.iter(|| cbc.decrypt_blocks(black_box(&cbc_ciphertext)).unwrap()));
+    group.bench_function("cbc_decrypt_reference", |b| b.iter(|| cbc_reference.decrypt_blocks(black_box(&cbc_ciphertext)).unwrap()));
+    group.bench_function("ctr_encrypt", |b| b.iter(|| ctr.encrypt_blocks(black_box(&src))));
+    group.bench_function("ctr_encrypt_reference", |b| b.iter(|| ctr_reference.encrypt_blocks(black_box(&src))));
+    group.bench_function("ctr_decrypt", |b| b.iter(|| ctr.decrypt_blocks(black_box(&ctr_ciphertext)).unwrap()));
+    group.bench_function("ctr_decrypt_reference", |b| b.iter(|| ctr_reference.decrypt_blocks(black_box(&ctr_ciphertext)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, blocks, processors);
+criterion_main!(benches);
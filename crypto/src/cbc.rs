@@ -6,11 +6,18 @@ use super::{CipherBlock,CipherError, CipherProcessor};
 
 use std::sync::Arc;
 
+use rand::Rng;
+use zeroize::Zeroizing;
+
+/// Inputs shorter than this per thread are decrypted on the calling thread.
+const MIN_PARALLEL_CHUNK: usize = 64 * 1024;
+
 #[derive(Clone)]
 pub struct CBCProcessor {
     block: Arc<dyn CipherBlock>,
     iv: Vec<u8>,
     block_size: usize,
+    threads: usize,
 }
 
 impl CBCProcessor {
@@ -23,10 +30,21 @@ impl CBCProcessor {
             block_size: block.get_block_size(),
             block,
             iv:iv.to_vec(),
+            threads: 1,
         };
 
         Ok(cbc)
     }
+
+    /// Decrypts inputs larger than 64KiB per thread with `threads` threads.
+    /// Every plaintext block depends only on two ciphertext blocks, so they are
+    /// decrypted in any order. Encryption stays sequential.
+    pub fn with_threads(self, threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            ..self
+        }
+    }
 }
 
 impl CBCProcessor {
@@ -43,23 +61,33 @@ impl CBCProcessor {
             .zip(src_block.iter())
             .for_each(|(x1, x2)| *x1 ^= x2);
 
-        let _state = state.to_vec();
+        self.block.encrypt_batch(state).unwrap();
+        dst_block.clone_from_slice(state);
+    }
+
+    /// Decrypts the blocks following block `first` of `src` into `dst`.
+    fn decrypt_range(&self, src: &[u8], dst: &mut [u8], first: usize) {
+        let start = first * self.block_size;
 
-        self.block.encrypt(&_state, state).unwrap();
-        dst_block.clone_from_slice(&state);
+        dst.clone_from_slice(&src[start + self.block_size..start + self.block_size + dst.len()]);
+        self.block.decrypt_batch(dst).unwrap();
+
+        dst.iter_mut()
+            .zip(&src[start..])
+            .for_each(|(x1, x2)| *x1 ^= x2);
     }
 
     pub fn encrypt_blocks(&self, src: &[u8]) -> Vec<u8> {
         CipherProcessor::encrypt_blocks(self, src)
     }
 
-    pub fn decrypt_blocks(&self, src: &[u8]) -> Result<Vec<u8>, CipherError>  {
-        CipherProcessor::decrypt_blocks(self, src)
-    }
-}
+    /// Encrypts `src` with `iv` instead of the IV the processor was created with.
+    /// The IV is stored in front of the ciphertext, so decryption needs no change.
+    pub fn encrypt_with_iv(&self, iv: &[u8], src: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if iv.len() != self.block_size {
+            return Err(CipherError::InvalidIVLength);
+        }
 
-impl CipherProcessor for CBCProcessor {
-    fn encrypt_blocks(&self, src: &[u8]) -> Vec<u8> {
         let last_block_size = src.len() % self.block_size;
         let padding_in_last_block = self.block_size - last_block_size;
         let block_count = src.len() / self.block_size;
@@ -75,7 +103,7 @@ impl CipherProcessor for CBCProcessor {
         let mut state = vec![0u8; self.block_size];
 
         // store IV in dst
-        state[..self.block_size].clone_from_slice(&self.iv);
+        state[..self.block_size].clone_from_slice(iv);
         dst[..self.block_size].clone_from_slice(&state);
 
         // encrypt and store blocks except last one
@@ -87,7 +115,7 @@ impl CipherProcessor for CBCProcessor {
         }
 
         // encrypt and store last block with padding
-        let mut last_src = vec![0u8; self.block_size * add_block_count];
+        let mut last_src = Zeroizing::new(vec![0u8; self.block_size * add_block_count]);
         let src_block = &src[block_count*self.block_size..];
         if last_block_size != 0 {
             last_src[..src_block.len()].clone_from_slice(src_block);
@@ -106,7 +134,26 @@ impl CipherProcessor for CBCProcessor {
             self.encrypt_block(src_block, dst_block, &mut state);
         }
 
-        dst
+        Ok(dst)
+    }
+
+    pub fn decrypt_blocks(&self, src: &[u8]) -> Result<Vec<u8>, CipherError>  {
+        CipherProcessor::decrypt_blocks(self, src)
+    }
+}
+
+impl CipherProcessor for CBCProcessor {
+    fn encrypt_blocks(&self, src: &[u8]) -> Vec<u8> {
+        self.encrypt_with_iv(&self.iv, src).unwrap()
+    }
+
+    /// Draws a new IV for every record, so records which start alike do not
+    /// give the same ciphertext.
+    fn encrypt_record(&self, src: &[u8]) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let iv: Vec<u8> = (0..self.block_size).map(|_| rng.gen()).collect();
+
+        self.encrypt_with_iv(&iv, src).unwrap()
     }
 
     fn decrypt_blocks(&self, src: &[u8]) -> Result<Vec<u8>, CipherError> {
@@ -120,7 +167,7 @@ impl CipherProcessor for CBCProcessor {
         //     return Err(CipherError::InvalidIVArePassed);
         // }
 
-        let mut last_block = vec![0u8; self.block_size];
+        let mut last_block = Zeroizing::new(vec![0u8; self.block_size]);
         let s1_block = &src[(block_count-2)*self.block_size..(block_count-1)*self.block_size];
         let s2_block = &src[(block_count-1)*self.block_size..];
 
@@ -129,6 +176,11 @@ impl CipherProcessor for CBCProcessor {
         let offset = self.block_size - PADDING;
         let padding_size = load_word(&last_block[offset..]) as usize;
 
+        // encryption pads with 1 to 2 blocks, anything else comes from a wrong key or corrupt input
+        if padding_size == 0 || padding_size > 2 * self.block_size {
+            return Err(CipherError::InvalidPaddingSize);
+        }
+
         let dst_size = match ((block_count - 1) * self.block_size).checked_sub(padding_size as usize) {
             Some(x) => x,
             None => return Err(CipherError::InvalidPaddingSize),
@@ -141,6 +193,9 @@ impl CipherProcessor for CBCProcessor {
 
         // minus 1 here is for situation when padding_size == 32. If remove this - 1 last block of decrypted text will be zero
         let add_block_count = (padding_size-1) / self.block_size + 1;
+        if add_block_count >= block_count {
+            return Err(CipherError::InvalidPaddingSize);
+        }
 
         if padding_size > self.block_size {
             if block_count < 3 {
@@ -155,11 +210,18 @@ impl CipherProcessor for CBCProcessor {
 
         dst[last_block_offset..].clone_from_slice(&last_block[..last_block_size]);
 
-        for i in (1..block_count-add_block_count).rev() {
-            let s1_block = &src[(i-1)*self.block_size..i*self.block_size];
-            let s2_block = &src[i*self.block_size..(i+1)*self.block_size];
-
-            self.decrypt_block(s1_block, s2_block,&mut dst[(i-1)*self.block_size..i*self.block_size]);
+        let body_blocks = (block_count - add_block_count).saturating_sub(1);
+        let body = &mut dst[..body_blocks*self.block_size];
+
+        let chunk_blocks = body_blocks.div_ceil(self.threads).max(1);
+        if self.threads == 1 || chunk_blocks * self.block_size < MIN_PARALLEL_CHUNK {
+            self.decrypt_range(src, body, 0);
+        } else {
+            std::thread::scope(|scope| {
+                for (n, chunk) in body.chunks_mut(chunk_blocks * self.block_size).enumerate() {
+                    scope.spawn(move || self.decrypt_range(src, chunk, n * chunk_blocks));
+                }
+            });
         }
 
 
@@ -245,6 +307,25 @@ mod cbc_encrypter_test {
 
         assert_eq!(ciphertext,expected);
     }
+
+    #[test]
+    fn encrypt_record_fresh_iv(){
+        let key: Vec<u8> = (0..32).collect();
+        let block: Arc<dyn CipherBlock> = Arc::new(Cipher256::new(&key, &[0; 16]).unwrap());
+        let cbc = CBCProcessor::new(block, &[7; 32]).unwrap();
+
+        let header = [5u8; 40];
+        let first = cbc.encrypt_record(&header);
+        let second = cbc.encrypt_record(&header);
+
+        assert_eq!(first.len(), cbc.encrypt_blocks(&header).len());
+        assert_ne!(first[..32], second[..32], "IVs must differ");
+        assert_ne!(first[32..64], second[32..64], "first blocks must differ");
+        assert_eq!(cbc.decrypt_blocks(&first).unwrap(), header);
+        assert_eq!(cbc.decrypt_blocks(&second).unwrap(), header);
+
+        assert_eq!(cbc.encrypt_with_iv(&[7; 31], &header).unwrap_err(), CipherError::InvalidIVLength);
+    }
 }
 
 
@@ -253,6 +334,32 @@ mod cbc_decrypter_test {
     use crate::threefish256::Cipher256;
     use super::*;
 
+    #[test]
+    fn decrypt_padding_errors(){
+        let key: Vec<u8> = (0..32).collect();
+        let block: Arc<dyn CipherBlock> = Arc::new(Cipher256::new(&key, &[0; 16]).unwrap());
+        let cbc = CBCProcessor::new(block, &[7; 32]).unwrap();
+
+        // 15 bytes are padded with 17, the IV is xored into the last block with its padding size
+        let ciphertext = cbc.encrypt_blocks(&[1; 15]);
+        assert_eq!(ciphertext.len(), 64);
+
+        for padding_size in [0u64, 65, u64::MAX] {
+            let mut corrupted = ciphertext.clone();
+            let forged = (17 ^ padding_size).to_le_bytes();
+            corrupted[24..32].iter_mut().zip(forged).for_each(|(c, f)| *c ^= f);
+
+            assert_eq!(cbc.decrypt_blocks(&corrupted).unwrap_err(), CipherError::InvalidPaddingSize, "padding {}", padding_size);
+        }
+
+        // a wrong key gives an arbitrary padding size and never panics
+        let other: Arc<dyn CipherBlock> = Arc::new(Cipher256::new(&[9; 32], &[0; 16]).unwrap());
+        let other = CBCProcessor::new(other, &[7; 32]).unwrap();
+        for length in [0, 15, 31, 32, 100] {
+            assert!(other.decrypt_blocks(&cbc.encrypt_blocks(&vec![1; length])).is_err());
+        }
+    }
+
     #[test]
     fn check_res(){
         let tweak: Vec<u8> = (0..16).collect();
@@ -278,4 +385,25 @@ mod cbc_decrypter_test {
 
         assert_eq!(plaintext,expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parallel_res(){
+        let tweak: Vec<u8> = (0..16).collect();
+        let key: Vec<u8> = (0..32).collect();
+        let iv: Vec<u8> = (0..32).rev().collect();
+
+        let c = Cipher256::new(&key, &tweak).unwrap();
+        let block: Arc<dyn CipherBlock> = Arc::new(c);
+        let cbc = CBCProcessor::new(block, &iv).unwrap();
+
+        for length in [0, 31, 32, 1000, MIN_PARALLEL_CHUNK * 4 + 17, MIN_PARALLEL_CHUNK * 7] {
+            let plaintext: Vec<u8> = (0..length).map(|i| ((i * 31) >> 3) as u8).collect();
+            let ciphertext = cbc.encrypt_blocks(&plaintext);
+
+            for threads in [1, 2, 3, 8] {
+                let parallel = cbc.clone().with_threads(threads);
+                assert_eq!(parallel.decrypt_blocks(&ciphertext).unwrap(), plaintext, "length {} threads {}", length, threads);
+            }
+        }
+    }
+}
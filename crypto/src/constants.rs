@@ -1,15 +1,23 @@
 pub const BLOCK_SIZE256: usize = 32;
 pub const NUM_ROUNDS256: usize = 72;
 pub const NUM_WORDS256: usize = 4;
+pub const BLOCK_SIZE512: usize = 64;
+pub const NUM_ROUNDS512: usize = 72;
+pub const NUM_WORDS512: usize = 8;
+pub const BLOCK_SIZE1024: usize = 128;
+pub const NUM_ROUNDS1024: usize = 80;
+pub const NUM_WORDS1024: usize = 16;
 pub const TWEAK_SIZE: usize = 16;
 pub const C240: u64 = 0x1bd11bdaa9fc1a22;
 
 
 pub const TWEAK_COUNT: usize = (TWEAK_SIZE / 8) + 1;
 pub const ROUND_KEYS_COUNT: usize = (NUM_ROUNDS256 / 4) + 1;
+pub const ROUND_KEYS_COUNT512: usize = (NUM_ROUNDS512 / 4) + 1;
+pub const ROUND_KEYS_COUNT1024: usize = (NUM_ROUNDS1024 / 4) + 1;
 
 
-pub const R4: [[i64; 2]; 8] = [
+pub const R4: [[u32; 2]; 8] = [
     [14, 16],
     [52, 57],
     [23, 40],
@@ -20,4 +28,31 @@ pub const R4: [[i64; 2]; 8] = [
     [32, 32],
 ];
 
-pub const PADDING: usize = 8;
\ No newline at end of file
+pub const R8: [[u32; 4]; 8] = [
+    [46, 36, 19, 37],
+    [33, 27, 14, 42],
+    [17, 49, 36, 39],
+    [44,  9, 54, 56],
+    [39, 30, 34, 24],
+    [13, 50, 10, 17],
+    [25, 29, 39, 43],
+    [ 8, 35, 56, 22],
+];
+
+pub const R16: [[u32; 8]; 8] = [
+    [24, 13,  8, 47,  8, 17, 22, 37],
+    [38, 19, 10, 55, 49, 18, 23, 52],
+    [33,  4, 51, 13, 34, 41, 59, 17],
+    [ 5, 20, 48, 41, 47, 28, 16, 25],
+    [41,  9, 37, 31, 12, 47, 44, 30],
+    [16, 34, 56, 51,  4, 53, 42, 41],
+    [31, 44, 47, 46, 19, 42, 44, 25],
+    [ 9, 48, 35, 52, 23, 31, 37, 20],
+];
+
+/// Word permutations applied after every round: word `i` takes the value of word `PI[i]`.
+pub const PI4: [usize; 4] = [0, 3, 2, 1];
+pub const PI8: [usize; 8] = [2, 1, 4, 7, 6, 5, 0, 3];
+pub const PI16: [usize; 16] = [0, 9, 2, 13, 6, 11, 4, 15, 10, 7, 12, 3, 14, 5, 8, 1];
+
+pub const PADDING: usize = 8;
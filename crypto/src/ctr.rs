@@ -0,0 +1,218 @@
+use super::utils::{load_word, store_word};
+use super::{CipherBlock, CipherError, CipherProcessor};
+
+use rand::Rng;
+use zeroize::Zeroizing;
+
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Inputs shorter than this per thread are processed on the calling thread.
+const MIN_PARALLEL_CHUNK: usize = 64 * 1024;
+/// Number of counter blocks encrypted with one batch call.
+const KEYSTREAM_BLOCKS: usize = 64;
+
+/// CTRProcessor encrypts the counter blocks `nonce + i` and xors them with the data,
+/// so the ciphertext has the length of the plaintext and every block can be
+/// processed on its own. Every call of `encrypt_blocks` draws a new nonce and
+/// stores it in front of the ciphertext, a nonce must never be used twice with one key.
+#[derive(Clone)]
+pub struct CTRProcessor {
+    block: Arc<dyn CipherBlock>,
+    block_size: usize,
+    threads: usize,
+}
+
+impl CTRProcessor {
+    pub fn new(block: Arc<dyn CipherBlock>) -> CTRProcessor {
+        CTRProcessor {
+            block_size: block.get_block_size(),
+            block,
+            threads: 1,
+        }
+    }
+
+    /// Splits inputs larger than 64KiB per thread between `threads` threads.
+    pub fn with_threads(self, threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            ..self
+        }
+    }
+
+    /// Length of the nonce stored in front of the ciphertext.
+    pub fn nonce_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn encrypt_with_nonce(&self, nonce: &[u8], src: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if nonce.len() != self.block_size {
+            return Err(CipherError::InvalidIVLength);
+        }
+
+        let mut dst = Vec::with_capacity(self.block_size + src.len());
+        dst.extend_from_slice(nonce);
+        dst.extend_from_slice(src);
+        self.apply_keystream(nonce, 0, &mut dst[self.block_size..]);
+
+        Ok(dst)
+    }
+
+    /// Decrypts `data`, the part of a ciphertext starting at byte `offset` of the plaintext.
+    /// Only the blocks covering `data` are computed, so a large entry can be read in pieces.
+    pub fn decrypt_at(&self, nonce: &[u8], offset: u64, data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if nonce.len() != self.block_size {
+            return Err(CipherError::InvalidIVLength);
+        }
+
+        let mut dst = data.to_vec();
+        self.apply_keystream(nonce, offset, &mut dst);
+
+        Ok(dst)
+    }
+
+    /// Decrypts the bytes `range` of the plaintext of `src`, a ciphertext made by `encrypt_blocks`.
+    pub fn decrypt_range(&self, src: &[u8], range: Range<usize>) -> Result<Vec<u8>, CipherError> {
+        if src.len() < self.block_size {
+            return Err(CipherError::InvalidCiphertextLength);
+        }
+
+        let (nonce, body) = src.split_at(self.block_size);
+        let data = body.get(range.clone()).ok_or(CipherError::InvalidCiphertextLength)?;
+
+        self.decrypt_at(nonce, range.start as u64, data)
+    }
+
+    fn apply_keystream(&self, nonce: &[u8], offset: u64, data: &mut [u8]) {
+        let chunk_size = data.len().div_ceil(self.threads).next_multiple_of(self.block_size);
+        if self.threads == 1 || chunk_size < MIN_PARALLEL_CHUNK {
+            self.apply_keystream_serial(nonce, offset, data);
+            return;
+        }
+
+        std::thread::scope(|scope| {
+            for (i, chunk) in data.chunks_mut(chunk_size).enumerate() {
+                let offset = offset + (i * chunk_size) as u64;
+                scope.spawn(move || self.apply_keystream_serial(nonce, offset, chunk));
+            }
+        });
+    }
+
+    fn apply_keystream_serial(&self, nonce: &[u8], offset: u64, data: &mut [u8]) {
+        let bs = self.block_size as u64;
+        let mut buffer = Zeroizing::new(vec![0u8; KEYSTREAM_BLOCKS * self.block_size]);
+        let first_word = load_word(&nonce[..8]);
+
+        let mut counter = offset / bs;
+        let mut skip = (offset % bs) as usize;
+        let mut pos = 0;
+        while pos < data.len() {
+            let blocks = (skip + data.len() - pos).div_ceil(self.block_size).min(KEYSTREAM_BLOCKS);
+            let keystream = &mut buffer[..blocks * self.block_size];
+
+            for (k, counter_block) in keystream.chunks_exact_mut(self.block_size).enumerate() {
+                counter_block.copy_from_slice(nonce);
+                store_word(first_word.wrapping_add(counter + k as u64), &mut counter_block[..8]);
+            }
+            self.block.encrypt_batch(keystream).unwrap();
+
+            let n = (keystream.len() - skip).min(data.len() - pos);
+            data[pos..pos + n].iter_mut()
+                .zip(&keystream[skip..skip + n])
+                .for_each(|(x1, x2)| *x1 ^= x2);
+
+            pos += n;
+            skip = 0;
+            counter += blocks as u64;
+        }
+    }
+}
+
+impl CipherProcessor for CTRProcessor {
+    fn encrypt_blocks(&self, src: &[u8]) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let nonce: Vec<u8> = (0..self.block_size).map(|_| rng.gen()).collect();
+
+        self.encrypt_with_nonce(&nonce, src).unwrap()
+    }
+
+    fn decrypt_blocks(&self, src: &[u8]) -> Result<Vec<u8>, CipherError> {
+        self.decrypt_range(src, 0..src.len().saturating_sub(self.block_size))
+    }
+}
+
+
+#[cfg(test)]
+mod ctr_test {
+    use crate::threefish256::Cipher256;
+    use crate::threefish512::Cipher512;
+    use super::*;
+
+    fn get_processor() -> CTRProcessor {
+        let tweak: Vec<u8> = (0..16).collect();
+        let key: Vec<u8> = (0..32).collect();
+
+        CTRProcessor::new(Arc::new(Cipher256::new(&key, &tweak).unwrap()))
+    }
+
+    #[test]
+    fn keystream_res() {
+        // the first counter block of a zero nonce is the zero block
+        let processor = CTRProcessor::new(Arc::new(Cipher256::new(&[0; 32], &[0; 16]).unwrap()));
+        let ciphertext = processor.encrypt_with_nonce(&[0; 32], &[0; 40]).unwrap();
+
+        assert_eq!(&ciphertext[..32], &[0; 32]);
+        assert_eq!(&ciphertext[32..40], &[0x84, 0xda, 0x2a, 0x1f, 0x8b, 0xea, 0xee, 0x94]);
+        assert_eq!(ciphertext.len(), 72);
+    }
+
+    #[test]
+    fn decrypt_encrypt_res() {
+        let processor = get_processor();
+
+        for length in [0, 1, 31, 32, 33, 1000] {
+            let plaintext: Vec<u8> = (0..length).map(|i| (i * 7) as u8).collect();
+
+            let ciphertext = processor.encrypt_blocks(&plaintext);
+            assert_eq!(ciphertext.len(), length + 32);
+            assert_eq!(processor.decrypt_blocks(&ciphertext).unwrap(), plaintext);
+        }
+
+        // a new nonce for every message
+        let a = processor.encrypt_blocks(&[0; 64]);
+        let b = processor.encrypt_blocks(&[0; 64]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn decrypt_range_res() {
+        let processor = CTRProcessor::new(Arc::new(Cipher512::new(&[7; 64], &[1; 16]).unwrap()));
+        let plaintext: Vec<u8> = (0..1000u32).map(|i| (i * 13) as u8).collect();
+        let ciphertext = processor.encrypt_blocks(&plaintext);
+
+        for range in [0..0, 0..1000, 1..2, 63..65, 100..357, 999..1000] {
+            assert_eq!(processor.decrypt_range(&ciphertext, range.clone()).unwrap(), &plaintext[range.clone()]);
+
+            let (nonce, body) = ciphertext.split_at(processor.nonce_size());
+            let part = processor.decrypt_at(nonce, range.start as u64, &body[range.clone()]).unwrap();
+            assert_eq!(part, &plaintext[range]);
+        }
+
+        assert_eq!(processor.decrypt_range(&ciphertext, 900..1001).unwrap_err(), CipherError::InvalidCiphertextLength);
+        assert_eq!(processor.decrypt_blocks(&ciphertext[..10]).unwrap_err(), CipherError::InvalidCiphertextLength);
+        assert_eq!(processor.decrypt_at(&[0; 32], 0, &[]).unwrap_err(), CipherError::InvalidIVLength);
+    }
+
+    #[test]
+    fn parallel_res() {
+        let serial = get_processor();
+        let parallel = get_processor().with_threads(4);
+        let plaintext: Vec<u8> = (0..1_000_003u32).map(|i| ((i * 31) >> 3) as u8).collect();
+        let nonce: Vec<u8> = (0..32).rev().collect();
+
+        let ciphertext = serial.encrypt_with_nonce(&nonce, &plaintext).unwrap();
+        assert_eq!(parallel.encrypt_with_nonce(&nonce, &plaintext).unwrap(), ciphertext);
+        assert_eq!(parallel.decrypt_blocks(&ciphertext).unwrap(), plaintext);
+        assert_eq!(parallel.decrypt_range(&ciphertext, 12345..800001).unwrap(), &plaintext[12345..800001]);
+    }
+}
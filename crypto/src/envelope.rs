@@ -0,0 +1,259 @@
+use super::skein;
+use super::utils::{from_hex, to_hex};
+use super::CipherError;
+
+use rand::rngs::OsRng;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use std::fmt;
+
+/// Length of X25519 public and secret keys.
+pub const KEY_SIZE: usize = 32;
+const TAG_SIZE: usize = 32;
+/// Identifier of the keys derived to wrap content keys with [`skein::kdf`].
+const ENVELOPE_KEY_ID: &[u8] = b"crypto_container envelope key";
+
+/// Identity is the X25519 secret key of a recipient. It opens the envelopes
+/// sealed for its [`Recipient`].
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity { secret: StaticSecret::random_from_rng(OsRng) }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Identity, CipherError> {
+        let bytes: [u8; KEY_SIZE] = bytes.try_into().map_err(|_| CipherError::InvalidKeyLength)?;
+        Ok(Identity { secret: StaticSecret::from(bytes) })
+    }
+
+    pub fn from_hex(s: &str) -> Result<Identity, CipherError> {
+        Identity::from_bytes(&from_hex(s.trim())?)
+    }
+
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(to_hex(self.secret.as_bytes()))
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient { public: PublicKey::from(&self.secret) }
+    }
+}
+
+/// Recipient is the X25519 public key an envelope is sealed for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recipient {
+    public: PublicKey,
+}
+
+impl Recipient {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Recipient, CipherError> {
+        let bytes: [u8; KEY_SIZE] = bytes.try_into().map_err(|_| CipherError::InvalidKeyLength)?;
+        Ok(Recipient { public: PublicKey::from(bytes) })
+    }
+
+    pub fn from_hex(s: &str) -> Result<Recipient, CipherError> {
+        Recipient::from_bytes(&from_hex(s.trim())?)
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_SIZE] {
+        self.public.to_bytes()
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(self.public.as_bytes()))
+    }
+}
+
+/// Stanza is the content key wrapped for one recipient: the key is xored with a
+/// key stream derived from the shared secret of an ephemeral key and the
+/// recipient, and authenticated with a Skein-MAC under another derived key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stanza {
+    pub recipient: [u8; KEY_SIZE],
+    ephemeral: [u8; KEY_SIZE],
+    wrapped: Vec<u8>,
+    tag: [u8; TAG_SIZE],
+}
+
+/// Envelope holds a content key wrapped for every recipient of an archive.
+/// Any one of the recipients' identities opens it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub stanzas: Vec<Stanza>,
+}
+
+impl Envelope {
+    pub fn seal(content_key: &[u8], recipients: &[Recipient]) -> Envelope {
+        let stanzas = recipients.iter()
+            .map(|recipient| {
+                let secret = EphemeralSecret::random_from_rng(OsRng);
+                let ephemeral = PublicKey::from(&secret).to_bytes();
+                let shared = secret.diffie_hellman(&recipient.public);
+
+                let keys = wrapping_keys(shared.as_bytes(), &ephemeral, &recipient.to_bytes(), content_key.len());
+                let (pad, mac_key) = keys.split_at(content_key.len());
+
+                let wrapped: Vec<u8> = content_key.iter().zip(pad).map(|(k, p)| k ^ p).collect();
+                let tag = skein::mac(mac_key, &wrapped, TAG_SIZE).try_into().unwrap();
+
+                Stanza { recipient: recipient.to_bytes(), ephemeral, wrapped, tag }
+            })
+            .collect();
+
+        Envelope { stanzas }
+    }
+
+    /// Unwraps the content key from the stanza sealed for `identity`.
+    pub fn open(&self, identity: &Identity) -> Result<Zeroizing<Vec<u8>>, CipherError> {
+        let recipient = identity.recipient().to_bytes();
+        let stanza = self.stanzas.iter()
+            .find(|s| s.recipient == recipient)
+            .ok_or(CipherError::RecipientNotFound)?;
+
+        let shared = identity.secret.diffie_hellman(&PublicKey::from(stanza.ephemeral));
+        if !shared.was_contributory() {
+            return Err(CipherError::InvalidEnvelope);
+        }
+
+        let keys = wrapping_keys(shared.as_bytes(), &stanza.ephemeral, &recipient, stanza.wrapped.len());
+        let (pad, mac_key) = keys.split_at(stanza.wrapped.len());
+
+        let tag = skein::mac(mac_key, &stanza.wrapped, TAG_SIZE);
+        let difference = tag.iter().zip(&stanza.tag).fold(0, |acc, (a, b)| acc | (a ^ b));
+        if difference != 0 {
+            return Err(CipherError::InvalidEnvelope);
+        }
+
+        Ok(Zeroizing::new(stanza.wrapped.iter().zip(pad).map(|(w, p)| w ^ p).collect()))
+    }
+
+    /// Stored as: stanza count, then recipient, ephemeral key, wrapped key length,
+    /// wrapped key and tag of every stanza.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.stanzas.len() as u64).to_le_bytes());
+
+        for stanza in &self.stanzas {
+            bytes.extend_from_slice(&stanza.recipient);
+            bytes.extend_from_slice(&stanza.ephemeral);
+            bytes.extend_from_slice(&(stanza.wrapped.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&stanza.wrapped);
+            bytes.extend_from_slice(&stanza.tag);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Envelope, CipherError> {
+        let count = take_u64(&mut bytes)?;
+
+        let mut stanzas = Vec::new();
+        for _ in 0..count {
+            let recipient = take(&mut bytes, KEY_SIZE)?.try_into().unwrap();
+            let ephemeral = take(&mut bytes, KEY_SIZE)?.try_into().unwrap();
+            let length = take_u64(&mut bytes)?;
+            let wrapped = take(&mut bytes, usize::try_from(length).map_err(|_| CipherError::InvalidEnvelope)?)?.to_vec();
+            let tag = take(&mut bytes, TAG_SIZE)?.try_into().unwrap();
+
+            stanzas.push(Stanza { recipient, ephemeral, wrapped, tag });
+        }
+
+        if !bytes.is_empty() {
+            return Err(CipherError::InvalidEnvelope);
+        }
+
+        Ok(Envelope { stanzas })
+    }
+}
+
+/// Key stream of `length` bytes followed by the MAC key, bound to both public keys.
+fn wrapping_keys(shared: &[u8], ephemeral: &[u8], recipient: &[u8], length: usize) -> Zeroizing<Vec<u8>> {
+    let mut id = ENVELOPE_KEY_ID.to_vec();
+    id.extend_from_slice(ephemeral);
+    id.extend_from_slice(recipient);
+
+    skein::kdf(shared, &id, length + TAG_SIZE)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], CipherError> {
+    if bytes.len() < n {
+        return Err(CipherError::InvalidEnvelope);
+    }
+
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, CipherError> {
+    Ok(u64::from_le_bytes(take(bytes, 8)?.try_into().unwrap()))
+}
+
+
+#[cfg(test)]
+mod envelope_test {
+    use super::*;
+
+    #[test]
+    fn seal_open_res() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let eve = Identity::generate();
+        let content_key: Vec<u8> = (0..64).collect();
+
+        let envelope = Envelope::seal(&content_key, &[alice.recipient(), bob.recipient()]);
+        assert_eq!(envelope.stanzas.len(), 2);
+
+        assert_eq!(*envelope.open(&alice).unwrap(), content_key);
+        assert_eq!(*envelope.open(&bob).unwrap(), content_key);
+        assert_eq!(envelope.open(&eve).unwrap_err(), CipherError::RecipientNotFound);
+
+        // the ephemeral keys differ, so the wrapped keys do too
+        assert_ne!(envelope.stanzas[0].wrapped, envelope.stanzas[1].wrapped);
+    }
+
+    #[test]
+    fn tampered_res() {
+        let alice = Identity::generate();
+        let mut envelope = Envelope::seal(&[7; 32], &[alice.recipient()]);
+
+        envelope.stanzas[0].wrapped[3] ^= 1;
+        assert_eq!(envelope.open(&alice).unwrap_err(), CipherError::InvalidEnvelope);
+    }
+
+    #[test]
+    fn bytes_res() {
+        let alice = Identity::generate();
+        let envelope = Envelope::seal(&[7; 128], &[alice.recipient(), Identity::generate().recipient()]);
+
+        let bytes = envelope.to_bytes();
+        let loaded = Envelope::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, envelope);
+        assert_eq!(*loaded.open(&alice).unwrap(), vec![7; 128]);
+
+        assert_eq!(Envelope::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), CipherError::InvalidEnvelope);
+        assert_eq!(Envelope::from_bytes(&[bytes.clone(), vec![0]].concat()).unwrap_err(), CipherError::InvalidEnvelope);
+        assert_eq!(Envelope::from_bytes(&[0xff; 8]).unwrap_err(), CipherError::InvalidEnvelope);
+    }
+
+    #[test]
+    fn hex_res() {
+        let alice = Identity::generate();
+
+        let restored = Identity::from_hex(&alice.to_hex()).unwrap();
+        assert_eq!(restored.recipient(), alice.recipient());
+
+        let recipient = Recipient::from_hex(&format!("{}\n", alice.recipient())).unwrap();
+        assert_eq!(recipient, alice.recipient());
+
+        assert_eq!(Recipient::from_hex("abc").unwrap_err(), CipherError::InvalidKeyLength);
+        assert_eq!(Recipient::from_hex(&"zz".repeat(32)).unwrap_err(), CipherError::InvalidKeyLength);
+        assert!(matches!(Identity::from_hex(&"00".repeat(31)), Err(CipherError::InvalidKeyLength)));
+    }
+}
@@ -0,0 +1,269 @@
+use super::skein;
+use super::CipherError;
+
+use rand::Rng;
+use zeroize::Zeroizing;
+
+/// Number of slots of every archive, free slots keep their place in the section.
+pub const MAX_KEY_SLOTS: usize = 8;
+/// Rounds of the password KDF for new slots, about a third of a second per try.
+pub const DEFAULT_ITERATIONS: u64 = 100_000;
+/// Upper bound of the KDF rounds, about half a minute per try. Slots asking for
+/// more are rejected, a corrupted count would hang opening the archive.
+pub const MAX_ITERATIONS: u64 = 100 * DEFAULT_ITERATIONS;
+const SALT_SIZE: usize = 32;
+const TAG_SIZE: usize = 32;
+/// state, iterations, salt and tag of a slot record, without the wrapped key.
+const SLOT_HEADER_SIZE: usize = 16 + SALT_SIZE + TAG_SIZE;
+/// Identifier of the keys derived from passwords with [`skein::kdf`].
+const SLOT_KEY_ID: &[u8] = b"crypto_container key slot";
+
+/// KeySlot is the content key wrapped under a key derived from one password.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySlot {
+    pub iterations: u64,
+    salt: [u8; SALT_SIZE],
+    wrapped: Vec<u8>,
+    tag: [u8; TAG_SIZE],
+}
+
+/// KeySlots holds the content key of an archive wrapped under several passwords,
+/// any of which opens it. Slots are added and removed without touching the
+/// content key, so the data encrypted with it stays as it is.
+///
+/// The encoded section has the same size whatever slots are in use, so it is
+/// rewritten in place: key size, then for every slot its state, iterations,
+/// salt, wrapped key and tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySlots {
+    key_size: usize,
+    slots: Vec<Option<KeySlot>>,
+}
+
+impl KeySlots {
+    pub fn new(key_size: usize) -> KeySlots {
+        KeySlots {
+            key_size,
+            slots: vec![None; MAX_KEY_SLOTS],
+        }
+    }
+
+    pub fn key_size(&self) -> usize {
+        self.key_size
+    }
+
+    /// Wraps `content_key` under `password` in the first free slot and returns its index.
+    /// `iterations` must not exceed [`MAX_ITERATIONS`].
+    pub fn add(&mut self, content_key: &[u8], password: &[u8], iterations: u64) -> Result<usize, CipherError> {
+        if content_key.len() != self.key_size {
+            return Err(CipherError::InvalidKeyLength);
+        }
+
+        if iterations > MAX_ITERATIONS {
+            return Err(CipherError::InvalidIterations);
+        }
+
+        let index = self.slots.iter()
+            .position(|slot| slot.is_none())
+            .ok_or(CipherError::KeySlotsFull)?;
+
+        let salt: [u8; SALT_SIZE] = rand::thread_rng().gen();
+        let keys = password_keys(password, &salt, iterations.max(1), self.key_size);
+        let (pad, mac_key) = keys.split_at(self.key_size);
+
+        let wrapped: Vec<u8> = content_key.iter().zip(pad).map(|(k, p)| k ^ p).collect();
+        let tag = skein::mac(mac_key, &wrapped, TAG_SIZE).try_into().unwrap();
+
+        self.slots[index] = Some(KeySlot { iterations: iterations.max(1), salt, wrapped, tag });
+        Ok(index)
+    }
+
+    /// Frees slot `index`. The last slot in use is never removed, the content key would be lost.
+    pub fn remove(&mut self, index: usize) -> Result<(), CipherError> {
+        if self.slots.get(index).is_none_or(|slot| slot.is_none()) {
+            return Err(CipherError::InvalidKeySlot);
+        }
+        if self.active().len() == 1 {
+            return Err(CipherError::LastKeySlot);
+        }
+
+        self.slots[index] = None;
+        Ok(())
+    }
+
+    /// Indices and KDF iterations of the slots in use.
+    pub fn active(&self) -> Vec<(usize, u64)> {
+        self.slots.iter()
+            .enumerate()
+            .filter_map(|(i, slot)| slot.as_ref().map(|s| (i, s.iterations)))
+            .collect()
+    }
+
+    /// Finds the slot `password` opens. Returns its index and the content key.
+    pub fn open(&self, password: &[u8]) -> Result<(usize, Zeroizing<Vec<u8>>), CipherError> {
+        for (index, slot) in self.slots.iter().enumerate() {
+            let Some(slot) = slot else { continue };
+
+            let keys = password_keys(password, &slot.salt, slot.iterations, self.key_size);
+            let (pad, mac_key) = keys.split_at(self.key_size);
+
+            let tag = skein::mac(mac_key, &slot.wrapped, TAG_SIZE);
+            let difference = tag.iter().zip(&slot.tag).fold(0, |acc, (a, b)| acc | (a ^ b));
+            if difference == 0 {
+                return Ok((index, Zeroizing::new(slot.wrapped.iter().zip(pad).map(|(w, p)| w ^ p).collect())));
+            }
+        }
+
+        Err(CipherError::NoMatchingKeySlot)
+    }
+
+    /// Size of the encoded section for content keys of `key_size` bytes.
+    pub fn encoded_size(key_size: usize) -> usize {
+        8 + MAX_KEY_SLOTS * (SLOT_HEADER_SIZE + key_size)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(KeySlots::encoded_size(self.key_size));
+        bytes.extend_from_slice(&(self.key_size as u64).to_le_bytes());
+
+        for slot in &self.slots {
+            match slot {
+                Some(slot) => {
+                    bytes.extend_from_slice(&1u64.to_le_bytes());
+                    bytes.extend_from_slice(&slot.iterations.to_le_bytes());
+                    bytes.extend_from_slice(&slot.salt);
+                    bytes.extend_from_slice(&slot.wrapped);
+                    bytes.extend_from_slice(&slot.tag);
+                },
+                None => bytes.resize(bytes.len() + SLOT_HEADER_SIZE + self.key_size, 0),
+            }
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<KeySlots, CipherError> {
+        if bytes.len() < 8 {
+            return Err(CipherError::InvalidKeySlot);
+        }
+
+        let key_size = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        if key_size == 0 || key_size > 1024 || bytes.len() != KeySlots::encoded_size(key_size as usize) {
+            return Err(CipherError::InvalidKeySlot);
+        }
+        let key_size = key_size as usize;
+
+        let slots = bytes[8..].chunks_exact(SLOT_HEADER_SIZE + key_size)
+            .map(|record| {
+                let state = u64::from_le_bytes(record[..8].try_into().unwrap());
+                let iterations = u64::from_le_bytes(record[8..16].try_into().unwrap());
+                let (salt, rest) = record[16..].split_at(SALT_SIZE);
+                let (wrapped, tag) = rest.split_at(key_size);
+
+                match state {
+                    0 => Ok(None),
+                    1 if (1..=MAX_ITERATIONS).contains(&iterations) => Ok(Some(KeySlot {
+                        iterations,
+                        salt: salt.try_into().unwrap(),
+                        wrapped: wrapped.to_vec(),
+                        tag: tag.try_into().unwrap(),
+                    })),
+                    _ => Err(CipherError::InvalidKeySlot),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(KeySlots { key_size, slots })
+    }
+}
+
+/// Key stream of `length` bytes followed by the MAC key. The password is
+/// chained through the KDF `iterations` times to slow down guessing.
+fn password_keys(password: &[u8], salt: &[u8], iterations: u64, length: usize) -> Zeroizing<Vec<u8>> {
+    let mut id = SLOT_KEY_ID.to_vec();
+    id.extend_from_slice(salt);
+
+    let mut key = skein::kdf(password, &id, 64);
+    for _ in 1..iterations {
+        key = skein::kdf(&key, &id, 64);
+    }
+
+    skein::kdf(&key, &id, length + TAG_SIZE)
+}
+
+
+#[cfg(test)]
+mod keyslot_test {
+    use super::*;
+
+    #[test]
+    fn add_open_remove_res() {
+        let content_key: Vec<u8> = (0..32).collect();
+        let mut slots = KeySlots::new(32);
+
+        assert_eq!(slots.add(&content_key, b"first", 3).unwrap(), 0);
+        assert_eq!(slots.add(&content_key, b"second", 5).unwrap(), 1);
+        assert_eq!(slots.active(), vec![(0, 3), (1, 5)]);
+
+        let (index, key) = slots.open(b"second").unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(*key, content_key);
+        assert_eq!(slots.open(b"third").unwrap_err(), CipherError::NoMatchingKeySlot);
+
+        slots.remove(0).unwrap();
+        assert_eq!(slots.open(b"first").unwrap_err(), CipherError::NoMatchingKeySlot);
+        assert_eq!(slots.remove(0).unwrap_err(), CipherError::InvalidKeySlot);
+        assert_eq!(slots.remove(MAX_KEY_SLOTS).unwrap_err(), CipherError::InvalidKeySlot);
+        assert_eq!(slots.remove(1).unwrap_err(), CipherError::LastKeySlot);
+
+        // freed slots are reused first
+        assert_eq!(slots.add(&content_key, b"third", 1).unwrap(), 0);
+        assert_eq!(slots.add(&[0; 31], b"fourth", 1).unwrap_err(), CipherError::InvalidKeyLength);
+    }
+
+    #[test]
+    fn full_res() {
+        let mut slots = KeySlots::new(32);
+        for i in 0..MAX_KEY_SLOTS {
+            assert_eq!(slots.add(&[1; 32], &[i as u8], 1).unwrap(), i);
+        }
+
+        assert_eq!(slots.add(&[1; 32], b"one more", 1).unwrap_err(), CipherError::KeySlotsFull);
+    }
+
+    #[test]
+    fn bytes_res() {
+        let mut slots = KeySlots::new(64);
+        slots.add(&[9; 64], b"first", 2).unwrap();
+        slots.add(&[9; 64], b"second", 2).unwrap();
+        slots.remove(0).unwrap();
+
+        let bytes = slots.to_bytes();
+        assert_eq!(bytes.len(), KeySlots::encoded_size(64));
+
+        let loaded = KeySlots::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, slots);
+        assert_eq!(*loaded.open(b"second").unwrap().1, vec![9; 64]);
+
+        assert_eq!(KeySlots::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), CipherError::InvalidKeySlot);
+
+        let mut corrupted = bytes.clone();
+        corrupted[8] = 2;
+        assert_eq!(KeySlots::from_bytes(&corrupted).unwrap_err(), CipherError::InvalidKeySlot);
+    }
+
+    #[test]
+    fn max_iterations_res() {
+        let mut slots = KeySlots::new(32);
+        assert_eq!(slots.add(&[1; 32], b"slow", MAX_ITERATIONS + 1).unwrap_err(), CipherError::InvalidIterations);
+        assert_eq!(slots.add(&[1; 32], b"fast", 1).unwrap(), 0);
+
+        // the iterations of slot 0 follow the key size and its state
+        let mut bytes = slots.to_bytes();
+        bytes[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(KeySlots::from_bytes(&bytes).unwrap_err(), CipherError::InvalidKeySlot);
+
+        bytes[16..24].copy_from_slice(&MAX_ITERATIONS.to_le_bytes());
+        assert_eq!(KeySlots::from_bytes(&bytes).unwrap().active(), vec![(0, MAX_ITERATIONS)]);
+    }
+}
@@ -1,14 +1,62 @@
+#[macro_use]
 mod mix;
 mod utils;
 mod constants;
 pub mod threefish256;
+pub mod threefish512;
+pub mod threefish1024;
 pub mod cbc;
+pub mod ctr;
+pub mod xts;
+pub mod skein;
+pub mod envelope;
+pub mod keyslot;
+pub mod signing;
 
 
 pub trait CipherBlock: Send + Sync {
     fn encrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), CipherError>;
     fn decrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), CipherError>;
     fn get_block_size(&self) -> usize;
+
+    /// Encrypts the consecutive blocks of `blocks` in place.
+    fn encrypt_batch(&self, blocks: &mut [u8]) -> Result<(), CipherError> {
+        let block_size = self.get_block_size();
+        if !blocks.len().is_multiple_of(block_size) {
+            return Err(CipherError::InvalidPlaintextLength);
+        }
+
+        let mut buffer = vec![0u8; block_size];
+        for block in blocks.chunks_exact_mut(block_size) {
+            buffer.copy_from_slice(block);
+            self.encrypt(&buffer, block)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts the consecutive blocks of `blocks` in place.
+    fn decrypt_batch(&self, blocks: &mut [u8]) -> Result<(), CipherError> {
+        let block_size = self.get_block_size();
+        if !blocks.len().is_multiple_of(block_size) {
+            return Err(CipherError::InvalidCiphertextLength);
+        }
+
+        let mut buffer = vec![0u8; block_size];
+        for block in blocks.chunks_exact_mut(block_size) {
+            buffer.copy_from_slice(block);
+            self.decrypt(&buffer, block)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// TweakableBlock is a block cipher which takes the tweak with every block
+/// instead of fixing it when the cipher is created, as Threefish allows.
+pub trait TweakableBlock: CipherBlock {
+    fn encrypt_tweaked(&self, tweak: &[u8], src: &[u8], dst: &mut [u8]) -> Result<(), CipherError>;
+    fn decrypt_tweaked(&self, tweak: &[u8], src: &[u8], dst: &mut [u8]) -> Result<(), CipherError>;
 }
 
 #[derive(Debug, PartialEq)]
@@ -20,11 +68,28 @@ pub enum CipherError {
     InvalidIVLength,
     InvalidPaddingSize,
     InvalidIVArePassed,
+    InvalidStateSize,
+    InvalidOutputLength,
+    InvalidSectorSize,
+    RecipientNotFound,
+    InvalidEnvelope,
+    KeySlotsFull,
+    InvalidKeySlot,
+    LastKeySlot,
+    NoMatchingKeySlot,
+    InvalidIterations,
+    InvalidSignature,
 }
 
 
 pub trait CipherProcessor: Send + Sync {
     fn encrypt_blocks(&self, src: &[u8]) -> Vec<u8>;
     fn decrypt_blocks(&self, src: &[u8]) -> Result<Vec<u8>, CipherError>;
+
+    /// Encrypts a short record such as an entry header with a fresh IV or nonce,
+    /// so two records never share ciphertext. Decrypted with `decrypt_blocks`.
+    fn encrypt_record(&self, src: &[u8]) -> Vec<u8> {
+        self.encrypt_blocks(src)
+    }
 }
 
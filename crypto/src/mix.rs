@@ -1,62 +1,312 @@
-use super::constants;
+use super::constants::{self, C240};
+use super::utils::{calculate_tweak, load_word};
+use super::CipherError;
 
-pub struct Mix{
-    d: usize,
-    j: usize
+/// One MIX step: adds the second word to the first and xors the first
+/// into the second one rotated left by `r` bits.
+#[inline(always)]
+pub fn mix(x0: u64, x1: u64, r: u32) -> (u64, u64) {
+    let y0 = x0.wrapping_add(x1);
+    let y1 = x1.rotate_left(r) ^ y0;
+    (y0, y1)
 }
 
-impl Mix {
-    pub fn new(d: usize, j: usize) -> Mix {
-        Mix {d,j}
+#[inline(always)]
+pub fn demix(y0: u64, y1: u64, r: u32) -> (u64, u64) {
+    let x1 = (y1 ^ y0).rotate_right(r);
+    let x0 = y0.wrapping_sub(x1);
+    (x0, x1)
+}
+
+/// Expands a key of `N` words into `R` round keys. Every round key is a rotation
+/// of the key words extended with their parity, with the tweak added to the last
+/// but two words and the round key number to the last one.
+pub fn expand_key<const N: usize, const R: usize>(key: &[u8], t: &[u64; constants::TWEAK_COUNT]) -> [[u64; N]; R] {
+    let mut k = [0; N];
+    let mut parity = C240;
+    for (i, word) in k.iter_mut().enumerate() {
+        *word = load_word(&key[i*8..(i+1)*8]);
+        parity ^= *word;
+    }
+
+    let mut ks = [[0; N]; R];
+    for (s, round_key) in ks.iter_mut().enumerate() {
+        for (i, word) in round_key.iter_mut().enumerate() {
+            let last_add = if i == N - 3 {
+                t[s%3]
+            } else if i == N - 2 {
+                t[(s+1)%3]
+            } else if i == N - 1 {
+                s as u64
+            } else {
+                0
+            };
+            let j = (s+i)%(N+1);
+            let key_word = if j == N { parity } else { k[j] };
+            *word = key_word.wrapping_add(last_add);
+        }
+    }
+
+    ks
+}
+
+/// The difference between the tweak words of `tweak` and the ones a key
+/// schedule was expanded with. Adding it to the round keys gives the schedule
+/// of `tweak` without expanding the key again.
+pub fn tweak_delta(tweak: &[u8], t: &[u64; constants::TWEAK_COUNT]) -> Result<[u64; constants::TWEAK_COUNT], CipherError> {
+    let tweak = calculate_tweak(tweak)?;
+    Ok([0, 1, 2].map(|i| tweak[i].wrapping_sub(t[i])))
+}
+
+/// Runs the Threefish rounds over `words`: a round key is added every four
+/// rounds, every round mixes the pairs of words and permutes them. All the
+/// loop bounds are constants, so the rounds are unrolled for every block size.
+/// `delta` moves the round keys to another tweak, see [`tweak_delta`].
+#[inline(always)]
+pub fn encrypt_words<const N: usize, const H: usize, const R: usize>(words: &mut [u64; N], ks: &[[u64; N]; R], delta: &[u64; constants::TWEAK_COUNT], rotations: &[[u32; H]; 8], permutation: &[usize; N]) {
+    for s in (0..R-1).step_by(2) {
+        add_key(words, &ks[s], s, delta);
+        encrypt_rounds(words, rotations, 0, permutation);
+        add_key(words, &ks[s+1], s+1, delta);
+        encrypt_rounds(words, rotations, 4, permutation);
+    }
+
+    add_key(words, &ks[R-1], R-1, delta);
+}
+
+/// Reverses [`encrypt_words`].
+#[inline(always)]
+pub fn decrypt_words<const N: usize, const H: usize, const R: usize>(words: &mut [u64; N], ks: &[[u64; N]; R], delta: &[u64; constants::TWEAK_COUNT], rotations: &[[u32; H]; 8], permutation: &[usize; N]) {
+    sub_key(words, &ks[R-1], R-1, delta);
+
+    for s in (0..R-1).step_by(2).rev() {
+        decrypt_rounds(words, rotations, 4, permutation);
+        sub_key(words, &ks[s+1], s+1, delta);
+        decrypt_rounds(words, rotations, 0, permutation);
+        sub_key(words, &ks[s], s, delta);
     }
+}
 
-    fn shift(&self) -> i64 {
-        constants::R4[self.d % 8][self.j]
+/// Adds the round key `s`, the tweak terms of it are shifted by `delta`.
+#[inline(always)]
+fn add_key<const N: usize>(words: &mut [u64; N], round_key: &[u64; N], s: usize, delta: &[u64; constants::TWEAK_COUNT]) {
+    for (word, key) in words.iter_mut().zip(round_key) {
+        *word = word.wrapping_add(*key);
     }
 
-    pub fn mix(&self, x0: u64, x1: u64) -> (u64, u64) {
-        let s = self.shift();
+    words[N-3] = words[N-3].wrapping_add(delta[s%3]);
+    words[N-2] = words[N-2].wrapping_add(delta[(s+1)%3]);
+}
+
+#[inline(always)]
+fn sub_key<const N: usize>(words: &mut [u64; N], round_key: &[u64; N], s: usize, delta: &[u64; constants::TWEAK_COUNT]) {
+    for (word, key) in words.iter_mut().zip(round_key) {
+        *word = word.wrapping_sub(*key);
+    }
+
+    words[N-3] = words[N-3].wrapping_sub(delta[s%3]);
+    words[N-2] = words[N-2].wrapping_sub(delta[(s+1)%3]);
+}
+
+/// Four rounds with the rotations `first..first + 4`.
+#[inline(always)]
+fn encrypt_rounds<const N: usize, const H: usize>(words: &mut [u64; N], rotations: &[[u32; H]; 8], first: usize, permutation: &[usize; N]) {
+    for rotation in &rotations[first..first+4] {
+        for (j, &r) in rotation.iter().enumerate() {
+            (words[2*j], words[2*j+1]) = mix(words[2*j], words[2*j+1], r);
+        }
 
-        let y0 = x0.wrapping_add(x1);
-        let y1 = ((x1 << s) | (x1 >> (64 - s))) ^ y0;
-        (y0, y1)
+        let mixed = *words;
+        for (word, &p) in words.iter_mut().zip(permutation) {
+            *word = mixed[p];
+        }
     }
+}
 
-    pub fn demix(&self, y0: u64, y1: u64) -> (u64, u64) {
-        let s = self.shift();
-        let x1 = ((y1 ^ y0) << (64 - s)) | ((y1 ^ y0) >> s);
-        let x0 = y0.wrapping_sub(x1);
-        (x0, x1)
+#[inline(always)]
+fn decrypt_rounds<const N: usize, const H: usize>(words: &mut [u64; N], rotations: &[[u32; H]; 8], first: usize, permutation: &[usize; N]) {
+    for rotation in rotations[first..first+4].iter().rev() {
+        let permuted = *words;
+        for (&word, &p) in permuted.iter().zip(permutation) {
+            words[p] = word;
+        }
+
+        for (j, &r) in rotation.iter().enumerate() {
+            (words[2*j], words[2*j+1]) = demix(words[2*j], words[2*j+1], r);
+        }
     }
 }
 
 
+/// Defines a Threefish cipher over the functions of this module. The block
+/// sizes differ only in their constants: the number of words and round keys,
+/// the rotations and the permutation.
+macro_rules! threefish_cipher {
+    ($name:ident, $bits:literal, $block_size:expr, $words:expr, $round_keys:expr, $rotations:expr, $permutation:expr) => {
+        #[derive(Debug)]
+        pub struct $name {
+            ks: [[u64; $words]; $round_keys],
+            /// The tweak words `ks` was expanded with.
+            t: [u64; $crate::constants::TWEAK_COUNT],
+        }
+
+        impl $name {
+            #[doc = concat!("Creates a Threefish cipher with a block size of ", $bits, " bits.")]
+            /// The key must be as long as a block and the tweak must be 16 bytes.
+            pub fn new(key: &[u8], tweak: &[u8]) -> Result<$name, $crate::CipherError> {
+                if key.len() != $block_size {
+                    return Err($crate::CipherError::InvalidKeyLength);
+                }
+
+                let t = $crate::utils::calculate_tweak(tweak)?;
+                let ks = $crate::mix::expand_key(key, &t);
+
+                Ok($name { ks, t })
+            }
+
+            /// Encrypts one block in place.
+            pub fn encrypt_block(&self, block: &mut [u8; $block_size]) {
+                self.encrypt_block_delta(block, &[0; $crate::constants::TWEAK_COUNT]);
+            }
+
+            /// Decrypts one block in place.
+            pub fn decrypt_block(&self, block: &mut [u8; $block_size]) {
+                self.decrypt_block_delta(block, &[0; $crate::constants::TWEAK_COUNT]);
+            }
+
+            fn encrypt_block_delta(&self, block: &mut [u8; $block_size], delta: &[u64; $crate::constants::TWEAK_COUNT]) {
+                let mut words: [u64; $words] = $crate::utils::load_words(block);
+                $crate::mix::encrypt_words(&mut words, &self.ks, delta, &$rotations, &$permutation);
+                $crate::utils::store_words(&words, block);
+            }
+
+            fn decrypt_block_delta(&self, block: &mut [u8; $block_size], delta: &[u64; $crate::constants::TWEAK_COUNT]) {
+                let mut words: [u64; $words] = $crate::utils::load_words(block);
+                $crate::mix::decrypt_words(&mut words, &self.ks, delta, &$rotations, &$permutation);
+                $crate::utils::store_words(&words, block);
+            }
+
+            fn encrypt_delta(&self, src: &[u8], dst: &mut [u8], delta: &[u64; $crate::constants::TWEAK_COUNT]) -> Result<(), $crate::CipherError> {
+                if src.len() != $block_size {
+                    return Err($crate::CipherError::InvalidPlaintextLength);
+                }
+
+                if dst.len() < $block_size {
+                    return Err($crate::CipherError::InvalidCiphertextLength);
+                }
+
+                dst[..$block_size].copy_from_slice(src);
+                self.encrypt_block_delta((&mut dst[..$block_size]).try_into().unwrap(), delta);
+
+                Ok(())
+            }
+
+            fn decrypt_delta(&self, src: &[u8], dst: &mut [u8], delta: &[u64; $crate::constants::TWEAK_COUNT]) -> Result<(), $crate::CipherError> {
+                if src.len() != $block_size {
+                    return Err($crate::CipherError::InvalidCiphertextLength);
+                }
+
+                if dst.len() < $block_size {
+                    return Err($crate::CipherError::InvalidPlaintextLength);
+                }
+
+                dst[..$block_size].copy_from_slice(src);
+                self.decrypt_block_delta((&mut dst[..$block_size]).try_into().unwrap(), delta);
+
+                Ok(())
+            }
+        }
+
+        /// Wipes the key schedule, the cipher is unusable afterwards.
+        impl ::zeroize::Zeroize for $name {
+            fn zeroize(&mut self) {
+                ::zeroize::Zeroize::zeroize(&mut self.ks);
+                ::zeroize::Zeroize::zeroize(&mut self.t);
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                ::zeroize::Zeroize::zeroize(self);
+            }
+        }
+
+        impl $crate::CipherBlock for $name {
+            fn encrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), $crate::CipherError> {
+                self.encrypt_delta(src, dst, &[0; $crate::constants::TWEAK_COUNT])
+            }
+
+            /// Decrypt loads ciphertext from src, decrypts it, and stores it in dst.
+            fn decrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), $crate::CipherError> {
+                self.decrypt_delta(src, dst, &[0; $crate::constants::TWEAK_COUNT])
+            }
+
+            fn encrypt_batch(&self, blocks: &mut [u8]) -> Result<(), $crate::CipherError> {
+                if !blocks.len().is_multiple_of($block_size) {
+                    return Err($crate::CipherError::InvalidPlaintextLength);
+                }
+
+                for block in blocks.chunks_exact_mut($block_size) {
+                    self.encrypt_block(block.try_into().unwrap());
+                }
+
+                Ok(())
+            }
+
+            fn decrypt_batch(&self, blocks: &mut [u8]) -> Result<(), $crate::CipherError> {
+                if !blocks.len().is_multiple_of($block_size) {
+                    return Err($crate::CipherError::InvalidCiphertextLength);
+                }
+
+                for block in blocks.chunks_exact_mut($block_size) {
+                    self.decrypt_block(block.try_into().unwrap());
+                }
+
+                Ok(())
+            }
+
+            fn get_block_size(&self) -> usize {
+                $block_size
+            }
+        }
+
+        /// The key schedule is kept, only its tweak terms are moved to `tweak`.
+        impl $crate::TweakableBlock for $name {
+            fn encrypt_tweaked(&self, tweak: &[u8], src: &[u8], dst: &mut [u8]) -> Result<(), $crate::CipherError> {
+                self.encrypt_delta(src, dst, &$crate::mix::tweak_delta(tweak, &self.t)?)
+            }
+
+            fn decrypt_tweaked(&self, tweak: &[u8], src: &[u8], dst: &mut [u8]) -> Result<(), $crate::CipherError> {
+                self.decrypt_delta(src, dst, &$crate::mix::tweak_delta(tweak, &self.t)?)
+            }
+        }
+    };
+}
+
+
 #[cfg(test)]
 mod mix_tests {
     use super::*;
 
     #[test]
     fn mix_overflow() {
-        let mix = Mix::new(0, 1);
         let (x0, x1) = (u64::MAX, 2);
-        let (y0, _) = mix.mix(x0, x1);
+        let (y0, _) = mix(x0, x1, constants::R4[0][1]);
         
         assert_eq!(y0, 1);
     }
 
     #[test]
     fn mix_check_res() {
-        let mix = Mix::new(4, 1);
         let (x0, x1) = (15, 44);
-        let (y0, y1) = mix.mix(x0, x1);
+        let (y0, y1) = mix(x0, x1, constants::R4[4][1]);
         
         assert_eq!(y0, 59);
         assert_eq!(y1, 377957122107);
 
 
-        let mix = Mix::new(2, 0);
         let (x0, x1) = (0x198248612874123, 0x123127121824178);
-        let (y0, y1) = mix.mix(x0, x1);
+        let (y0, y1) = mix(x0, x1, constants::R4[2][0]);
         
         assert_eq!(y0, 196811444078609051);
         assert_eq!(y1, 4191716383270703890);
@@ -64,16 +314,14 @@ mod mix_tests {
 
     #[test]
     fn demix_check_res() {
-        let mix = Mix::new(4, 1);
         let (y0, y1) = (59, 377957122107);
-        let (x0, x1) = mix.demix(y0, y1);
+        let (x0, x1) = demix(y0, y1, constants::R4[4][1]);
         
         assert_eq!(x0, 15);
         assert_eq!(x1, 44);
 
-        let mix = Mix::new(2, 0);
         let (y0, y1) = (196811444078609051, 4191716383270703890);
-        let (x0, x1) = mix.demix(y0, y1);
+        let (x0, x1) = demix(y0, y1, constants::R4[2][0]);
         
         assert_eq!(x0, 0x198248612874123);
         assert_eq!(x1, 0x123127121824178);
@@ -0,0 +1,110 @@
+use super::utils::{from_hex, to_hex};
+use super::CipherError;
+
+use ed25519_dalek::Signer;
+use rand::rngs::OsRng;
+use zeroize::Zeroizing;
+
+use std::fmt;
+
+/// Length of Ed25519 public and secret keys.
+pub const KEY_SIZE: usize = 32;
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// SigningKey is the Ed25519 secret key of a publisher. It signs archives
+/// anyone holding its [`VerifyingKey`] can check the origin of.
+pub struct SigningKey {
+    key: ed25519_dalek::SigningKey,
+}
+
+impl SigningKey {
+    pub fn generate() -> SigningKey {
+        SigningKey { key: ed25519_dalek::SigningKey::generate(&mut OsRng) }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SigningKey, CipherError> {
+        let bytes: Zeroizing<[u8; KEY_SIZE]> = Zeroizing::new(bytes.try_into().map_err(|_| CipherError::InvalidKeyLength)?);
+        Ok(SigningKey { key: ed25519_dalek::SigningKey::from_bytes(&bytes) })
+    }
+
+    pub fn from_hex(s: &str) -> Result<SigningKey, CipherError> {
+        SigningKey::from_bytes(&Zeroizing::new(from_hex(s.trim())?))
+    }
+
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(to_hex(self.key.as_bytes()))
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey { key: self.key.verifying_key() }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+        self.key.sign(message).to_bytes()
+    }
+}
+
+/// VerifyingKey is the Ed25519 public key signatures are checked against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifyingKey {
+    key: ed25519_dalek::VerifyingKey,
+}
+
+impl VerifyingKey {
+    pub fn from_bytes(bytes: &[u8]) -> Result<VerifyingKey, CipherError> {
+        let bytes: [u8; KEY_SIZE] = bytes.try_into().map_err(|_| CipherError::InvalidKeyLength)?;
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&bytes).map_err(|_| CipherError::InvalidKeyLength)?;
+        Ok(VerifyingKey { key })
+    }
+
+    pub fn from_hex(s: &str) -> Result<VerifyingKey, CipherError> {
+        VerifyingKey::from_bytes(&from_hex(s.trim())?)
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_SIZE] {
+        self.key.to_bytes()
+    }
+
+    /// Checks `signature` of `message`. Malleable signatures and weak keys are rejected.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), CipherError> {
+        let signature = ed25519_dalek::Signature::from_slice(signature).map_err(|_| CipherError::InvalidSignature)?;
+        self.key.verify_strict(message, &signature).map_err(|_| CipherError::InvalidSignature)
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(self.key.as_bytes()))
+    }
+}
+
+
+#[cfg(test)]
+mod signing_test {
+    use super::*;
+
+    #[test]
+    fn sign_verify_res() {
+        let key = SigningKey::generate();
+        let signature = key.sign(b"archive digest");
+
+        assert_eq!(key.verifying_key().verify(b"archive digest", &signature), Ok(()));
+        assert_eq!(key.verifying_key().verify(b"archive digesT", &signature), Err(CipherError::InvalidSignature));
+        assert_eq!(SigningKey::generate().verifying_key().verify(b"archive digest", &signature), Err(CipherError::InvalidSignature));
+        assert_eq!(key.verifying_key().verify(b"archive digest", &signature[1..]), Err(CipherError::InvalidSignature));
+    }
+
+    #[test]
+    fn hex_res() {
+        let key = SigningKey::generate();
+
+        let restored = SigningKey::from_hex(&key.to_hex()).unwrap();
+        assert_eq!(restored.verifying_key(), key.verifying_key());
+
+        let public = VerifyingKey::from_hex(&format!("{}\n", key.verifying_key())).unwrap();
+        assert_eq!(public, key.verifying_key());
+
+        assert_eq!(VerifyingKey::from_hex("abc").unwrap_err(), CipherError::InvalidKeyLength);
+        assert!(matches!(SigningKey::from_hex(&"00".repeat(31)), Err(CipherError::InvalidKeyLength)));
+    }
+}
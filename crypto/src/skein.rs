@@ -0,0 +1,280 @@
+use super::{CipherBlock, CipherError};
+use super::constants::*;
+use super::threefish256::Cipher256;
+use super::threefish512::Cipher512;
+use super::threefish1024::Cipher1024;
+
+use zeroize::{Zeroize, Zeroizing};
+
+/// Types of the UBI blocks, stored in bits 120..126 of the tweak.
+const TYPE_KEY: u64 = 0;
+const TYPE_CFG: u64 = 4;
+const TYPE_KDF: u64 = 16;
+const TYPE_MSG: u64 = 48;
+const TYPE_OUT: u64 = 63;
+
+/// "SHA3" in little endian, the schema identifier of the configuration block.
+const SCHEMA_ID: u32 = 0x3341_4853;
+const SCHEMA_VERSION: u16 = 1;
+const CONFIG_SIZE: usize = 32;
+
+/// Identifier of the keys derived for archives with [`kdf`].
+pub const ARCHIVE_KEY_ID: &[u8] = b"crypto_container archive key";
+
+/// Skein is the hash function built on Threefish. Every input is chained
+/// through UBI: the blocks are encrypted with the chaining value as the key
+/// and the position and type of the block in the tweak, then xored with
+/// the plaintext to get the next chaining value.
+///
+/// The state size picks Threefish-256, 512 or 1024, the output size is
+/// independent of it. A key turns the hash into Skein-MAC.
+#[derive(Clone)]
+pub struct Skein {
+    state_size: usize,
+    output_size: usize,
+    message_type: u64,
+    chain: Vec<u8>,
+    buffer: Vec<u8>,
+    position: u64,
+}
+
+impl Skein {
+    /// New creates a Skein hash with a state of `state_size` bytes (32, 64 or 128)
+    /// giving `output_size` bytes.
+    pub fn new(state_size: usize, output_size: usize) -> Result<Skein, CipherError> {
+        Skein::with_key(state_size, output_size, &[])
+    }
+
+    /// Skein-MAC: the key is chained before the configuration block.
+    /// An empty key gives the plain hash.
+    pub fn with_key(state_size: usize, output_size: usize, key: &[u8]) -> Result<Skein, CipherError> {
+        if ![BLOCK_SIZE256, BLOCK_SIZE512, BLOCK_SIZE1024].contains(&state_size) {
+            return Err(CipherError::InvalidStateSize);
+        }
+        if output_size == 0 {
+            return Err(CipherError::InvalidOutputLength);
+        }
+
+        let mut chain = vec![0u8; state_size];
+        if !key.is_empty() {
+            ubi(&mut chain, key, TYPE_KEY);
+        }
+
+        let mut config = [0u8; CONFIG_SIZE];
+        config[..4].copy_from_slice(&SCHEMA_ID.to_le_bytes());
+        config[4..6].copy_from_slice(&SCHEMA_VERSION.to_le_bytes());
+        config[8..16].copy_from_slice(&(output_size as u64 * 8).to_le_bytes());
+        ubi(&mut chain, &config, TYPE_CFG);
+
+        Ok(Skein {
+            state_size,
+            output_size,
+            message_type: TYPE_MSG,
+            chain,
+            buffer: Vec::with_capacity(state_size),
+            position: 0,
+        })
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        // the last block is kept until finalize, it has to be flagged as final
+        while self.buffer.len() + data.len() > self.state_size {
+            let n = self.state_size - self.buffer.len();
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+
+            self.position += self.state_size as u64;
+            ubi_block(&mut self.chain, &self.buffer, self.position, self.message_type, self.position == self.state_size as u64, false);
+            self.buffer.clear();
+        }
+
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn finalize(mut self) -> Vec<u8> {
+        let first = self.position == 0;
+        self.position += self.buffer.len() as u64;
+        self.buffer.resize(self.state_size, 0);
+        ubi_block(&mut self.chain, &self.buffer, self.position, self.message_type, first, true);
+
+        let mut output = Vec::with_capacity(self.output_size);
+        for counter in 0..self.output_size.div_ceil(self.state_size) as u64 {
+            let mut chain = Zeroizing::new(self.chain.clone());
+            ubi(&mut chain, &counter.to_le_bytes(), TYPE_OUT);
+            output.extend_from_slice(&chain);
+        }
+        output.truncate(self.output_size);
+
+        output
+    }
+}
+
+/// The chaining value of a keyed Skein depends on the key, so it is wiped as well as the buffered input.
+impl Zeroize for Skein {
+    fn zeroize(&mut self) {
+        self.chain.zeroize();
+        self.buffer.zeroize();
+    }
+}
+
+impl Drop for Skein {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+/// Skein-256-256.
+pub fn skein256(data: &[u8]) -> Vec<u8> {
+    hash(BLOCK_SIZE256, data, 32)
+}
+
+/// Skein-512-512.
+pub fn skein512(data: &[u8]) -> Vec<u8> {
+    hash(BLOCK_SIZE512, data, 64)
+}
+
+pub fn hash(state_size: usize, data: &[u8], output_size: usize) -> Vec<u8> {
+    let mut skein = Skein::new(state_size, output_size).unwrap();
+    skein.update(data);
+    skein.finalize()
+}
+
+/// Skein-512 MAC of `data` with `key`.
+pub fn mac(key: &[u8], data: &[u8], output_size: usize) -> Vec<u8> {
+    let mut skein = Skein::with_key(BLOCK_SIZE512, output_size, key).unwrap();
+    skein.update(data);
+    skein.finalize()
+}
+
+/// Skein-512 KDF: derives `length` bytes from `master_key` for the key identified by `id`.
+/// Different identifiers give independent keys from the same master key.
+pub fn kdf(master_key: &[u8], id: &[u8], length: usize) -> Zeroizing<Vec<u8>> {
+    let mut skein = Skein::with_key(BLOCK_SIZE512, length, master_key).unwrap();
+    skein.message_type = TYPE_KDF;
+    skein.update(id);
+    Zeroizing::new(skein.finalize())
+}
+
+/// Chains a whole input of one type.
+fn ubi(chain: &mut [u8], data: &[u8], block_type: u64) {
+    let state_size = chain.len();
+    if data.is_empty() {
+        ubi_block(chain, &vec![0u8; state_size], 0, block_type, true, true);
+        return;
+    }
+
+    let blocks = data.len().div_ceil(state_size);
+    for (i, block) in data.chunks(state_size).enumerate() {
+        let mut padded = Zeroizing::new(block.to_vec());
+        padded.resize(state_size, 0);
+
+        let position = (i * state_size + block.len()) as u64;
+        ubi_block(chain, &padded, position, block_type, i == 0, i == blocks - 1);
+    }
+}
+
+/// Encrypts `block` keyed with the chaining value and replaces it with the ciphertext xor `block`.
+/// `position` is the number of input bytes processed including this block.
+fn ubi_block(chain: &mut [u8], block: &[u8], position: u64, block_type: u64, first: bool, last: bool) {
+    let t1 = block_type << 56 | (first as u64) << 62 | (last as u64) << 63;
+
+    let mut tweak = [0u8; TWEAK_SIZE];
+    tweak[..8].copy_from_slice(&position.to_le_bytes());
+    tweak[8..].copy_from_slice(&t1.to_le_bytes());
+
+    let cipher: Box<dyn CipherBlock> = match chain.len() {
+        BLOCK_SIZE256 => Box::new(Cipher256::new(chain, &tweak).unwrap()),
+        BLOCK_SIZE512 => Box::new(Cipher512::new(chain, &tweak).unwrap()),
+        _ => Box::new(Cipher1024::new(chain, &tweak).unwrap()),
+    };
+
+    cipher.encrypt(block, chain).unwrap();
+    chain.iter_mut()
+        .zip(block)
+        .for_each(|(x1, x2)| *x1 ^= x2);
+}
+
+
+#[cfg(test)]
+mod skein_test {
+    use super::*;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i+2], 16).unwrap()).collect()
+    }
+
+    fn descending(length: usize) -> Vec<u8> {
+        (0..length).map(|i| 0xff - i as u8).collect()
+    }
+
+    #[test]
+    fn skein256_known_answer() {
+        assert_eq!(skein256(&[0xff]), from_hex("0b98dcd198ea0e50a7a244c444e25c23da30c10fc9a1f270a6637f1f34e67ed2"));
+        assert_eq!(skein256(&descending(64)), from_hex("df28e916630d0b44c4a849dc9a02f07a07cb30f732318256b15d865ac4ae162f"));
+        assert_eq!(skein256(b""), from_hex("c8877087da56e072870daa843f176e9453115929094c3a40c463a196c29bf7ba"));
+    }
+
+    #[test]
+    fn skein512_known_answer() {
+        assert_eq!(skein512(&[0xff]), from_hex("
+            71b7bce6fe6452227b9ced6014249e5bf9a9754c3ad618ccc4e0aae16b316cc8
+            ca698d864307ed3e80b6ef1570812ac5272dc409b5a012df2a579102f340617a"));
+        assert_eq!(skein512(&descending(64)), from_hex("
+            45863ba3be0c4dfc27e75d358496f4ac9a736a505d9313b42b2f5eada79fc17f
+            63861e947afb1d056aa199575ad3f8c9a3cc1780b5e5fa4cae050e989876625b"));
+        assert_eq!(hash(BLOCK_SIZE512, b"", 32), from_hex("39ccc4554a8b31853b9de7a1fe638a24cce6b35a55f2431009e18780335d2621"));
+    }
+
+    #[test]
+    fn update_in_chunks() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+
+        for state_size in [BLOCK_SIZE256, BLOCK_SIZE512, BLOCK_SIZE1024] {
+            let mut skein = Skein::new(state_size, 48).unwrap();
+            for chunk in data.chunks(13) {
+                skein.update(chunk);
+            }
+
+            assert_eq!(skein.finalize(), hash(state_size, &data, 48));
+        }
+    }
+
+    #[test]
+    fn mac_res() {
+        let data = b"lorem ipsum dolor sit amet";
+
+        assert_eq!(mac(b"", data, 64), skein512(data));
+        assert_ne!(mac(b"key", data, 64), skein512(data));
+        assert_ne!(mac(b"key", data, 64), mac(b"kez", data, 64));
+        assert_eq!(mac(b"key", data, 32), mac(b"key", data, 32));
+    }
+
+    #[test]
+    fn kdf_res() {
+        let key = kdf(b"password", ARCHIVE_KEY_ID, 128);
+        assert_eq!(key.len(), 128);
+        assert_eq!(key, kdf(b"password", ARCHIVE_KEY_ID, 128));
+
+        // longer outputs are not extensions of shorter ones, the length is in the config
+        assert_ne!(kdf(b"password", ARCHIVE_KEY_ID, 32)[..], key[..32]);
+        assert_ne!(kdf(b"password", b"other key", 128), key);
+        assert_ne!(kdf(b"passwore", ARCHIVE_KEY_ID, 128), key);
+    }
+
+    #[test]
+    fn new_errors() {
+        assert_eq!(Skein::new(48, 32).err(), Some(CipherError::InvalidStateSize));
+        assert_eq!(Skein::new(BLOCK_SIZE256, 0).err(), Some(CipherError::InvalidOutputLength));
+    }
+
+    #[test]
+    fn zeroize_res() {
+        let mut skein = Skein::with_key(BLOCK_SIZE256, 32, b"key").unwrap();
+        skein.update(b"message");
+        skein.zeroize();
+
+        assert!(skein.chain.iter().all(|&b| b == 0));
+        assert!(skein.buffer.iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,74 @@
+use super::constants::*;
+
+threefish_cipher!(Cipher1024, "1024", BLOCK_SIZE1024, NUM_WORDS1024, ROUND_KEYS_COUNT1024, R16, PI16);
+
+
+#[cfg(test)]
+mod cipher1024_test {
+    use super::*;
+    use crate::{CipherBlock, CipherError};
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i+2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn new_errors(){
+        let tweak: Vec<u8> = (0..15).collect();
+        let key: Vec<u8> = (0..128).collect();
+
+        let r = Cipher1024::new(&key, &tweak);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidTweakLength);
+
+        let tweak: Vec<u8> = (0..16).collect();
+        let key: Vec<u8> = (0..32).collect();
+
+        let r = Cipher1024::new(&key, &tweak);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidKeyLength);
+    }
+
+    #[test]
+    fn encrypt_decrypt_errors(){
+        let c = Cipher1024::new(&[0; 128], &[0; 16]).unwrap();
+
+        let r = c.encrypt(&[0; 127], &mut [0; 128]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidPlaintextLength);
+        let r = c.encrypt(&[0; 128], &mut [0; 127]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidCiphertextLength);
+
+        let r = c.decrypt(&[0; 127], &mut [0; 128]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidCiphertextLength);
+        let r = c.decrypt(&[0; 128], &mut [0; 127]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidPlaintextLength);
+    }
+
+    #[test]
+    fn known_answer_zero(){
+        let c = Cipher1024::new(&[0; 128], &[0; 16]).unwrap();
+
+        let mut ciphertext = vec![0u8; 128];
+        c.encrypt(&[0; 128], &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, from_hex("f05c3d0a3d05b304f785ddc7d1e036015c8aa76e2f217b06c6e1544c0bc1a90df0accb9473c24e0fd54fea68057f43329cb454761d6df5cf7b2e9b3614fbd5a20b2e4760b40603540d82eabc5482c171c832afbe68406bc39500367a592943fa9a5b4a43286ca3c4cf46104b443143d560a4b230488311df4feef7e1dfe8391e"));
+
+        let mut plaintext = vec![0u8; 128];
+        c.decrypt(&ciphertext, &mut plaintext).unwrap();
+        assert_eq!(plaintext, vec![0u8; 128]);
+    }
+
+    #[test]
+    fn known_answer(){
+        let key: Vec<u8> = (0x10..0x10 + 128).map(|b| b as u8).collect();
+        let tweak: Vec<u8> = (0..16).collect();
+        let plaintext: Vec<u8> = (0..128).map(|i| 0xff - i as u8).collect();
+
+        let c = Cipher1024::new(&key, &tweak).unwrap();
+
+        let mut ciphertext = vec![0u8; 128];
+        c.encrypt(&plaintext, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, from_hex("a6654ddbd73cc3b05dd777105aa849bce49372eaaffc5568d254771bab85531c94f780e7ffaae430d5d8af8c70eebbe1760f3b42b737a89cb363490d670314bd8aa41ee63c2e1f45fbd477922f8360b388d6125ea6c7af0ad7056d01796e90c83313f4150a5716b30ed5f569288ae974ce2b4347926fce57de44512177dd7cde"));
+
+        let mut decrypted = vec![0u8; 128];
+        c.decrypt(&ciphertext, &mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+}
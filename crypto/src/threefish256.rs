@@ -1,158 +1,19 @@
-use super::{constants::*, utils::*, mix::*, CipherError, CipherBlock};
-
-use std::sync::Arc;
-
-#[derive(Debug)]
-pub struct Cipher256 {
-    t:  [u64; TWEAK_COUNT],
-    ks: [[u64; NUM_WORDS256]; ROUND_KEYS_COUNT],
-}
-
-impl Cipher256 {
-    /// New256 creates a new Threefish cipher with a block size of 256 bits.
-    /// The key argument must be 32 bytes and the tweak argument must be 16 bytes.
-    pub fn new(key: &[u8], tweak: &[u8]) -> Result<Cipher256, CipherError> {
-        // Length check the provided key
-        if key.len() != BLOCK_SIZE256 {
-            return Err(CipherError::InvalidKeyLength);
-        }
-
-        // Load and extend the tweak value
-        let t = calculate_tweak(tweak)?;
-
-
-        let mut c: Cipher256 = Cipher256{
-            t: t.try_into().expect("Mismatch size in tweak"),
-            ks: [[0; NUM_WORDS256]; ROUND_KEYS_COUNT],
-        };
-
-        // Load and extend key
-        let mut k = vec![0; NUM_WORDS256 + 1];
-        k[NUM_WORDS256] = C240;
-        for i in 0..NUM_WORDS256 {
-            k[i] = load_word(&key[i*8..(i+1)*8]);
-            k[NUM_WORDS256] ^= k[i];
-        }
-
-        for s in 0..=NUM_ROUNDS256/4 {
-            for i in 0..NUM_WORDS256 {
-                c.ks[s][i] = k[(s+i)%(NUM_WORDS256+1)];
-                
-                let last_add = if i == NUM_WORDS256 - 3 {
-                    c.t[s%3]
-                } else if i == NUM_WORDS256 - 2 {
-                    c.t[(s+1)%3]
-                } else if i == NUM_WORDS256 - 1 {
-                    s as u64
-                } else {
-                    0
-                };
-                c.ks[s][i] = c.ks[s][i].wrapping_add(last_add);
-            }
-        }
-        Ok(c)
-    }
-}
-
-impl CipherBlock for Cipher256 {
-    fn encrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), CipherError> {
-        if src.len() != BLOCK_SIZE256 {
-            return Err(CipherError::InvalidPlaintextLength);
-        }
-
-        if dst.len() < BLOCK_SIZE256 {
-            return Err(CipherError::InvalidCiphertextLength);
-        }
-
-        let mut words = Vec::new();
-        for i in 0..NUM_WORDS256 {
-            let word = load_word(&src[i*8..(i+1)*8]);
-            words.push(word);
-        }
-
-        for d in 0..(ROUND_KEYS_COUNT-1) {
-            for i in 0..NUM_WORDS256 {
-                words[i] = words[i].wrapping_add(self.ks[d][i]);
-            }
-
-            for i in 0..NUM_WORDS256 {
-                for j in 0..(NUM_WORDS256/2) {
-                    let mix: Mix = Mix::new(d*4+i, j);
-                    (words[2*j], words[2*j+1]) = mix.mix(words[2*j], words[2*j+1]);
-                }
-                
-                (words[1], words[3]) = (words[3], words[1]);
-            }
-        }
-
-        for i in 0..NUM_WORDS256 {
-            words[i] = words[i].wrapping_add(self.ks[ROUND_KEYS_COUNT-1][i]);
-        }
-        
-        for i in 0..NUM_WORDS256 {
-            store_word(words[i], &mut dst[(i*8)..(i+1)*8]);
-        }
-
-        Ok(())
-
-    }
-
-
-    /// Decrypt loads ciphertext from src, decrypts it, and stores it in dst.
-    fn decrypt(&self, src: &[u8], dst: &mut [u8]) -> Result<(), CipherError> {
-        if src.len() != BLOCK_SIZE256 {
-            return Err(CipherError::InvalidCiphertextLength);
-        }
-
-        if dst.len() < BLOCK_SIZE256 {
-            return Err(CipherError::InvalidPlaintextLength);
-        }
-
-        // Load the ciphertext
-        let mut words = Vec::new();
-        for i in 0..NUM_WORDS256 {
-            let word = load_word(&src[i*8..(i+1)*8]);
-            words.push(word);
-        }
-
-        // Subtract the final round key
-        for i in 0..NUM_WORDS256 {
-            words[i] = words[i].wrapping_sub(self.ks[ROUND_KEYS_COUNT-1][i]);
-        }
-
-        // Perform decryption rounds
-        for d in (0..ROUND_KEYS_COUNT-1).rev() {
-            for i in (0..NUM_WORDS256).rev() {
-                (words[1], words[3]) = (words[3], words[1]);
-
-                for j in (0..NUM_WORDS256/2).rev() {
-                    let mix = Mix::new(d*4+i, j);
-                    (words[2*j], words[2*j+1]) = mix.demix(words[2*j], words[2*j+1]);
-                }
-            }
-
-            for i in 0..NUM_WORDS256 {
-                words[i] = words[i].wrapping_sub(self.ks[d][i]);
-            }
-        }
-  
-        for i in 0..NUM_WORDS256 {
-            store_word(words[i], &mut dst[(i*8)..(i+1)*8]);
-        }
-
-        Ok(())
-    }
-
-    fn get_block_size(&self) -> usize {
-        BLOCK_SIZE256
-    }
-}
+use super::constants::*;
 
+threefish_cipher!(Cipher256, "256", BLOCK_SIZE256, NUM_WORDS256, ROUND_KEYS_COUNT, R4, PI4);
 
 
 #[cfg(test)]
 mod cipher256_test {
     use super::*;
+    use crate::{CipherBlock, CipherError, TweakableBlock};
+
+    use std::sync::Arc;
+    use zeroize::Zeroize;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i+2], 16).unwrap()).collect()
+    }
 
     #[test]
     fn new_errors(){
@@ -183,7 +44,6 @@ mod cipher256_test {
 
 
         let expected = Cipher256 { 
-            t: [506097522914230528, 1084818905618843912, 578721382704613384], 
             ks: [[506097522914230528, 1590916428533074440, 2748359193942301208, 2242261671028070680], 
                 [1084818905618843912, 2748359193942301208, 2820983053732684064, 2004413935125273123], 
                 [1663540288323457296, 2820983053732684064, 2510511458039503650, 506097522914230530], 
@@ -202,10 +62,10 @@ mod cipher256_test {
                 [506097522914230528, 1590916428533074440, 2748359193942301208, 2242261671028070695], 
                 [1084818905618843912, 2748359193942301208, 2820983053732684064, 2004413935125273138], 
                 [1663540288323457296, 2820983053732684064, 2510511458039503650, 506097522914230545], 
-                [2242261671028070680, 2510511458039503650, 1590916428533074440, 1084818905618843930]]
+                [2242261671028070680, 2510511458039503650, 1590916428533074440, 1084818905618843930]],
+            t: [0x0706050403020100, 0x0f0e0d0c0b0a0908, 0x0808080808080808],
         };
 
-        assert_eq!(r.t, expected.t, "tweaks are incorrect");
         assert_eq!(r.ks, expected.ks, "round keys are incorrect");
 
     }
@@ -339,4 +199,94 @@ mod cipher256_test {
 
         assert_eq!(plaintext, expected); 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn known_answer_zero(){
+        let c = Cipher256::new(&[0; 32], &[0; 16]).unwrap();
+
+        let mut ciphertext = vec![0u8; 32];
+        c.encrypt(&[0; 32], &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, from_hex("84da2a1f8beaee947066ae3e3103f1ad536db1f4a1192495116b9f3ce6133fd8"));
+    }
+
+    #[test]
+    fn known_answer(){
+        let key: Vec<u8> = (0x10..0x30).collect();
+        let tweak: Vec<u8> = (0..16).collect();
+        let plaintext: Vec<u8> = (0..32).map(|i| 0xff - i as u8).collect();
+
+        let c = Cipher256::new(&key, &tweak).unwrap();
+
+        let mut ciphertext = vec![0u8; 32];
+        c.encrypt(&plaintext, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, from_hex("e0d091ff0eea8fdfc98192e62ed80ad59d865d08588df476657056b5955e97df"));
+
+        let mut decrypted = vec![0u8; 32];
+        c.decrypt(&ciphertext, &mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn block_batch_res(){
+        let key: Vec<u8> = (0..32).collect();
+        let tweak: Vec<u8> = (0..16).collect();
+        let c = Cipher256::new(&key, &tweak).unwrap();
+
+        let plaintext: Vec<u8> = (0..32 * 5).map(|i| (i * 7) as u8).collect();
+
+        let mut batch = plaintext.clone();
+        c.encrypt_batch(&mut batch).unwrap();
+
+        for (block, ciphertext) in plaintext.chunks(32).zip(batch.chunks(32)) {
+            let mut expected = vec![0u8; 32];
+            c.encrypt(block, &mut expected).unwrap();
+            assert_eq!(ciphertext, expected);
+
+            let mut in_place: [u8; 32] = block.try_into().unwrap();
+            c.encrypt_block(&mut in_place);
+            assert_eq!(in_place[..], expected[..]);
+
+            c.decrypt_block(&mut in_place);
+            assert_eq!(in_place[..], block[..]);
+        }
+
+        c.decrypt_batch(&mut batch).unwrap();
+        assert_eq!(batch, plaintext);
+
+        assert_eq!(c.encrypt_batch(&mut [0; 33]).unwrap_err(), CipherError::InvalidPlaintextLength);
+        assert_eq!(c.decrypt_batch(&mut [0; 31]).unwrap_err(), CipherError::InvalidCiphertextLength);
+    }
+
+    #[test]
+    fn tweaked_res(){
+        let key: Vec<u8> = (0..32).collect();
+        let c = Cipher256::new(&key, &[0; 16]).unwrap();
+        let plaintext: Vec<u8> = (0..32).map(|i| i * 3).collect();
+
+        for tweak in [[0u8; 16], [0xff; 16], core::array::from_fn(|i| i as u8 * 17)] {
+            let expected_cipher = Cipher256::new(&key, &tweak).unwrap();
+            let mut expected = vec![0u8; 32];
+            expected_cipher.encrypt(&plaintext, &mut expected).unwrap();
+
+            let mut ciphertext = vec![0u8; 32];
+            c.encrypt_tweaked(&tweak, &plaintext, &mut ciphertext).unwrap();
+            assert_eq!(ciphertext, expected);
+
+            let mut decrypted = vec![0u8; 32];
+            c.decrypt_tweaked(&tweak, &ciphertext, &mut decrypted).unwrap();
+            assert_eq!(decrypted, plaintext);
+        }
+
+        assert_eq!(c.encrypt_tweaked(&[0; 15], &plaintext, &mut [0; 32]).unwrap_err(), CipherError::InvalidTweakLength);
+    }
+
+    #[test]
+    fn zeroize_res(){
+        let key: Vec<u8> = (0..32).collect();
+        let mut c = Cipher256::new(&key, &[1; 16]).unwrap();
+        c.zeroize();
+
+        assert_eq!(c.ks, [[0; NUM_WORDS256]; ROUND_KEYS_COUNT]);
+        assert_eq!(c.t, [0; 3]);
+    }
+}
@@ -0,0 +1,74 @@
+use super::constants::*;
+
+threefish_cipher!(Cipher512, "512", BLOCK_SIZE512, NUM_WORDS512, ROUND_KEYS_COUNT512, R8, PI8);
+
+
+#[cfg(test)]
+mod cipher512_test {
+    use super::*;
+    use crate::{CipherBlock, CipherError};
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i+2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn new_errors(){
+        let tweak: Vec<u8> = (0..15).collect();
+        let key: Vec<u8> = (0..64).collect();
+
+        let r = Cipher512::new(&key, &tweak);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidTweakLength);
+
+        let tweak: Vec<u8> = (0..16).collect();
+        let key: Vec<u8> = (0..32).collect();
+
+        let r = Cipher512::new(&key, &tweak);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidKeyLength);
+    }
+
+    #[test]
+    fn encrypt_decrypt_errors(){
+        let c = Cipher512::new(&[0; 64], &[0; 16]).unwrap();
+
+        let r = c.encrypt(&[0; 63], &mut [0; 64]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidPlaintextLength);
+        let r = c.encrypt(&[0; 64], &mut [0; 63]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidCiphertextLength);
+
+        let r = c.decrypt(&[0; 63], &mut [0; 64]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidCiphertextLength);
+        let r = c.decrypt(&[0; 64], &mut [0; 63]);
+        assert_eq!(r.unwrap_err(), CipherError::InvalidPlaintextLength);
+    }
+
+    #[test]
+    fn known_answer_zero(){
+        let c = Cipher512::new(&[0; 64], &[0; 16]).unwrap();
+
+        let mut ciphertext = vec![0u8; 64];
+        c.encrypt(&[0; 64], &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, from_hex("b1a2bbc6ef6025bc40eb3822161f36e375d1bb0aee3186fbd19e47c5d479947b7bc2f8586e35f0cff7e7f03084b0b7b1f1ab3961a580a3e97eb41ea14a6d7bbe"));
+
+        let mut plaintext = vec![0u8; 64];
+        c.decrypt(&ciphertext, &mut plaintext).unwrap();
+        assert_eq!(plaintext, vec![0u8; 64]);
+    }
+
+    #[test]
+    fn known_answer(){
+        let key: Vec<u8> = (0x10..0x10 + 64).map(|b| b as u8).collect();
+        let tweak: Vec<u8> = (0..16).collect();
+        let plaintext: Vec<u8> = (0..64).map(|i| 0xff - i as u8).collect();
+
+        let c = Cipher512::new(&key, &tweak).unwrap();
+
+        let mut ciphertext = vec![0u8; 64];
+        c.encrypt(&plaintext, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, from_hex("e304439626d45a2cb401cad8d636249a6338330eb06d45dd8b36b90e97254779272a0a8d99463504784420ea18c9a725af11dffea10162348927673d5c1caf3d"));
+
+        let mut decrypted = vec![0u8; 64];
+        c.decrypt(&ciphertext, &mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+}
@@ -15,21 +15,46 @@ pub fn store_word(src: u64, dst: &mut [u8]) {
     dst[..8].copy_from_slice(&byte_array);
 }
 
-pub fn calculate_tweak(tweak: &[u8]) -> Result<Vec<u64>, CipherError> {
+/// Loads the little endian words of a block.
+#[inline(always)]
+pub fn load_words<const N: usize>(src: &[u8]) -> [u64; N] {
+    let mut words = [0u64; N];
+    for (word, bytes) in words.iter_mut().zip(src.chunks_exact(8)) {
+        *word = u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    words
+}
+
+#[inline(always)]
+pub fn store_words<const N: usize>(words: &[u64; N], dst: &mut [u8]) {
+    for (word, bytes) in words.iter().zip(dst.chunks_exact_mut(8)) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+pub fn calculate_tweak(tweak: &[u8]) -> Result<[u64; TWEAK_COUNT], CipherError> {
     if tweak.len() != TWEAK_SIZE {
         return Err(CipherError::InvalidTweakLength);
     }
 
-    let mut dst = Vec::new();
-
     let word0 = load_word(&tweak[0..8]);
     let word1 = load_word(&tweak[8..16]);
 
-    dst.push(word0);
-    dst.push(word1);
-    dst.push(word0 ^ word1);
+    Ok([word0, word1, word0 ^ word1])
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>, CipherError> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(CipherError::InvalidKeyLength);
+    }
 
-    Ok(dst)
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i+2], 16).map_err(|_| CipherError::InvalidKeyLength))
+        .collect()
 }
 
 
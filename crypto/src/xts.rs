@@ -0,0 +1,262 @@
+use super::constants::TWEAK_SIZE;
+use super::{CipherError, CipherProcessor, TweakableBlock};
+
+use std::sync::Arc;
+
+use rand::Rng;
+use zeroize::Zeroizing;
+
+pub const DEFAULT_SECTOR_SIZE: usize = 4096;
+
+/// Block index of the tweak used for sectors shorter than one block.
+const SHORT_SECTOR_INDEX: u64 = u64::MAX;
+
+/// XTSProcessor splits the data into sectors and encrypts block `j` of sector `i`
+/// with the tweak `(i, j)` xored with a nonce, so any sector can be read or
+/// rewritten on its own and the sectors have the length of the plaintext. A sector
+/// ending with a partial block steals the missing bytes from the ciphertext of the
+/// block before it.
+///
+/// Every call of `encrypt_blocks` draws a new nonce and stores it in front of the
+/// ciphertext, so the data encrypted with one key never shares the tweaks. With
+/// one nonce the encryption is deterministic: equal sectors at the same position
+/// encrypt to equal ciphertexts. A sector shorter than one block has nothing to
+/// steal from and is xored with the encryption of its tweak.
+#[derive(Clone)]
+pub struct XTSProcessor {
+    block: Arc<dyn TweakableBlock>,
+    block_size: usize,
+    sector_size: usize,
+}
+
+impl XTSProcessor {
+    /// The sector size must be a multiple of the block size.
+    pub fn new(block: Arc<dyn TweakableBlock>, sector_size: usize) -> Result<XTSProcessor, CipherError> {
+        let block_size = block.get_block_size();
+        if sector_size == 0 || !sector_size.is_multiple_of(block_size) {
+            return Err(CipherError::InvalidSectorSize);
+        }
+
+        Ok(XTSProcessor { block, block_size, sector_size })
+    }
+
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    /// Length of the nonce stored in front of the ciphertext.
+    pub fn nonce_size(&self) -> usize {
+        TWEAK_SIZE
+    }
+
+    /// Encrypts sector number `sector`. Only the last sector of the data may be shorter than the sector size.
+    pub fn encrypt_sector(&self, nonce: &[u8], sector: u64, src: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let nonce: &[u8; TWEAK_SIZE] = nonce.try_into().map_err(|_| CipherError::InvalidIVLength)?;
+        if src.len() > self.sector_size {
+            return Err(CipherError::InvalidPlaintextLength);
+        }
+
+        Ok(self.process_sector(nonce, sector, src, true))
+    }
+
+    pub fn decrypt_sector(&self, nonce: &[u8], sector: u64, src: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let nonce: &[u8; TWEAK_SIZE] = nonce.try_into().map_err(|_| CipherError::InvalidIVLength)?;
+        if src.len() > self.sector_size {
+            return Err(CipherError::InvalidCiphertextLength);
+        }
+
+        Ok(self.process_sector(nonce, sector, src, false))
+    }
+
+    fn process_sector(&self, nonce: &[u8; TWEAK_SIZE], sector: u64, src: &[u8], encrypt: bool) -> Vec<u8> {
+        let bs = self.block_size;
+        let mut dst = vec![0u8; src.len()];
+
+        if src.len() < bs {
+            let mut keystream = Zeroizing::new(vec![0u8; bs]);
+            self.block.encrypt_tweaked(&tweak(nonce, sector, SHORT_SECTOR_INDEX), &vec![0u8; bs], &mut keystream).unwrap();
+
+            dst.iter_mut()
+                .zip(src.iter().zip(keystream.iter()))
+                .for_each(|(x, (s, k))| *x = s ^ k);
+            return dst;
+        }
+
+        let full_blocks = src.len() / bs;
+        let tail = src.len() % bs;
+        // with a partial tail the last full block is processed together with it
+        let plain_blocks = if tail == 0 { full_blocks } else { full_blocks - 1 };
+
+        for j in 0..plain_blocks {
+            self.process_block(nonce, sector, j as u64, &src[j*bs..(j+1)*bs], &mut dst[j*bs..(j+1)*bs], encrypt);
+        }
+
+        if tail != 0 {
+            let m = plain_blocks;
+            let last = &src[m*bs..(m+1)*bs];
+            let partial = &src[(m+1)*bs..];
+
+            // the full block is processed with index m when encrypting and with m + 1 when
+            // decrypting, as encryption wrote the stolen block in its place
+            let (first_index, second_index) = if encrypt { (m, m + 1) } else { (m + 1, m) };
+
+            let mut stolen = Zeroizing::new(vec![0u8; bs]);
+            self.process_block(nonce, sector, first_index as u64, last, &mut stolen, encrypt);
+
+            let mut joined = Zeroizing::new(partial.to_vec());
+            joined.extend_from_slice(&stolen[tail..]);
+
+            dst[(m+1)*bs..].copy_from_slice(&stolen[..tail]);
+            self.process_block(nonce, sector, second_index as u64, &joined, &mut dst[m*bs..(m+1)*bs], encrypt);
+        }
+
+        dst
+    }
+
+    fn process_block(&self, nonce: &[u8; TWEAK_SIZE], sector: u64, index: u64, src: &[u8], dst: &mut [u8], encrypt: bool) {
+        let tweak = tweak(nonce, sector, index);
+        if encrypt {
+            self.block.encrypt_tweaked(&tweak, src, dst).unwrap();
+        } else {
+            self.block.decrypt_tweaked(&tweak, src, dst).unwrap();
+        }
+    }
+}
+
+/// The sector and the block index in little endian, xored with the nonce.
+fn tweak(nonce: &[u8; TWEAK_SIZE], sector: u64, index: u64) -> [u8; TWEAK_SIZE] {
+    let mut tweak = [0u8; TWEAK_SIZE];
+    tweak[..8].copy_from_slice(&sector.to_le_bytes());
+    tweak[8..].copy_from_slice(&index.to_le_bytes());
+    tweak.iter_mut().zip(nonce).for_each(|(t, n)| *t ^= n);
+    tweak
+}
+
+impl CipherProcessor for XTSProcessor {
+    fn encrypt_blocks(&self, src: &[u8]) -> Vec<u8> {
+        let nonce: [u8; TWEAK_SIZE] = rand::thread_rng().gen();
+
+        let mut dst = Vec::with_capacity(TWEAK_SIZE + src.len());
+        dst.extend_from_slice(&nonce);
+        for (i, sector) in src.chunks(self.sector_size).enumerate() {
+            dst.extend(self.process_sector(&nonce, i as u64, sector, true));
+        }
+
+        dst
+    }
+
+    fn decrypt_blocks(&self, src: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if src.len() < TWEAK_SIZE {
+            return Err(CipherError::InvalidCiphertextLength);
+        }
+
+        let (nonce, body) = src.split_at(TWEAK_SIZE);
+        let nonce: &[u8; TWEAK_SIZE] = nonce.try_into().unwrap();
+        Ok(body.chunks(self.sector_size)
+            .enumerate()
+            .flat_map(|(i, sector)| self.process_sector(nonce, i as u64, sector, false))
+            .collect())
+    }
+}
+
+
+#[cfg(test)]
+mod xts_test {
+    use crate::CipherBlock;
+    use crate::threefish256::Cipher256;
+    use crate::threefish1024::Cipher1024;
+    use super::*;
+
+    fn get_processor(sector_size: usize) -> XTSProcessor {
+        let key: Vec<u8> = (0..32).collect();
+        let block = Arc::new(Cipher256::new(&key, &[0; 16]).unwrap());
+
+        XTSProcessor::new(block, sector_size).unwrap()
+    }
+
+    #[test]
+    fn new_errors() {
+        let block = Arc::new(Cipher256::new(&[0; 32], &[0; 16]).unwrap());
+
+        assert!(matches!(XTSProcessor::new(block.clone(), 0), Err(CipherError::InvalidSectorSize)));
+        assert!(matches!(XTSProcessor::new(block.clone(), 100), Err(CipherError::InvalidSectorSize)));
+        assert!(XTSProcessor::new(block, 96).is_ok());
+    }
+
+    #[test]
+    fn first_block_res() {
+        // block 0 of sector 0 is Threefish with the nonce as the tweak
+        let key: Vec<u8> = (0..32).collect();
+        let plaintext: Vec<u8> = (0..32).map(|i| i * 3).collect();
+
+        let ciphertext = get_processor(DEFAULT_SECTOR_SIZE).encrypt_blocks(&plaintext);
+        let (nonce, body) = ciphertext.split_at(TWEAK_SIZE);
+
+        let mut expected = vec![0u8; 32];
+        Cipher256::new(&key, nonce).unwrap().encrypt(&plaintext, &mut expected).unwrap();
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn nonce_res() {
+        let processor = get_processor(128);
+        let plaintext = vec![0u8; 300];
+
+        // equal data encrypted twice shares no tweaks
+        let first = processor.encrypt_blocks(&plaintext);
+        let second = processor.encrypt_blocks(&plaintext);
+        assert_eq!(first.len(), processor.nonce_size() + plaintext.len());
+        assert_ne!(first[..TWEAK_SIZE], second[..TWEAK_SIZE]);
+        assert_ne!(first[TWEAK_SIZE..], second[TWEAK_SIZE..]);
+
+        assert_eq!(processor.decrypt_blocks(&first).unwrap(), plaintext);
+        assert_eq!(processor.decrypt_blocks(&second).unwrap(), plaintext);
+        assert_eq!(processor.decrypt_blocks(&[0; 15]).unwrap_err(), CipherError::InvalidCiphertextLength);
+    }
+
+    #[test]
+    fn decrypt_encrypt_res() {
+        let processor = get_processor(128);
+
+        for length in [0, 1, 31, 32, 33, 63, 64, 100, 128, 129, 150, 160, 1000] {
+            let plaintext: Vec<u8> = (0..length).map(|i| (i * 7) as u8).collect();
+
+            let ciphertext = processor.encrypt_blocks(&plaintext);
+            assert_eq!(ciphertext.len(), TWEAK_SIZE + length);
+            assert_eq!(processor.decrypt_blocks(&ciphertext).unwrap(), plaintext, "length {}", length);
+        }
+    }
+
+    #[test]
+    fn sector_res() {
+        let key: Vec<u8> = (0..128).collect();
+        let block = Arc::new(Cipher1024::new(&key, &[0; 16]).unwrap());
+        let processor = XTSProcessor::new(block, 512).unwrap();
+
+        let plaintext: Vec<u8> = (0..2000u32).map(|i| (i * 13) as u8).collect();
+        let ciphertext = processor.encrypt_blocks(&plaintext);
+        let (nonce, body) = ciphertext.split_at(TWEAK_SIZE);
+
+        // every sector decrypts on its own
+        for (i, sector) in body.chunks(512).enumerate() {
+            let decrypted = processor.decrypt_sector(nonce, i as u64, sector).unwrap();
+            assert_eq!(decrypted, &plaintext[i*512..(i*512 + sector.len())]);
+        }
+
+        // equal sectors differ at different positions
+        let zeros = processor.encrypt_blocks(&[0; 1024]);
+        assert_ne!(zeros[TWEAK_SIZE..TWEAK_SIZE + 512], zeros[TWEAK_SIZE + 512..]);
+
+        // rewriting a sector leaves the others as they are
+        let mut changed = plaintext.clone();
+        changed[600] ^= 1;
+        let rewritten = processor.encrypt_sector(nonce, 1, &changed[512..1024]).unwrap();
+        assert_ne!(rewritten[..], body[512..1024]);
+        assert_eq!(processor.decrypt_sector(nonce, 1, &rewritten).unwrap(), &changed[512..1024]);
+        assert_eq!(processor.encrypt_sector(nonce, 2, &plaintext[1024..1536]).unwrap(), &body[1024..1536]);
+
+        assert_eq!(processor.encrypt_sector(nonce, 0, &[0; 513]).unwrap_err(), CipherError::InvalidPlaintextLength);
+        assert_eq!(processor.decrypt_sector(nonce, 0, &[0; 513]).unwrap_err(), CipherError::InvalidCiphertextLength);
+        assert_eq!(processor.encrypt_sector(&[0; 8], 0, &[0; 512]).unwrap_err(), CipherError::InvalidIVLength);
+    }
+}
@@ -6,7 +6,11 @@ use crypto::{
     CipherProcessor,
     CipherBlock,
     threefish256::Cipher256,
-    cbc::CBCProcessor
+    threefish512::Cipher512,
+    threefish1024::Cipher1024,
+    cbc::CBCProcessor,
+    ctr::CTRProcessor,
+    xts::XTSProcessor
 };
 
 #[test]
@@ -56,4 +60,78 @@ fn cipher_processor_decrypt_encrypt() {
 
         assert_eq!(plaintext1, plaintext2);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn wide_blocks_decrypt_encrypt() {
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..20 {
+        let tweak: Vec<u8> = (0..16).map(|_| rng.gen()).collect();
+        let key512: Vec<u8> = (0..64).map(|_| rng.gen()).collect();
+        let key1024: Vec<u8> = (0..128).map(|_| rng.gen()).collect();
+
+        let blocks: [Arc<dyn CipherBlock>; 2] = [
+            Arc::new(Cipher512::new(&key512, &tweak).unwrap()),
+            Arc::new(Cipher1024::new(&key1024, &tweak).unwrap()),
+        ];
+
+        for block in blocks {
+            let iv: Vec<u8> = (0..block.get_block_size()).map(|_| rng.gen()).collect();
+            let processor: Arc<dyn CipherProcessor> = Arc::new(CBCProcessor::new(block, &iv).unwrap());
+
+            let plaintext_length = rng.gen_range(0..2000);
+            let plaintext1: Vec<u8> = (0..plaintext_length).map(|_| rng.gen()).collect();
+
+            let ciphertext = processor.encrypt_blocks(&plaintext1);
+            let plaintext2 = processor.decrypt_blocks(&ciphertext).unwrap();
+
+            assert_eq!(plaintext1, plaintext2);
+        }
+    }
+}
+
+#[test]
+fn ctr_processor_decrypt_encrypt() {
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..100 {
+        let tweak: Vec<u8> = (0..16).map(|_| rng.gen()).collect();
+        let key: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
+
+        let cipher = Cipher256::new(&key, &tweak).unwrap();
+        let block: Arc<dyn CipherBlock> = Arc::new(cipher);
+
+        let processor: Arc<dyn CipherProcessor> = Arc::new(CTRProcessor::new(block));
+
+        let plaintext_length = rng.gen_range(0..2000);
+        let plaintext1: Vec<u8> = (0..plaintext_length).map(|_| rng.gen()).collect();
+
+        let ciphertext = processor.encrypt_blocks(&plaintext1);
+        assert_eq!(ciphertext.len(), plaintext_length + 32);
+
+        let plaintext2 = processor.decrypt_blocks(&ciphertext).unwrap();
+        assert_eq!(plaintext1, plaintext2);
+    }
+}
+
+#[test]
+fn xts_processor_decrypt_encrypt() {
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..100 {
+        let key: Vec<u8> = (0..64).map(|_| rng.gen()).collect();
+
+        let cipher = Cipher512::new(&key, &[0; 16]).unwrap();
+        let processor: Arc<dyn CipherProcessor> = Arc::new(XTSProcessor::new(Arc::new(cipher), 256).unwrap());
+
+        let plaintext_length = rng.gen_range(0..2000);
+        let plaintext1: Vec<u8> = (0..plaintext_length).map(|_| rng.gen()).collect();
+
+        let ciphertext = processor.encrypt_blocks(&plaintext1);
+        assert_eq!(ciphertext.len(), plaintext_length + 16);
+
+        let plaintext2 = processor.decrypt_blocks(&ciphertext).unwrap();
+        assert_eq!(plaintext1, plaintext2);
+    }
+}
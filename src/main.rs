@@ -1,62 +1,388 @@
-use clap::{Arg, Command, ArgAction};
+use clap::{Arg, ArgGroup, ArgMatches, Command, ArgAction};
 use rand::Rng;
 use sha2::{Sha256, Digest};
+use zeroize::Zeroizing;
 
-use std::{path::Path, process, sync::Arc};
+use std::{env, fs, io::Write, os::unix::fs::OpenOptionsExt, path::Path, process, sync::Arc};
 
 use crypto::{
     CipherProcessor,
-    CipherBlock,
+    TweakableBlock,
     threefish256::Cipher256,
-    cbc::CBCProcessor
+    threefish512::Cipher512,
+    threefish1024::Cipher1024,
+    cbc::CBCProcessor,
+    ctr::CTRProcessor,
+    xts::{XTSProcessor, DEFAULT_SECTOR_SIZE},
+    envelope::{Envelope, Identity, Recipient},
+    keyslot::{KeySlots, DEFAULT_ITERATIONS},
+    signing::{SigningKey, VerifyingKey},
+    CipherError,
+    skein
 };
 
 use compressor::{
     Compressor, 
-    lzw::LZW
+    CompressorOptions,
+    filter::Filter,
+    registry::Registry
 };
 
-use archiver::{Archiver,ArchiveError};
+use archiver::{Archiver,ArchiveError,codec_name};
+use archiver::select::CodecSelector;
+use archiver::padding::Padding;
+use archiver::mode::CipherMode;
 
 const TWEAK: [u8; 16] = [61,76,51,71,52,61,75,88,13,7,3,1,5,241,177,23];
 
 struct Args {
     unzip: bool,
+    list: bool,
     compressor: Option<Arc<dyn Compressor>>,
+    selector: Option<Arc<CodecSelector>>,
+    filter: Filter,
+    dictionary_size: Option<usize>,
+    solid_block_size: Option<usize>,
+    encrypted_headers: bool,
+    padding: Padding,
+    cipher_mode: CipherMode,
+    extract: Option<String>,
     processor: Option<Arc<dyn CipherProcessor>>,
+    envelope: Option<Envelope>,
+    key_slots: Option<KeySlots>,
+    rekey_processor: Option<Arc<dyn CipherProcessor>>,
+    signing_key: Option<SigningKey>,
     threads: u32,
-    output: String,
+    output: Option<String>,
     source: String,
 }
 
 
-fn get_cipherprocessor(key: &[u8], tweak: &[u8], iv: &[u8]) -> Arc<dyn CipherProcessor> {
-    let cipher = Cipher256::new(&key, &tweak).unwrap();
-    let block: Arc<dyn CipherBlock> = Arc::new(cipher);
+fn get_cipherblock(bits: u32, key: &[u8], tweak: &[u8]) -> Arc<dyn TweakableBlock> {
+    match bits {
+        512 => Arc::new(Cipher512::new(key, tweak).unwrap()),
+        1024 => Arc::new(Cipher1024::new(key, tweak).unwrap()),
+        _ => Arc::new(Cipher256::new(key, tweak).unwrap()),
+    }
+}
 
-    let cbc_processor = CBCProcessor::new(block, &iv).unwrap();
-    let processor: Arc<dyn CipherProcessor> = Arc::new(cbc_processor);
+fn get_cipherprocessor(mode: CipherMode, block: Arc<dyn TweakableBlock>, iv: &[u8], threads: usize) -> Arc<dyn CipherProcessor> {
+    match mode {
+        CipherMode::Ctr => Arc::new(CTRProcessor::new(block).with_threads(threads)),
+        CipherMode::Xts => Arc::new(XTSProcessor::new(block, DEFAULT_SECTOR_SIZE).unwrap()),
+        CipherMode::Cbc => Arc::new(CBCProcessor::new(block, iv).unwrap().with_threads(threads)),
+    }
+}
 
-    processor
+fn get_compressor(codec: &str, options: &CompressorOptions) -> Arc<dyn Compressor> {
+    Registry::new().get_by_name(codec, options).unwrap()
 }
 
-fn get_compressor() -> Arc<dyn Compressor> {
-    let compressor:Arc<dyn Compressor> = Arc::new(LZW::new());
-    compressor
+fn get_selector(options: &CompressorOptions) -> Arc<CodecSelector> {
+    let fast = get_compressor("lz77", options);
+    let strong = get_compressor("bwt", options);
+
+    Arc::new(CodecSelector::new(fast, strong))
 }
 
-fn hash_key(key: &str) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(&key);
-    let result = hasher.finalize();
-    let mut hash_array = [0u8; 32];
-    hash_array.copy_from_slice(&result);
-    
-    hash_array
+/// Derives `length` bytes of key from the password with the Skein KDF.
+fn hash_key(key: &str, length: usize) -> Zeroizing<Vec<u8>> {
+    skein::kdf(key.as_bytes(), skein::ARCHIVE_KEY_ID, length)
+}
+
+/// Key of the archives made by the first versions: the SHA-256 of the password, for Threefish-256.
+fn legacy_hash_key(key: &str) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(Sha256::digest(key.as_bytes()).to_vec())
+}
+
+/// Takes the password from `--key`, `--key-file`, `--key-env` or `--key-fd`, the value
+/// is moved out of the matches and the `--key-env` variable out of the environment, so
+/// only the zeroizing copy is left. `--key` without a value asks for it without echo,
+/// twice if `confirm` as a new archive is made with it.
+fn read_key(matches: &mut ArgMatches, confirm: bool) -> Option<Zeroizing<String>> {
+    let key = if matches.contains_id("key") {
+        matches.remove_one::<String>("key")
+            .map(Zeroizing::new)
+            .unwrap_or_else(|| prompt_key("Key: ", confirm))
+    } else if let Some(path) = matches.get_one::<String>("key_file") {
+        read_key_file(path)
+    } else if let Some(var) = matches.get_one::<String>("key_env") {
+        let key = Zeroizing::new(env::var(var).unwrap_or_else(|_| {
+            eprintln!("Environment variable {} is not set!", var);
+            process::exit(1);
+        }));
+        // the key is read before any worker starts, nothing else reads the environment yet
+        env::remove_var(var);
+        key
+    } else if let Some(fd) = matches.get_one::<u32>("key_fd") {
+        read_key_file(&format!("/dev/fd/{}", fd))
+    } else {
+        return None;
+    };
+
+    if key.is_empty() {
+        eprintln!("Key is empty!");
+        process::exit(1);
+    }
+
+    Some(key)
+}
+
+/// Takes a new password given as the value of `id`, or asks for it twice.
+fn read_new_key(matches: &mut ArgMatches, id: &str) -> Option<Zeroizing<String>> {
+    if !matches.contains_id(id) {
+        return None;
+    }
+
+    let key = matches.remove_one::<String>(id)
+        .map(Zeroizing::new)
+        .unwrap_or_else(|| prompt_key("New key: ", true));
+    if key.is_empty() {
+        eprintln!("Key is empty!");
+        process::exit(1);
+    }
+
+    Some(key)
+}
+
+fn prompt_key(prompt: &str, confirm: bool) -> Zeroizing<String> {
+    let read = |prompt: &str| Zeroizing::new(rpassword::prompt_password(prompt).unwrap_or_else(|e| {
+        eprintln!("Can not read the key from the terminal: {}", e);
+        process::exit(1);
+    }));
+
+    let key = read(prompt);
+    if confirm && *read("Repeat key: ") != *key {
+        eprintln!("Keys do not match!");
+        process::exit(1);
+    }
+
+    key
+}
+
+/// Reads a key file or descriptor. The line break editors add at the end is not part of the key.
+fn read_key_file(path: &str) -> Zeroizing<String> {
+    let mut key = Zeroizing::new(fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Can not read {}: {}", path, e);
+        process::exit(1);
+    }));
+
+    let length = key.strip_suffix('\n').map_or(key.len(), |k| k.strip_suffix('\r').unwrap_or(k).len());
+    key.truncate(length);
+    key
+}
+
+/// Writes a new identity to `path` and its public key to `path.pub`.
+fn keygen(path: &str) {
+    let identity = Identity::generate();
+    write_key_pair(path, &identity.to_hex(), &identity.recipient().to_string());
+}
+
+/// Writes a new signing key to `path` and its public key to `path.pub`.
+fn signing_keygen(path: &str) {
+    let key = SigningKey::generate();
+    write_key_pair(path, &key.to_hex(), &key.verifying_key().to_string());
+}
+
+/// Writes the secret key readable by the owner only, it is never overwritten.
+fn write_key_pair(path: &str, secret: &str, public: &str) {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .unwrap_or_else(|e| {
+            eprintln!("Can not create {}: {}", path, e);
+            process::exit(1);
+        });
+    let public_path = format!("{}.pub", path);
+    let written = writeln!(file, "{}", secret)
+        .and_then(|_| fs::write(&public_path, format!("{}\n", public)));
+    if let Err(e) = written {
+        // a secret key without its public half is of no use
+        let _ = fs::remove_file(path);
+        eprintln!("Can not write the key pair {}: {}", path, e);
+        process::exit(1);
+    }
+
+    println!("Public key: {}", public);
+}
+
+fn load_recipient(path: &str) -> Recipient {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Can not read {}: {}", path, e);
+        process::exit(1);
+    });
+
+    Recipient::from_hex(&text).unwrap_or_else(|_| {
+        eprintln!("{} is not a public key!", path);
+        process::exit(1);
+    })
+}
+
+fn load_identity(path: &str) -> Identity {
+    let text = Zeroizing::new(fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Can not read {}: {}", path, e);
+        process::exit(1);
+    }));
+
+    Identity::from_hex(&text).unwrap_or_else(|_| {
+        eprintln!("{} is not a secret key!", path);
+        process::exit(1);
+    })
+}
+
+fn load_signing_key(path: &str) -> SigningKey {
+    let text = Zeroizing::new(fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Can not read {}: {}", path, e);
+        process::exit(1);
+    }));
+
+    SigningKey::from_hex(&text).unwrap_or_else(|_| {
+        eprintln!("{} is not a signing key!", path);
+        process::exit(1);
+    })
+}
+
+/// Checks the signature of the archive at `source` against the public key in `path` and exits.
+fn verify_signature(source: &str, path: &str) -> ! {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Can not read {}: {}", path, e);
+        process::exit(1);
+    });
+    let key = VerifyingKey::from_hex(&text).unwrap_or_else(|_| {
+        eprintln!("{} is not a public signing key!", path);
+        process::exit(1);
+    });
+
+    match Archiver::new(Path::new(source), 1, None, None).verify_signature(&key) {
+        Ok(()) => {
+            println!("Signature is valid, signed by {}", key);
+            process::exit(0);
+        },
+        Err(e) => {
+            match e {
+                ArchiveError::NotSigned => eprintln!("Archive is not signed!"),
+                ArchiveError::InvalidSignature => eprintln!("Signature is invalid! The archive was changed or signed by another key."),
+                _ => eprintln!("Error while reading archive: {:?}", e),
+            }
+            process::exit(1);
+        },
+    }
+}
+
+/// Unwraps the content key of the archive at `source` with `identity`.
+fn open_envelope(source: &str, identity: &Identity) -> Zeroizing<Vec<u8>> {
+    let envelope = match Archiver::new(Path::new(source), 1, None, None).load_envelope() {
+        Ok(Some(envelope)) => envelope,
+        Ok(None) => {
+            eprintln!("Archive is not encrypted for recipients!");
+            process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("Error while reading archive: {:?}", e);
+            process::exit(1);
+        },
+    };
+
+    envelope.open(identity).unwrap_or_else(|e| {
+        match e {
+            CipherError::RecipientNotFound => eprintln!("Archive is not encrypted for this identity!"),
+            _ => eprintln!("Archive envelope is corrupted!"),
+        }
+        process::exit(1);
+    })
+}
+
+/// The mode stored in the archive header, archives made before it was stored have none.
+fn stored_cipher_mode(source: &str) -> Option<CipherMode> {
+    Archiver::new(Path::new(source), 1, None, None).load_cipher_mode().unwrap_or_else(|_| {
+        eprintln!("Archive header is corrupted!");
+        process::exit(1);
+    })
+}
+
+/// Unwraps the content key of the archive at `source` with the password. Archives
+/// made before key slots use the password hash as the key, the first versions hashed
+/// it with SHA-256 and their archives have no KDF flag.
+fn open_key_slots(source: &str, key: &str, length: usize) -> Zeroizing<Vec<u8>> {
+    let archiver = Archiver::new(Path::new(source), 1, None, None);
+    let key_slots = archiver.load_key_slots()
+        .and_then(|key_slots| archiver.has_skein_kdf().map(|skein_kdf| (key_slots, skein_kdf)));
+
+    match key_slots {
+        Ok((Some(key_slots), _)) => key_slots.open(key.as_bytes())
+            .map(|(_, content_key)| content_key)
+            .unwrap_or_else(|_| {
+                eprintln!("Key does not open any key slot of the archive!");
+                process::exit(1);
+            }),
+        Ok((None, true)) => hash_key(key, length),
+        Ok((None, false)) => legacy_hash_key(key),
+        Err(e) => {
+            eprintln!("Error while reading archive: {:?}", e);
+            process::exit(1);
+        },
+    }
+}
+
+/// Makes a random content key of `length` bytes and wraps it under the password.
+fn new_key_slots(key: &str, length: usize) -> (Zeroizing<Vec<u8>>, KeySlots) {
+    let mut rng = rand::thread_rng();
+    let content_key = Zeroizing::new((0..length).map(|_| rng.gen()).collect::<Vec<u8>>());
+
+    let mut key_slots = KeySlots::new(length);
+    key_slots.add(&content_key, key.as_bytes(), DEFAULT_ITERATIONS).unwrap();
+
+    (content_key, key_slots)
+}
+
+/// Adds, removes or lists the key slots of the archive at `source` and exits.
+fn manage_key_slots(matches: &ArgMatches, source: &str, key: Option<&str>, new_key: Option<&str>) -> ! {
+    let archiver = Archiver::new(Path::new(source), 1, None, None);
+    let key = key.unwrap_or_default().as_bytes();
+
+    let result = if let Some(new_key) = new_key {
+        archiver.add_key_slot(key, new_key.as_bytes(), DEFAULT_ITERATIONS)
+            .map(|index| println!("Key added to slot {}", index))
+    } else if let Some(index) = matches.get_one::<u32>("remove_key") {
+        archiver.remove_key_slot(key, *index as usize)
+            .map(|_| println!("Key slot {} removed", index))
+    } else {
+        archiver.list_key_slots().map(|slots| {
+            println!("{:>4} {:>12}", "slot", "iterations");
+            for (index, iterations) in slots {
+                println!("{:>4} {:>12}", index, iterations);
+            }
+        })
+    };
+
+    if let Err(e) = result {
+        match e {
+            ArchiveError::DecryptError => eprintln!("Key does not open any key slot of the archive!"),
+            ArchiveError::NoKeySlots => eprintln!("Archive has no key slots!"),
+            ArchiveError::KeySlotsFull => eprintln!("All key slots are in use!"),
+            ArchiveError::InvalidKeySlot => eprintln!("Key slot is not in use!"),
+            ArchiveError::LastKeySlot => eprintln!("The last key slot can not be removed!"),
+            ArchiveError::SignedArchive => eprintln!("Archive is signed, changing its key slots would invalidate the signature!"),
+            _ => eprintln!("Error while reading archive: {:?}", e),
+        }
+        process::exit(1);
+    }
+
+    process::exit(0);
+}
+
+/// Appends the signature trailer to the archive at `path` when there is a signing key.
+fn sign_archive(path: &Path, key: Option<&SigningKey>) -> Result<(), ArchiveError> {
+    match key {
+        Some(key) => Archiver::new(path, 1, None, None).sign(key),
+        None => Ok(()),
+    }
 }
 
 fn get_args() -> Args {
-    let matches = Command::new("File Processor")
+    let mut matches = Command::new("File Processor")
         .about("Программа для шифрования, дешифрования и сжатия файлов")
         .arg(Arg::new("unzip")
             .short('u')
@@ -64,11 +390,82 @@ fn get_args() -> Args {
             .action(ArgAction::SetTrue)
             .conflicts_with("compress")
             .help("Разархивировать"))
+        .arg(Arg::new("list")
+            .short('t')
+            .long("list")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["unzip", "compress"])
+            .help("Показать содержимое архива"))
         .arg(Arg::new("compress")
             .short('C')
             .long("compress")
             .action(ArgAction::SetTrue)
             .help("Включить сжатие"))
+        .arg(Arg::new("codec")
+            .short('c')
+            .long("codec")
+            .value_parser(["lzw", "huffman", "range", "bwt", "lz77", "auto"])
+            .default_value("lzw")
+            .help("Алгоритм сжатия: lzw, huffman, range, bwt, lz77, auto (выбор для каждого файла). По умолчанию: lzw"))
+        .arg(Arg::new("level")
+            .short('l')
+            .long("level")
+            .value_parser(clap::value_parser!(u8).range(1..=9))
+            .default_value("6")
+            .help("Уровень сжатия от 1 до 9. По умолчанию: 6"))
+        .arg(Arg::new("filter")
+            .short('f')
+            .long("filter")
+            .value_parser(|s: &str| s.parse::<Filter>().map_err(|_| "ожидается none, delta, delta:N, x86 или arm64"))
+            .default_value("none")
+            .requires("compress")
+            .help("Фильтр перед сжатием: none, delta, delta:N (шаг N байт), x86, arm64. По умолчанию: none"))
+        .arg(Arg::new("dictionary")
+            .short('D')
+            .long("dictionary")
+            .value_parser(clap::value_parser!(u32).range(1..=16384))
+            .num_args(0..=1)
+            .default_missing_value("32")
+            .requires("compress")
+            .help("Обучить общий словарь на мелких файлах и сохранить его в архиве. Размер в КиБ, по умолчанию: 32"))
+        .arg(Arg::new("solid")
+            .long("solid")
+            .value_parser(clap::value_parser!(u32).range(1..=4096))
+            .num_args(0..=1)
+            .default_missing_value("16")
+            .requires("compress")
+            .help("Сжимать файлы с одинаковым расширением одним потоком, блоками до указанного размера в МиБ. По умолчанию: 16"))
+        .arg(Arg::new("encrypt_headers")
+            .long("encrypt-headers")
+            .action(ArgAction::SetTrue)
+            .requires("secret")
+            .conflicts_with_all(["unzip", "list"])
+            .help("Шифровать заголовки файлов: имена, права и размеры. Содержимое архива показывается только с ключом"))
+        .arg(Arg::new("padding")
+            .long("padding")
+            .value_parser(|s: &str| s.parse::<Padding>().map_err(|_| "ожидается none, pow2 или padme"))
+            .default_value("none")
+            .requires("encrypt_headers")
+            .conflicts_with_all(["unzip", "list"])
+            .help("Дополнение зашифрованных данных, скрывающее размер файлов: none, pow2 (до степени двойки), padme (не больше 12%). Указывается вместе с --encrypt-headers, иначе размеры видны в открытых заголовках. По умолчанию: none"))
+        .arg(Arg::new("extract")
+            .short('x')
+            .long("extract")
+            .num_args(1)
+            .requires("unzip")
+            .help("Извлечь из архива только указанный файл"))
+        .arg(Arg::new("cipher")
+            .long("cipher")
+            .value_parser(["256", "512", "1024"])
+            .default_value("256")
+            .requires("secret")
+            .help("Размер блока Threefish в битах: 256, 512, 1024. При распаковке размер определяется по архиву. По умолчанию: 256"))
+        .arg(Arg::new("mode")
+            .long("mode")
+            .value_parser(|s: &str| s.parse::<CipherMode>().map_err(|_| "ожидается cbc, ctr или xts"))
+            .default_value("cbc")
+            .requires("secret")
+            .help("Режим шифрования: cbc, ctr (без дополнения, блоки шифруются параллельно), xts (секторы по 4 КиБ шифруются независимо, без дополнения). Режим сохраняется в архиве, при распаковке его указывать не нужно. По умолчанию: cbc"))
         .arg(Arg::new("threads")
             .short('T')
             .long("threads")
@@ -79,49 +476,220 @@ fn get_args() -> Args {
             .short('o')
             .long("output")
             .num_args(1)
-            .required(true)
+            .required_unless_present_any(["list", "keygen", "signing_keygen", "add_key", "remove_key", "list_keys", "verify_signature"])
             .help("Путь, куда записываются файлы"))
         .arg(Arg::new("source")
             .short('s')
             .long("source")
             .num_args(1)
-            .required(true)
+            .required_unless_present_any(["keygen", "signing_keygen"])
             .help("Путь, откуда считывается файл"))
         .arg(Arg::new("key")
             .short('k')
             .long("key")
+            .num_args(0..=1)
+            .help("Ключ, используемый для шифрования. Без значения ключ запрашивается без отображения ввода"))
+        .arg(Arg::new("key_file")
+            .long("key-file")
+            .num_args(1)
+            .help("Файл с ключом. Перевод строки в конце файла не входит в ключ"))
+        .arg(Arg::new("key_env")
+            .long("key-env")
             .num_args(1)
-            .help("Ключ, используемый для шифрования"))
+            .help("Переменная окружения с ключом. После чтения она удаляется из окружения"))
+        .arg(Arg::new("key_fd")
+            .long("key-fd")
+            .value_parser(clap::value_parser!(u32))
+            .help("Номер открытого файлового дескриптора, из которого читается ключ"))
+        .arg(Arg::new("recipient")
+            .short('r')
+            .long("recipient")
+            .num_args(1)
+            .action(ArgAction::Append)
+            .conflicts_with_all(["unzip", "list"])
+            .help("Файл с открытым ключом получателя. Архив шифруется случайным ключом, который открывается секретным ключом любого из получателей. Можно указать несколько раз"))
+        .arg(Arg::new("identity")
+            .short('i')
+            .long("identity")
+            .num_args(1)
+            .conflicts_with("compress")
+            .help("Файл с секретным ключом получателя для распаковки архива"))
+        .arg(Arg::new("keygen")
+            .long("keygen")
+            .num_args(1)
+            .exclusive(true)
+            .help("Создать секретный ключ в указанном файле и открытый ключ в файле с расширением .pub"))
+        .arg(Arg::new("add_key")
+            .long("add-key")
+            .num_args(0..=1)
+            .requires("password")
+            .conflicts_with_all(["unzip", "list", "compress"])
+            .help("Добавить в архив слот с новым ключом. Архив открывается ключом --key, содержимое не перешифровывается. Без значения новый ключ запрашивается"))
+        .arg(Arg::new("remove_key")
+            .long("remove-key")
+            .value_parser(clap::value_parser!(u32))
+            .requires("password")
+            .conflicts_with_all(["unzip", "list", "compress", "add_key"])
+            .help("Удалить из архива слот с указанным номером. Архив открывается ключом --key"))
+        .arg(Arg::new("rekey")
+            .long("rekey")
+            .num_args(0..=1)
+            .requires_all(["password", "output"])
+            .conflicts_with_all(["unzip", "list", "compress", "add_key", "remove_key"])
+            .help("Перешифровать архив новым ключом без повторного сжатия. Архив открывается ключом --key, новый архив записывается в --output с размером блока --cipher в том же режиме шифрования. Без значения новый ключ запрашивается"))
+        .arg(Arg::new("list_keys")
+            .long("list-keys")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["unzip", "list", "compress", "add_key", "remove_key", "rekey"])
+            .help("Показать занятые слоты ключей архива"))
+        .arg(Arg::new("signing_keygen")
+            .long("signing-keygen")
+            .num_args(1)
+            .exclusive(true)
+            .help("Создать ключ подписи Ed25519 в указанном файле и открытый ключ в файле с расширением .pub"))
+        .arg(Arg::new("sign")
+            .long("sign")
+            .num_args(1)
+            .conflicts_with_all(["unzip", "list", "add_key", "remove_key", "list_keys"])
+            .help("Файл с ключом подписи. Созданный архив подписывается, подпись дописывается в конец архива"))
+        .arg(Arg::new("verify_signature")
+            .long("verify-signature")
+            .num_args(1)
+            .conflicts_with_all(["unzip", "list", "compress", "add_key", "remove_key", "list_keys", "rekey", "sign", "secret"])
+            .help("Проверить подпись архива открытым ключом из указанного файла"))
+        .group(ArgGroup::new("secret")
+            .args(["key", "key_file", "key_env", "key_fd", "recipient", "identity"])
+            .multiple(false))
+        .group(ArgGroup::new("password")
+            .args(["key", "key_file", "key_env", "key_fd"])
+            .multiple(false))
         .get_matches();
 
+    if let Some(path) = matches.get_one::<String>("keygen") {
+        keygen(path);
+        process::exit(0);
+    }
+    if let Some(path) = matches.get_one::<String>("signing_keygen") {
+        signing_keygen(path);
+        process::exit(0);
+    }
+    if let Some(path) = matches.get_one::<String>("verify_signature") {
+        verify_signature(matches.get_one::<String>("source").unwrap(), path);
+    }
+
     let unzip = matches.get_flag("unzip");
+    let list = matches.get_flag("list");
+    let manage = matches.contains_id("add_key") || matches.contains_id("remove_key") || matches.get_flag("list_keys");
+    // a mistyped key would lock the new archive, so it is asked twice
+    let creating = !unzip && !list && !manage && !matches.contains_id("rekey");
+    let key = read_key(&mut matches, creating);
+    let rekey = read_new_key(&mut matches, "rekey");
+    if manage {
+        let new_key = read_new_key(&mut matches, "add_key");
+        manage_key_slots(&matches, matches.get_one::<String>("source").unwrap(), key.as_deref().map(|k| k.as_str()), new_key.as_deref().map(|k| k.as_str()));
+    }
     let threads: u32 = *matches.get_one::<u32>("threads").unwrap_or(&4);
-    let output = matches.get_one::<String>("output").unwrap();
+    let output = matches.get_one::<String>("output");
     let source = matches.get_one::<String>("source").unwrap();
     let compress = matches.get_flag("compress");
-    let key = matches.get_one::<String>("key");
+    let codec = matches.get_one::<String>("codec").unwrap();
+    let level: u8 = *matches.get_one::<u8>("level").unwrap_or(&6);
+    let filter: Filter = *matches.get_one::<Filter>("filter").unwrap();
+    let dictionary_size = matches.get_one::<u32>("dictionary").map(|kb| *kb as usize * 1024);
+    let solid_block_size = matches.get_one::<u32>("solid").map(|mb| *mb as usize * 1024 * 1024);
+    let extract = matches.get_one::<String>("extract");
+    let encrypted_headers = matches.get_flag("encrypt_headers");
+    let padding: Padding = *matches.get_one::<Padding>("padding").unwrap();
+    let cipher: u32 = matches.get_one::<String>("cipher").unwrap().parse().unwrap();
+    let mut cipher_mode: CipherMode = *matches.get_one::<CipherMode>("mode").unwrap();
+    let signing_key = matches.get_one::<String>("sign").map(|path| load_signing_key(path));
     
     let mut compressor = None;
+    let mut selector = None;
     let mut processor = None;
-    if compress || unzip {
-        compressor = Some(get_compressor());
+    let mut envelope = None;
+    let mut key_slots = None;
+    let mut rekey_processor = None;
+    if compress {
+        let options = CompressorOptions {
+            threads: threads as usize,
+            ..CompressorOptions::with_level(level)
+        };
+        if codec == "auto" {
+            selector = Some(get_selector(&options));
+        } else {
+            compressor = Some(get_compressor(codec, &options));
+        }
     }
 
-    if let Some(k) = key {
-        let mut rng = rand::thread_rng();
-        let iv: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
-        let hash = hash_key(k);
+    let mut rng = rand::thread_rng();
+    let content_key = if let Some(k) = &key {
+        if unzip || list || rekey.is_some() {
+            Some(open_key_slots(source, k, cipher as usize / 8))
+        } else {
+            // the password wraps a random content key, so more passwords can be added later
+            let (content_key, slots) = new_key_slots(k, cipher as usize / 8);
+            key_slots = Some(slots);
+            Some(content_key)
+        }
+    } else if let Some(paths) = matches.get_many::<String>("recipient") {
+        let recipients: Vec<Recipient> = paths.map(|p| load_recipient(p)).collect();
+        let content_key = Zeroizing::new((0..cipher as usize / 8).map(|_| rng.gen()).collect::<Vec<u8>>());
+
+        envelope = Some(Envelope::seal(&content_key, &recipients));
+        Some(content_key)
+    } else {
+        matches.get_one::<String>("identity")
+            .map(|path| open_envelope(source, &load_identity(path)))
+    };
+
+    if let Some(content_key) = content_key {
+        // the size of an unwrapped content key gives the cipher the archive was made with
+        let cipher = content_key.len() as u32 * 8;
+        if ![256, 512, 1024].contains(&cipher) {
+            eprintln!("Archive envelope is corrupted!");
+            process::exit(1);
+        }
+
+        if unzip || list || rekey.is_some() {
+            // --mode is only needed for archives made before the mode was stored
+            cipher_mode = stored_cipher_mode(source).unwrap_or(cipher_mode);
+        }
+
+        let iv: Vec<u8> = (0..cipher as usize / 8).map(|_| rng.gen()).collect();
+        let block = get_cipherblock(cipher, &content_key, &TWEAK);
+        processor = Some(get_cipherprocessor(cipher_mode, block, &iv, threads as usize))
+    }
+
+    if let Some(new_key) = &rekey {
+        let (content_key, slots) = new_key_slots(new_key, cipher as usize / 8);
+        let iv: Vec<u8> = (0..cipher as usize / 8).map(|_| rng.gen()).collect();
+        let block = get_cipherblock(cipher, &content_key, &TWEAK);
 
-        processor = Some(get_cipherprocessor(&hash, &TWEAK, &iv))
+        key_slots = Some(slots);
+        rekey_processor = Some(get_cipherprocessor(cipher_mode, block, &iv, threads as usize));
     }
 
     Args {
         unzip,
+        list,
         compressor,
+        selector,
+        filter,
+        dictionary_size,
+        solid_block_size,
+        encrypted_headers,
+        padding,
+        cipher_mode,
+        extract: extract.cloned(),
         threads,
-        output: output.clone(),
+        output: output.cloned(),
         source: source.clone(),
-        processor
+        processor,
+        envelope,
+        key_slots,
+        rekey_processor,
+        signing_key,
     }
 }
 
@@ -130,32 +698,114 @@ fn main() {
 
     let source_path = Path::new(&args.source);
     let mut archiver = Archiver::new(
-        &source_path,
+        source_path,
         args.threads as usize,
         args.compressor,
         args.processor,
     );
+    if let Some(selector) = args.selector {
+        archiver = archiver.with_selector(selector);
+    }
+    if args.filter != Filter::None {
+        archiver = archiver.with_filter(args.filter);
+    }
+    if let Some(size) = args.dictionary_size {
+        let dictionary = archiver.train_dictionary(size).unwrap_or_else(|e| {
+            eprintln!("Error while training dictionary: {:?}", e);
+            process::exit(1);
+        });
+        if dictionary.is_empty() {
+            println!("No samples to train a dictionary on, zipping without it");
+        } else {
+            println!("Dictionary trained: {} bytes", dictionary.len());
+            archiver = archiver.with_dictionary(Arc::new(dictionary));
+        }
+    }
+    if let Some(size) = args.solid_block_size {
+        archiver = archiver.with_solid(size);
+    }
+    if args.encrypted_headers {
+        archiver = archiver.with_encrypted_headers();
+    }
+    if args.padding != Padding::None {
+        archiver = archiver.with_padding(args.padding);
+    }
+    if let Some(envelope) = args.envelope {
+        archiver = archiver.with_envelope(envelope);
+    }
+    if let Some(key_slots) = args.key_slots {
+        archiver = archiver.with_key_slots(key_slots).with_skein_kdf();
+    }
+    archiver = archiver.with_cipher_mode(args.cipher_mode);
+
+    if args.list {
+        let entries = match archiver.list() {
+            Ok(entries) => entries,
+            Err(ArchiveError::EncryptedHeaders) => {
+                eprintln!("Archive headers are encrypted, the key is required to list it!");
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error while reading archive: {:?}", e);
+                process::exit(1);
+            }
+        };
+
+        let registry = Registry::new();
+        println!("{:>12} {:>12}  {:<16} path", "size", "packed", "codec");
+        for entry in entries {
+            let packed = match entry.block {
+                Some(block) => format!("block {}", block),
+                None => entry.zip_size.to_string(),
+            };
+            println!("{:>12} {:>12}  {:<16} {}", entry.size, packed, codec_name(&registry, entry.codec), entry.rel_path);
+        }
+        return;
+    }
 
-    let output_path = Path::new(&args.output);
+    let output = args.output.unwrap();
+    let output_path = Path::new(&output);
+
+    if let Some(processor) = args.rekey_processor {
+        println!("Started rekey process...");
+        if let Err(e) = archiver.rekey(output_path, processor).and_then(|_| sign_archive(output_path, args.signing_key.as_ref())) {
+            match e {
+                ArchiveError::DecryptError => eprintln!("Error while decrypting. Maybe your key is incorrect!"),
+                ArchiveError::FileAlreadyDecrypted => eprintln!("Archive is not encrypted!"),
+                ArchiveError::CorruptedHeader => eprintln!("Archive header is corrupted!"),
+                _ => eprintln!("Error while rekeying archive: {:?}", e),
+            }
+            process::exit(1);
+        }
+        println!("Process done!");
+        return;
+    }
 
     if args.unzip {
         println!("Started unzip process...");
-        match archiver.unzip(&output_path) {
+        let result = match &args.extract {
+            Some(rel_path) => archiver.extract(rel_path, output_path).map(|_| 1),
+            None => archiver.unzip(output_path),
+        };
+        match result {
             Ok(_) => {
             }
             Err(e) => {
                 match e {
-                    ArchiveError::DecryptError => {
-                        eprintln!("Error while decompressing. Maybe your key is incorrect!");
-                    },
-                    _ => (),
+                    ArchiveError::DecryptError => eprintln!("Error while decompressing. Maybe your key is incorrect!"),
+                    ArchiveError::CorruptedHeader => eprintln!("Archive header is corrupted!"),
+                    ArchiveError::OutputLimitExceeded => eprintln!("File expands past its recorded size. Archive is corrupted!"),
+                    ArchiveError::EntryNotFound => eprintln!("File not found in archive!"),
+                    ArchiveError::EncryptedHeaders => eprintln!("Archive headers are encrypted, the key is required!"),
+                    _ => {}
                 }
                 process::exit(1);
             }
         }
     } else {
         println!("Started zip process...");
-        archiver.zip(&output_path).unwrap();
+        archiver.zip(output_path).unwrap();
+        sign_archive(output_path, args.signing_key.as_ref()).unwrap();
     }
 
     println!("Process done!");
@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const BASELINE_ARCHIVE: &str = "tests/static/baseline.rz";
+const BASELINE_FOLDER: &str = "tests/static/baseline";
+const BASELINE_KEY: &str = "baseline password";
+
+fn get_path(path: &str) -> PathBuf {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    manifest.join(path)
+}
+
+fn get_tmp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rzip-cli-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crypto_container"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+
+/// The first versions had no key slots and used the SHA-256 of the password as
+/// the Threefish-256 key, `baseline.rz` was made by them with `-C -k`.
+#[test]
+fn unzip_baseline_archive() {
+    let source = get_path(BASELINE_ARCHIVE);
+    let output_dir = get_tmp_path("baseline");
+
+    let output = run(&["-u", "-s", source.to_str().unwrap(), "-o", output_dir.to_str().unwrap(), "-k", BASELINE_KEY]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    for rel_path in ["readme.txt", "docs/data.csv"] {
+        let expected = std::fs::read(get_path(BASELINE_FOLDER).join(rel_path)).unwrap();
+        let actual = std::fs::read(output_dir.join(rel_path)).unwrap();
+        assert!(expected == actual, "{} differs after unzip", rel_path);
+    }
+
+    let output = run(&["-u", "-s", source.to_str().unwrap(), "-o", get_tmp_path("baseline_wrong").to_str().unwrap(), "-k", "wrong password"]);
+    assert!(!output.status.success());
+}
+
+/// The mode is stored in the header, so the archive is unzipped without `--mode`.
+#[test]
+fn unzip_stored_cipher_mode() {
+    let source = get_path(BASELINE_FOLDER);
+    let archive = get_tmp_path("xts.rz");
+    let output_dir = get_tmp_path("xts");
+
+    let output = run(&["-s", source.to_str().unwrap(), "-o", archive.to_str().unwrap(), "-k", BASELINE_KEY, "--mode", "xts", "--cipher", "512"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run(&["-u", "-s", archive.to_str().unwrap(), "-o", output_dir.to_str().unwrap(), "-k", BASELINE_KEY]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    for rel_path in ["readme.txt", "docs/data.csv"] {
+        let expected = std::fs::read(source.join(rel_path)).unwrap();
+        let actual = std::fs::read(output_dir.join(rel_path)).unwrap();
+        assert!(expected == actual, "{} differs after unzip", rel_path);
+    }
+}
+
+/// A key pair is written whole or not at all.
+#[test]
+fn keygen_write_error() {
+    let path = get_tmp_path("identity");
+    // the public key can not be written over a directory
+    std::fs::create_dir_all(get_tmp_path("identity.pub")).unwrap();
+
+    let output = run(&["--keygen", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Can not write the key pair"));
+    assert!(!path.exists());
+}
+
+/// Without encrypted headers the sizes stay in clear, so padding would hide nothing.
+#[test]
+fn padding_requires_encrypted_headers() {
+    let source = get_path(BASELINE_FOLDER);
+    let archive = get_tmp_path("padded.rz");
+
+    let output = run(&["-s", source.to_str().unwrap(), "-o", archive.to_str().unwrap(), "-k", BASELINE_KEY, "--padding", "pow2"]);
+    assert!(!output.status.success());
+    assert!(!archive.exists());
+
+    let output = run(&["-s", source.to_str().unwrap(), "-o", archive.to_str().unwrap(), "-k", BASELINE_KEY, "--padding", "pow2", "--encrypt-headers"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}